@@ -0,0 +1,102 @@
+//! Times rendering every display set of a long epoch with a
+//! [`StreamRenderer`], which keeps the flattened palette between display
+//! sets, against rebuilding it for each one with [`render_display_set`].
+//!
+//! Run with `cargo bench --bench stream_renderer`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use pgs_rs::{
+    encode::PgsBuilder,
+    parse::{
+        CompositionState, ObjectDefinition, PaletteDefinition, PaletteEntry, Pgs, Segment,
+        SegmentContents, Window,
+    },
+    render::{DisplaySetIterator, StreamRenderer, render_display_set},
+};
+
+const DISPLAY_SETS: u32 = 100;
+const ROUNDS: u32 = 3;
+
+/// An epoch start showing a 256 color object, followed by display sets that
+/// keep showing it at a new position each frame.
+fn stream() -> Pgs {
+    let palette = PaletteDefinition {
+        id: 0,
+        version: 0,
+        entries: (0..=255)
+            .map(|id| {
+                let entry = PaletteEntry {
+                    id,
+                    luminance: id,
+                    color_difference_red: 128,
+                    color_difference_blue: 128,
+                    alpha: 255,
+                };
+                (id, entry)
+            })
+            .collect(),
+    };
+    let indices: Vec<u8> = (0..640 * 64).map(|index| (index / 3) as u8).collect();
+    let object = ObjectDefinition::from_bitmap(0, 0, 640, 64, &indices).unwrap();
+    let window = Window {
+        id: 0,
+        horizontal_position: 0,
+        vertical_position: 800,
+        width: 1920,
+        height: 64,
+    };
+    let mut builder = PgsBuilder::new(1920, 1080, 0x10);
+    builder.show_at(0, window, palette, object, 0, 800);
+    let mut pgs = builder.build();
+
+    let composition = pgs.segments[0].clone();
+    for frame in 1..DISPLAY_SETS {
+        let pts = frame * 3_750;
+        let mut composition = composition.clone();
+        composition.pts = pts;
+        composition.dts = pts;
+        if let SegmentContents::PresentationComposition(composition) = &mut composition.contents {
+            composition.composition_state = CompositionState::Normal;
+            composition.composition_number = frame as u16;
+            composition.composition_objects[0].horizontal_position = (frame % 1280) as u16;
+        }
+        pgs.segments.push(composition);
+        pgs.segments.push(Segment {
+            pts,
+            dts: pts,
+            contents: SegmentContents::End,
+        });
+    }
+    pgs
+}
+
+fn time(mut render: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        render();
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let pgs = stream();
+    let display_sets: Vec<_> = DisplaySetIterator::new(&pgs).collect();
+
+    let per_set = time(|| {
+        for display_set in &display_sets {
+            black_box(render_display_set(display_set).unwrap());
+        }
+    });
+    let streamed = time(|| {
+        let mut renderer = StreamRenderer::new();
+        for display_set in &display_sets {
+            black_box(renderer.render(display_set).unwrap());
+        }
+    });
+
+    println!("{} display sets", display_sets.len());
+    println!("render_display_set: {per_set:?}");
+    println!("StreamRenderer:     {streamed:?}");
+}
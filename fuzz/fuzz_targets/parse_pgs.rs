@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pgs_rs::{parse_pgs, render::DisplaySetIterator};
+
+fuzz_target!(|data: &[u8]| {
+    let mut data = data.to_vec();
+    if let Ok(pgs) = parse_pgs(&mut data) {
+        let mut display_sets = DisplaySetIterator::new(&pgs);
+        while display_sets.try_next().is_some() {}
+    }
+});
@@ -0,0 +1,1268 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::{
+    error::PgsResult,
+    parse::{CompositionObject, ObjectDefinition, PaletteDefinition, Pgs, SegmentContents, Window},
+    render::{DisplaySet, DisplaySetIterator, PIXEL_SIZE, crop_distance, render_display_set_ayuv},
+    timing::pts_to_duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadeDirection {
+    In,
+    Out,
+}
+
+/// A run of consecutive palette-update display sets that only animate the
+/// alpha of an otherwise unchanged composition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FadeGroup {
+    pub direction: FadeDirection,
+    pub start: Duration,
+    pub end: Duration,
+    /// Indices of the palette-update display sets making up the fade.
+    pub display_sets: Range<usize>,
+}
+
+impl FadeGroup {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    pub fn steps(&self) -> usize {
+        self.display_sets.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackKind {
+    /// No display set ever shows anything.
+    Empty,
+    /// Only forced subtitles, e.g. translations of foreign dialogue or signs.
+    ForcedOnly,
+    /// Regular subtitles with no forced events.
+    Normal,
+    /// A full track that also flags some events as forced.
+    Mixed,
+}
+
+impl Pgs {
+    /// Guesses what kind of subtitle track this is from the proportion of
+    /// visible events whose composition objects are all flagged as forced.
+    pub fn classify(&self) -> TrackKind {
+        let (mut forced, mut total) = (0usize, 0usize);
+        for display_set in DisplaySetIterator::new(self).filter(|ds| !ds.is_empty()) {
+            total += 1;
+            if display_set
+                .composition_objects
+                .iter()
+                .all(|composition_object| composition_object.forced)
+            {
+                forced += 1;
+            }
+        }
+        match (forced, total) {
+            (_, 0) => TrackKind::Empty,
+            (0, _) => TrackKind::Normal,
+            (forced, total) if forced == total => TrackKind::ForcedOnly,
+            _ => TrackKind::Mixed,
+        }
+    }
+
+    pub fn fade_groups(&self) -> Vec<FadeGroup> {
+        let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(self).collect();
+        let mut groups = Vec::new();
+        let mut index = 1;
+        while index < display_sets.len() {
+            let base = &display_sets[index - 1];
+            let start = index;
+            while index < display_sets.len()
+                && display_sets[index].palette_update
+                && same_composition(base, &display_sets[index])
+            {
+                index += 1;
+            }
+            if index == start {
+                index += 1;
+                continue;
+            }
+            let first = &display_sets[start];
+            let last = &display_sets[index - 1];
+            let direction = match total_alpha(last).cmp(&total_alpha(base)) {
+                std::cmp::Ordering::Greater => FadeDirection::In,
+                std::cmp::Ordering::Less => FadeDirection::Out,
+                std::cmp::Ordering::Equal => continue,
+            };
+            groups.push(FadeGroup {
+                direction,
+                start: pts_to_duration(first.presentation_timestamp),
+                end: pts_to_duration(last.presentation_timestamp),
+                display_sets: start..index,
+            });
+        }
+        groups
+    }
+}
+
+fn same_composition(a: &DisplaySet, b: &DisplaySet) -> bool {
+    !a.is_empty() && a.composition_objects == b.composition_objects
+}
+
+fn total_alpha(display_set: &DisplaySet) -> u64 {
+    display_set
+        .palettes
+        .get(&display_set.palette_id)
+        .map(|palette| {
+            palette
+                .entries
+                .values()
+                .map(|entry| entry.alpha as u64)
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// An `(x, y)` position in pixels.
+pub type Position = (u16, u16);
+
+/// Reports composition objects present in both display sets whose position
+/// changed, as `(id, (old_x, old_y), (new_x, new_y))`. Objects are matched
+/// by id alone, so one moved into another window is included. Objects that
+/// were added or removed are not.
+pub fn moved_objects(prev: &DisplaySet, cur: &DisplaySet) -> Vec<(u16, Position, Position)> {
+    cur.composition_objects
+        .iter()
+        .filter_map(|current| {
+            let previous = prev
+                .composition_objects
+                .iter()
+                .find(|previous| previous.id == current.id)?;
+            let old = (previous.horizontal_position, previous.vertical_position);
+            let new = (current.horizontal_position, current.vertical_position);
+            (old != new).then_some((current.id, old, new))
+        })
+        .collect()
+}
+
+impl CompositionObject {
+    /// The object's position as fractions of the frame size, for placing it
+    /// on a render target of a different resolution.
+    pub fn normalized_position(&self, frame_width: u16, frame_height: u16) -> (f32, f32) {
+        normalize(
+            (self.horizontal_position, self.vertical_position),
+            frame_width,
+            frame_height,
+        )
+    }
+
+    /// The crop rectangle in screen coordinates, the area of the frame the
+    /// object can draw to. Returns `None` if the object is not cropped.
+    pub fn screen_crop_rect(&self) -> Option<Rect> {
+        let crop = self.cropped.as_ref()?;
+        Some(Rect {
+            x: self
+                .horizontal_position
+                .saturating_add(crop.horizontal_position),
+            y: self
+                .vertical_position
+                .saturating_add(crop.vertical_position),
+            width: crop.width,
+            height: crop.height,
+        })
+    }
+}
+
+impl Window {
+    /// The window's position as fractions of the frame size.
+    pub fn normalized_position(&self, frame_width: u16, frame_height: u16) -> (f32, f32) {
+        normalize(
+            (self.horizontal_position, self.vertical_position),
+            frame_width,
+            frame_height,
+        )
+    }
+}
+
+fn normalize((x, y): Position, frame_width: u16, frame_height: u16) -> (f32, f32) {
+    (
+        x as f32 / frame_width.max(1) as f32,
+        y as f32 / frame_height.max(1) as f32,
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Top,
+    Middle,
+    Bottom,
+    /// The subtitle spans from the top third of the frame to the bottom third.
+    FullWidth,
+}
+
+impl DisplaySet<'_> {
+    /// Classifies where on screen the windows used by this display set sit,
+    /// by thirds of the frame height. Returns `None` if nothing is shown.
+    pub fn vertical_region(&self) -> Option<Region> {
+        let (top, bottom) = self
+            .composition_objects
+            .iter()
+            .filter_map(|composition_object| self.windows.get(&composition_object.window_id))
+            .map(|window| {
+                (
+                    window.vertical_position as u32,
+                    window.vertical_position as u32 + window.height as u32,
+                )
+            })
+            .reduce(|(top, bottom), (t, b)| (top.min(t), bottom.max(b)))?;
+        let third = self.height as u32 / 3;
+        Some(if top < third && bottom > 2 * third {
+            Region::FullWidth
+        } else if (top + bottom) / 2 < third {
+            Region::Top
+        } else if (top + bottom) / 2 >= 2 * third {
+            Region::Bottom
+        } else {
+            Region::Middle
+        })
+    }
+}
+
+/// Size information for an object definition, gathered without decoding its
+/// run-length data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub id: u16,
+    pub version: u8,
+    pub width: u16,
+    pub height: u16,
+    /// Size of the run-length data as read, summed across all fragments.
+    pub encoded_size: usize,
+}
+
+impl Pgs {
+    /// Lists every object definition in the stream, merging fragmented
+    /// objects into a single entry.
+    pub fn object_inventory(&self) -> Vec<ObjectInfo> {
+        // The first fragment of an object records the size of all of them.
+        self.segments
+            .iter()
+            .filter_map(|segment| match &segment.contents {
+                SegmentContents::ObjectDefinition(object) if object.last_in_sequence.is_first() => {
+                    Some(ObjectInfo {
+                        id: object.id,
+                        version: object.version,
+                        width: object.width,
+                        height: object.height,
+                        encoded_size: object.encoded_size(),
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl ObjectDefinition {
+    /// Size of the run-length data as read, or of its shortest encoding for
+    /// objects built in memory.
+    pub fn encoded_size(&self) -> usize {
+        self.raw_data_len.unwrap_or_else(|| self.data.encoded_len())
+    }
+
+    /// The distinct palette entries referenced by the object's pixels.
+    pub fn used_colors(&self) -> HashSet<u8> {
+        self.data
+            .0
+            .iter()
+            .filter(|run| run.count > 0)
+            .map(|run| run.color)
+            .collect()
+    }
+}
+
+/// How well an object's pixels compress under run-length encoding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RleStats {
+    /// Size of the run-length data, as given by
+    /// [`ObjectDefinition::encoded_size`].
+    pub encoded_bytes: usize,
+    /// Number of pixels the runs decode to.
+    pub pixels: u64,
+    /// Encoded bytes per decoded pixel; lower is better.
+    pub bytes_per_pixel: f64,
+    /// Number of runs, excluding end-of-line markers.
+    pub runs: usize,
+    /// Number of runs covering a single pixel, which gain nothing from the
+    /// encoding. Many of them suggest a poor encoder.
+    pub single_pixel_runs: usize,
+}
+
+impl ObjectDefinition {
+    pub fn rle_stats(&self) -> RleStats {
+        let encoded_bytes = self.encoded_size();
+        let runs = self.data.0.iter().filter(|run| run.count > 0);
+        let pixels: u64 = runs.clone().map(|run| run.count as u64).sum();
+        RleStats {
+            encoded_bytes,
+            pixels,
+            bytes_per_pixel: if pixels == 0 {
+                0.0
+            } else {
+                encoded_bytes as f64 / pixels as f64
+            },
+            runs: runs.clone().count(),
+            single_pixel_runs: runs.filter(|run| run.count == 1).count(),
+        }
+    }
+}
+
+impl DisplaySet<'_> {
+    /// The distinct palette entries referenced by the composed objects.
+    pub fn used_colors(&self) -> HashSet<u8> {
+        self.composition_objects
+            .iter()
+            .filter_map(|composition_object| self.objects.get(&composition_object.id))
+            .flat_map(|object| object.used_colors())
+            .collect()
+    }
+
+    /// Number of palette entries that are both referenced by a composed object
+    /// and not fully transparent.
+    pub fn effective_palette_size(&self) -> usize {
+        let Some(palette) = self.palettes.get(&self.palette_id) else {
+            return 0;
+        };
+        self.used_colors()
+            .into_iter()
+            .filter(|color| {
+                palette
+                    .entries
+                    .get(color)
+                    .is_some_and(|entry| entry.alpha > 0)
+            })
+            .count()
+    }
+
+    /// Minimum number of bits per pixel needed to index the distinct palette
+    /// entries referenced by the composed objects, e.g. 2 for an object drawn
+    /// with three colors. A display set using a single color needs none.
+    pub fn effective_bit_depth(&self) -> u8 {
+        let colors = self.used_colors().len() as u32;
+        (u32::BITS - colors.saturating_sub(1).leading_zeros()) as u8
+    }
+}
+
+impl Pgs {
+    /// Reports every point where the set of defined windows changes, along
+    /// with the new layout ordered by window id.
+    pub fn window_layout_changes(&self) -> Vec<(Duration, Vec<Window>)> {
+        let mut changes: Vec<(Duration, Vec<Window>)> = Vec::new();
+        for display_set in DisplaySetIterator::new(self) {
+            let mut layout: Vec<Window> = display_set
+                .windows
+                .values()
+                .map(|window| (*window).clone())
+                .collect();
+            layout.sort_by_key(|window| window.id);
+            if changes
+                .last()
+                .is_none_or(|(_, previous)| *previous != layout)
+            {
+                changes.push((pts_to_duration(display_set.presentation_timestamp), layout));
+            }
+        }
+        changes
+    }
+}
+
+/// Peak resource usage of a stream, as reported by [`Pgs::peak_complexity`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Complexity {
+    /// Most distinct windows shown by a single display set.
+    pub max_windows: usize,
+    /// Most composition objects shown by a single display set.
+    pub max_objects: usize,
+    /// Most entries in the palette used by a single display set.
+    pub max_palette_entries: usize,
+}
+
+impl Pgs {
+    /// The highest number of windows, objects and palette entries in use by
+    /// any single display set, e.g. to check against a hardware decoder's
+    /// limits.
+    pub fn peak_complexity(&self) -> Complexity {
+        let mut peak = Complexity::default();
+        for display_set in DisplaySetIterator::new(self) {
+            let windows: HashSet<u8> = display_set
+                .composition_objects
+                .iter()
+                .map(|composition_object| composition_object.window_id)
+                .collect();
+            let palette_entries = display_set
+                .palettes
+                .get(&display_set.palette_id)
+                .map_or(0, |palette| palette.entries.len());
+            peak.max_windows = peak.max_windows.max(windows.len());
+            peak.max_objects = peak.max_objects.max(display_set.composition_objects.len());
+            peak.max_palette_entries = peak.max_palette_entries.max(palette_entries);
+        }
+        peak
+    }
+}
+
+/// Storage resolutions commonly used for anamorphic video, with the display
+/// aspect ratio they are usually intended for.
+const ANAMORPHIC_RESOLUTIONS: [((u16, u16), (u32, u32)); 5] = [
+    ((720, 480), (16, 9)),
+    ((720, 576), (16, 9)),
+    ((704, 480), (16, 9)),
+    ((1440, 1080), (16, 9)),
+    ((1280, 1080), (16, 9)),
+];
+
+impl Pgs {
+    /// Guesses the display aspect ratio when the stream's resolution is one
+    /// commonly used for anamorphic video. PGS does not carry the display
+    /// aspect ratio, so this is only a hint; `None` means the resolution is
+    /// not a known anamorphic one and its pixels are most likely square.
+    pub fn likely_display_aspect(&self) -> Option<(u32, u32)> {
+        let resolution = self
+            .segments
+            .iter()
+            .find_map(|segment| match &segment.contents {
+                SegmentContents::PresentationComposition(composition) => {
+                    Some((composition.width, composition.height))
+                }
+                _ => None,
+            })?;
+        ANAMORPHIC_RESOLUTIONS
+            .iter()
+            .find(|(known, _)| *known == resolution)
+            .map(|(_, aspect)| *aspect)
+    }
+}
+
+/// An axis-aligned rectangle in frame pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl DisplaySet<'_> {
+    /// Splits the rendered subtitle into candidate text lines: bands of rows
+    /// containing visible pixels, separated by fully transparent rows. Each
+    /// rectangle is trimmed horizontally to the visible pixels of its band.
+    pub fn text_line_rects(&self) -> PgsResult<Vec<Rect>> {
+        let ayuv = render_display_set_ayuv(self)?;
+        let width = self.width as usize;
+        let mut rects: Vec<Rect> = Vec::new();
+        let mut open = false;
+        for (y, row) in ayuv.chunks_exact(width * PIXEL_SIZE).enumerate() {
+            let mut visible = row
+                .chunks_exact(PIXEL_SIZE)
+                .enumerate()
+                .filter(|(_, pixel)| pixel[0] > 0)
+                .map(|(x, _)| x);
+            let Some(first) = visible.next() else {
+                open = false;
+                continue;
+            };
+            let last = visible.next_back().unwrap_or(first);
+            match rects.last_mut() {
+                Some(rect) if open => {
+                    let left = (rect.x as usize).min(first);
+                    let right = (rect.x as usize + rect.width as usize).max(last + 1);
+                    rect.x = left as u16;
+                    rect.width = (right - left) as u16;
+                    rect.height += 1;
+                }
+                _ => rects.push(Rect {
+                    x: first as u16,
+                    y: y as u16,
+                    width: (last + 1 - first) as u16,
+                    height: 1,
+                }),
+            }
+            open = true;
+        }
+        Ok(rects)
+    }
+}
+
+impl DisplaySet<'_> {
+    /// A window with id 0 covering every composition object, for re-authoring
+    /// the display set with a single window. Cropped objects count only
+    /// their [`CompositionObject::screen_crop_rect`] and objects without a
+    /// definition are ignored. Returns `None` if no object could be measured.
+    pub fn enclosing_window(&self) -> Option<Window> {
+        let (left, top, right, bottom) = self
+            .composition_objects
+            .iter()
+            .filter_map(|composition_object| {
+                let object = self.objects.get(&composition_object.id)?;
+                let rect = composition_object.screen_crop_rect().unwrap_or(Rect {
+                    x: composition_object.horizontal_position,
+                    y: composition_object.vertical_position,
+                    width: object.width,
+                    height: object.height,
+                });
+                let (x, y) = (rect.x as u32, rect.y as u32);
+                Some((x, y, x + rect.width as u32, y + rect.height as u32))
+            })
+            .reduce(|(left, top, right, bottom), (x, y, x_end, y_end)| {
+                (left.min(x), top.min(y), right.max(x_end), bottom.max(y_end))
+            })?;
+        Some(Window {
+            id: 0,
+            horizontal_position: left as u16,
+            vertical_position: top as u16,
+            width: (right - left).min(u16::MAX as u32) as u16,
+            height: (bottom - top).min(u16::MAX as u32) as u16,
+        })
+    }
+
+    /// Mean opacity of the rendered pixels within the
+    /// [`DisplaySet::enclosing_window`], from 0.0 for a fully transparent
+    /// area to 1.0 for a fully opaque one. The part of that area outside the
+    /// frame is ignored. Returns 0.0 if nothing is shown.
+    pub fn opacity_ratio(&self) -> PgsResult<f32> {
+        let Some(bounds) = self.enclosing_window() else {
+            return Ok(0.0);
+        };
+        let frame_width = self.width as usize;
+        let left = (bounds.horizontal_position as usize).min(frame_width);
+        let right = (left + bounds.width as usize).min(frame_width);
+        let top = (bounds.vertical_position as usize).min(self.height as usize);
+        let bottom = (top + bounds.height as usize).min(self.height as usize);
+        let area = (right - left) * (bottom - top);
+        if area == 0 {
+            return Ok(0.0);
+        }
+        let ayuv = render_display_set_ayuv(self)?;
+        let alpha: u64 = (top..bottom)
+            .flat_map(|y| (left..right).map(move |x| (y * frame_width + x) * PIXEL_SIZE))
+            .map(|offset| ayuv[offset] as u64)
+            .sum();
+        Ok(alpha as f32 / (area as f32 * 255.0))
+    }
+}
+
+impl ObjectDefinition {
+    /// Trims the rows and columns along the object's edges whose pixels are
+    /// all transparent in `palette`, returning the remaining area as a
+    /// rectangle relative to the object's top-left corner along with its
+    /// row-major palette indices. Entries missing from the palette count as
+    /// transparent. A fully transparent object yields an empty rectangle.
+    pub fn trim_transparent(&self, palette: &PaletteDefinition) -> (Rect, Vec<u8>) {
+        let indices = self.decode_indexed();
+        let width = self.width as usize;
+        let visible = |index: &u8| {
+            palette
+                .entries
+                .get(index)
+                .is_some_and(|entry| entry.alpha > 0)
+        };
+        let (mut left, mut right, mut top, mut bottom) = (width, 0, self.height as usize, 0);
+        for (y, row) in indices.chunks_exact(width.max(1)).enumerate() {
+            let Some(first) = row.iter().position(visible) else {
+                continue;
+            };
+            let last = row.iter().rposition(visible).unwrap_or(first);
+            left = left.min(first);
+            right = right.max(last + 1);
+            top = top.min(y);
+            bottom = y + 1;
+        }
+        if left >= right {
+            return (
+                Rect {
+                    x: 0,
+                    y: 0,
+                    width: 0,
+                    height: 0,
+                },
+                Vec::new(),
+            );
+        }
+        let trimmed = (top..bottom)
+            .flat_map(|y| &indices[y * width + left..y * width + right])
+            .copied()
+            .collect();
+        (
+            Rect {
+                x: left as u16,
+                y: top as u16,
+                width: (right - left) as u16,
+                height: (bottom - top) as u16,
+            },
+            trimmed,
+        )
+    }
+}
+
+impl ObjectDefinition {
+    /// The palette index of the pixel at `(column, row)`, found by summing the
+    /// run lengths of that scanline alone rather than decoding the whole
+    /// object. Agrees with [`ObjectDefinition::decode_indexed`], so missing
+    /// pixels read as index 0. Returns `None` outside the object.
+    pub fn index_at(&self, column: u16, row: u16) -> Option<u8> {
+        if column >= self.width || row >= self.height {
+            return None;
+        }
+        let Some(range) = self.data.line_ranges().get(row as usize).cloned() else {
+            return Some(0);
+        };
+        let mut end = 0;
+        for run in &self.data.0[range] {
+            end += run.count as usize;
+            if (column as usize) < end {
+                return Some(run.color);
+            }
+        }
+        Some(0)
+    }
+}
+
+impl DisplaySet<'_> {
+    /// The id of the composition object visible at frame pixel `(x, y)`,
+    /// taking the object's crop rectangle and window into account. Returns
+    /// `None` if the pixel is transparent.
+    pub fn object_at(&self, x: u16, y: u16) -> Option<u16> {
+        let palette = self.palettes.get(&self.palette_id)?;
+        // Later composition objects are drawn over earlier ones.
+        self.composition_objects
+            .iter()
+            .rev()
+            .find(|composition_object| {
+                let (Some(object), Some(window)) = (
+                    self.objects.get(&composition_object.id),
+                    self.windows.get(&composition_object.window_id),
+                ) else {
+                    return false;
+                };
+                let within = |position: u16, start: u16, length: u16| {
+                    (start as u32..start as u32 + length as u32).contains(&(position as u32))
+                };
+                let in_window = within(x, window.horizontal_position, window.width)
+                    && within(y, window.vertical_position, window.height);
+                let (Some(column), Some(row)) = (
+                    x.checked_sub(composition_object.horizontal_position),
+                    y.checked_sub(composition_object.vertical_position),
+                ) else {
+                    return false;
+                };
+                if !in_window
+                    || crop_distance(column as usize, row as usize, composition_object) > 0
+                {
+                    return false;
+                }
+                object
+                    .index_at(column, row)
+                    .and_then(|color| palette.entries.get(&color))
+                    .is_some_and(|entry| entry.alpha > 0)
+            })
+            .map(|composition_object| composition_object.id)
+    }
+}
+
+impl Pgs {
+    /// The id of the composition object visible at frame pixel `(x, y)` at
+    /// time `pts`, as in [`DisplaySet::object_at`]. Each call scans the stream
+    /// up to `pts`; for repeated queries, keep the display set on screen and
+    /// query it directly.
+    pub fn object_at(&self, pts: u32, x: u16, y: u16) -> Option<u16> {
+        DisplaySetIterator::new(self)
+            .take_while(|display_set| display_set.presentation_timestamp <= pts)
+            .last()?
+            .object_at(x, y)
+    }
+}
+
+impl Pgs {
+    /// Number of pixels drawn with each palette entry across every object in
+    /// the stream, keyed by `(palette_id, entry_id)`. Each object is counted
+    /// once, against the palette selected by the display set defining it.
+    pub fn palette_usage_histogram(&self) -> HashMap<(u8, u8), u64> {
+        let mut histogram = HashMap::new();
+        let mut palette_id = 0;
+        for segment in &self.segments {
+            match &segment.contents {
+                SegmentContents::PresentationComposition(composition) => {
+                    palette_id = composition.palette_id;
+                }
+                SegmentContents::ObjectDefinition(object) => {
+                    for run in &object.data.0 {
+                        *histogram.entry((palette_id, run.color)).or_insert(0) += run.count as u64;
+                    }
+                }
+                _ => {}
+            }
+        }
+        histogram.retain(|_, count| *count > 0);
+        histogram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        PALETTE, clear, composition_object, cue, end, object_fragments, ods, pcs, pcs_with_objects,
+        pds, wds, with_palette_id,
+    };
+    use crate::parse::{RlEncodedPixels, RunLengthEncodedData};
+    use crate::parse_pgs;
+
+    #[test]
+    fn fade_out_is_grouped() {
+        let mut segments = vec![
+            pcs(0, (64, 32), 0, 0x80, 0, &[(0, 0, 8, 8)]),
+            wds(0, &[(0, 8, 8, 4, 2)]),
+            pds(0, 0, &[(1, 235, 128, 128, 255)]),
+            ods(0, 0, 4, &[1; 8]),
+            end(0),
+        ];
+        for (step, alpha) in [170, 85, 0].into_iter().enumerate() {
+            let pts = 9_000 * (step as u32 + 1);
+            segments.extend([
+                pcs(pts, (64, 32), step as u16 + 1, 0x00, 0x80, &[(0, 0, 8, 8)]),
+                pds(pts, step as u8 + 1, &[(1, 235, 128, 128, alpha)]),
+                end(pts),
+            ]);
+        }
+        let pgs = parse_pgs(&mut segments.concat()).unwrap();
+
+        let groups = pgs.fade_groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].direction, FadeDirection::Out);
+        assert_eq!(groups[0].display_sets, 1..4);
+        assert_eq!(groups[0].steps(), 3);
+        assert_eq!(groups[0].start, Duration::from_millis(100));
+        assert_eq!(groups[0].duration(), Duration::from_millis(200));
+    }
+
+    /// A stream with one cue per entry of `forced`, each showing a single
+    /// object flagged as forced or not and cleared before the next.
+    fn cues_with_forced_flags(forced: &[bool]) -> Pgs {
+        let mut segments = Vec::new();
+        for (index, forced) in forced.iter().enumerate() {
+            let pts = index as u32 * 90_000;
+            let flags = if *forced { 0x80 } else { 0 };
+            segments.extend([
+                pcs_with_objects(
+                    pts,
+                    (64, 32),
+                    0,
+                    0x80,
+                    0,
+                    &[composition_object(0, 0, (8, 8), flags, None)],
+                ),
+                wds(pts, &[(0, 8, 8, 2, 2)]),
+                pds(pts, 0, &PALETTE),
+                ods(pts, 0, 2, &[1; 4]),
+                end(pts),
+                clear(pts + 45_000, (64, 32)),
+            ]);
+        }
+        parse_pgs(&mut segments.concat()).unwrap()
+    }
+
+    #[test]
+    fn forced_only_track() {
+        assert_eq!(
+            cues_with_forced_flags(&[true, true]).classify(),
+            TrackKind::ForcedOnly
+        );
+    }
+
+    #[test]
+    fn mixed_track() {
+        assert_eq!(
+            cues_with_forced_flags(&[false, true, false]).classify(),
+            TrackKind::Mixed
+        );
+        assert_eq!(
+            cues_with_forced_flags(&[false]).classify(),
+            TrackKind::Normal
+        );
+        let cleared = parse_pgs(&mut clear(0, (64, 32))).unwrap();
+        assert_eq!(cleared.classify(), TrackKind::Empty);
+    }
+
+    #[test]
+    fn object_shifted_right_is_reported() {
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[(0, 0, 8, 8), (1, 0, 20, 8)]),
+            wds(0, &[(0, 0, 0, 64, 16), (1, 0, 16, 64, 16)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 2, &[1; 4]),
+            ods(0, 1, 2, &[1; 4]),
+            end(0),
+            pcs(9_000, (64, 32), 1, 0x00, 0, &[(0, 0, 18, 8), (1, 0, 20, 8)]),
+            end(9_000),
+            pcs(18_000, (64, 32), 2, 0x00, 0, &[(0, 1, 18, 20)]),
+            end(18_000),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(&pgs).collect();
+
+        assert_eq!(
+            moved_objects(&display_sets[0], &display_sets[1]),
+            [(0, (8, 8), (18, 8))]
+        );
+        assert_eq!(
+            moved_objects(&display_sets[1], &display_sets[2]),
+            [(0, (18, 8), (18, 20))]
+        );
+    }
+
+    #[test]
+    fn window_in_the_top_quarter_is_top() {
+        let region = |y: u16| {
+            let pgs = parse_pgs(&mut cue(0, (64, 64), (8, y), 4, &[1; 8])).unwrap();
+            DisplaySetIterator::new(&pgs)
+                .next()
+                .unwrap()
+                .vertical_region()
+        };
+        assert_eq!(region(4), Some(Region::Top));
+        assert_eq!(region(30), Some(Region::Middle));
+        assert_eq!(region(58), Some(Region::Bottom));
+
+        let cleared = parse_pgs(&mut clear(0, (64, 64))).unwrap();
+        let display_set = DisplaySetIterator::new(&cleared).next().unwrap();
+        assert_eq!(display_set.vertical_region(), None);
+    }
+
+    #[test]
+    fn inventory_reports_the_size_as_read() {
+        // A run of two pixels written with the long code instead of the
+        // three byte one, split across three fragments.
+        let long_codes = [0, 0xC0, 2, 1, 0, 0].repeat(2);
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[]),
+            ods(0, 0, 2, &[1; 4]),
+            object_fragments(0, 1, (2, 2), &long_codes, &[9, 14]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        let info = |id, encoded_size| ObjectInfo {
+            id,
+            version: 0,
+            width: 2,
+            height: 2,
+            encoded_size,
+        };
+        assert_eq!(pgs.object_inventory(), [info(0, 10), info(1, 12)]);
+
+        let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+        let stats = display_set.objects[&1].rle_stats();
+        assert_eq!(stats.encoded_bytes, 12);
+        assert_eq!(stats.bytes_per_pixel, 3.0);
+        assert_eq!(display_set.objects[&1].data.encoded_len(), 10);
+    }
+
+    #[test]
+    fn effective_palette_size_ignores_unused_and_transparent_entries() {
+        // 256 entries, of which the object draws with one transparent and
+        // two opaque ones.
+        let entries: Vec<(u8, u8, u8, u8, u8)> =
+            (0..=255).map(|id| (id, 128, 128, 128, id)).collect();
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[(0, 0, 0, 0)]),
+            wds(0, &[(0, 0, 0, 4, 1)]),
+            pds(0, 0, &entries),
+            ods(0, 0, 4, &[0, 7, 7, 200]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+
+        assert_eq!(display_set.palettes[&0].entries.len(), 256);
+        assert_eq!(display_set.effective_palette_size(), 2);
+    }
+
+    #[test]
+    fn positions_are_normalized_to_the_frame() {
+        let pgs = parse_pgs(&mut cue(0, (1920, 1080), (480, 810), 2, &[1; 4])).unwrap();
+        let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+
+        let position = display_set.composition_objects[0].normalized_position(1920, 1080);
+        assert_eq!(position, (0.25, 0.75));
+        assert_eq!(
+            display_set.windows[&0].normalized_position(1920, 1080),
+            (0.25, 0.75)
+        );
+        assert_eq!(
+            display_set.windows[&0].normalized_position(0, 0),
+            (480.0, 810.0)
+        );
+    }
+
+    #[test]
+    fn window_layout_changes_between_epochs() {
+        let mut bytes = [
+            cue(0, (64, 32), (8, 8), 4, &[1; 8]),
+            clear(90_000, (64, 32)),
+            cue(180_000, (64, 32), (8, 8), 4, &[3; 8]),
+            cue(270_000, (64, 32), (16, 20), 2, &[1; 6]),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut bytes).unwrap();
+
+        let window = |x, y, width, height| Window {
+            id: 0,
+            horizontal_position: x,
+            vertical_position: y,
+            width,
+            height,
+        };
+        assert_eq!(
+            pgs.window_layout_changes(),
+            vec![
+                (Duration::ZERO, vec![window(8, 8, 4, 2)]),
+                (Duration::from_secs(3), vec![window(16, 20, 2, 3)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn peak_complexity_takes_each_maximum_across_display_sets() {
+        let six_entries: Vec<(u8, u8, u8, u8, u8)> =
+            (0..6).map(|id| (id, 255, 128, 128, 255)).collect();
+        let mut data = [
+            pcs(
+                0,
+                (64, 32),
+                0,
+                0x80,
+                0,
+                &[(0, 0, 0, 0), (1, 0, 4, 0), (2, 1, 0, 16)],
+            ),
+            wds(0, &[(0, 0, 0, 8, 2), (1, 0, 16, 2, 2)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 2, &[1; 4]),
+            ods(0, 1, 2, &[1; 4]),
+            ods(0, 2, 2, &[1; 4]),
+            end(0),
+            clear(90_000, (64, 32)),
+            pcs(180_000, (64, 32), 2, 0x80, 0, &[(0, 0, 0, 0)]),
+            wds(180_000, &[(0, 0, 0, 2, 2)]),
+            pds(180_000, 0, &six_entries),
+            ods(180_000, 0, 2, &[5; 4]),
+            end(180_000),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        assert_eq!(
+            pgs.peak_complexity(),
+            Complexity {
+                max_windows: 2,
+                max_objects: 3,
+                max_palette_entries: 6,
+            }
+        );
+    }
+
+    #[test]
+    fn anamorphic_resolution_hints_at_its_display_aspect() {
+        let aspect = |frame| {
+            parse_pgs(&mut cue(0, frame, (0, 0), 2, &[1; 4]))
+                .unwrap()
+                .likely_display_aspect()
+        };
+        assert_eq!(aspect((720, 480)), Some((16, 9)));
+        assert_eq!(aspect((1920, 1080)), None);
+    }
+
+    #[test]
+    fn two_line_subtitle_has_two_text_line_rects() {
+        #[rustfmt::skip]
+        let indices = [
+            0, 1, 1, 1, 0, 0,
+            0, 1, 0, 1, 0, 0,
+            0, 0, 0, 0, 0, 0,
+            1, 1, 0, 0, 1, 0,
+        ];
+        let mut data = cue(0, (16, 8), (4, 2), 6, &indices);
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+
+        assert_eq!(
+            display_set.text_line_rects().unwrap(),
+            [
+                Rect {
+                    x: 5,
+                    y: 2,
+                    width: 3,
+                    height: 2,
+                },
+                Rect {
+                    x: 4,
+                    y: 5,
+                    width: 5,
+                    height: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn object_at_finds_the_object_under_a_pixel() {
+        let mut data = [
+            pcs_with_objects(
+                9_000,
+                (64, 32),
+                0,
+                0x80,
+                0,
+                &[
+                    composition_object(0, 0, (4, 2), 0, None),
+                    composition_object(1, 0, (10, 2), 0x40, Some((0, 0, 2, 1))),
+                ],
+            ),
+            wds(9_000, &[(0, 4, 2, 7, 2)]),
+            pds(9_000, 0, &PALETTE),
+            ods(9_000, 0, 3, &[1, 0, 1, 1, 1, 1]),
+            ods(9_000, 1, 2, &[3; 4]),
+            end(9_000),
+            clear(18_000, (64, 32)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        assert_eq!(pgs.object_at(9_000, 4, 2), Some(0));
+        assert_eq!(pgs.object_at(12_000, 6, 3), Some(0));
+        assert_eq!(pgs.object_at(9_000, 10, 2), Some(1));
+        // A transparent pixel, a pixel cropped away, one outside the window
+        // and one outside every object.
+        assert_eq!(pgs.object_at(9_000, 5, 2), None);
+        assert_eq!(pgs.object_at(9_000, 10, 3), None);
+        assert_eq!(pgs.object_at(9_000, 11, 2), None);
+        assert_eq!(pgs.object_at(9_000, 0, 0), None);
+        // Before the subtitle is shown and after it is cleared.
+        assert_eq!(pgs.object_at(0, 4, 2), None);
+        assert_eq!(pgs.object_at(18_000, 4, 2), None);
+
+        // The same lookups against the display set on screen.
+        let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+        assert_eq!(display_set.object_at(4, 2), Some(0));
+        assert_eq!(display_set.object_at(10, 2), Some(1));
+        assert_eq!(display_set.object_at(5, 2), None);
+    }
+
+    #[test]
+    fn index_at_agrees_with_the_decoded_bitmap() {
+        let object =
+            ObjectDefinition::from_bitmap(0, 0, 3, 3, &[1, 0, 2, 3, 3, 3, 0, 0, 0]).unwrap();
+        // A run spilling past its line, then a short line and a missing one.
+        let mut malformed = object.clone();
+        let run = |count, color| RlEncodedPixels { count, color };
+        malformed.data = RunLengthEncodedData(vec![run(1, 1), run(5, 2), run(0, 0), run(1, 3)]);
+        for object in [object, malformed] {
+            let indices = object.decode_indexed();
+            for row in 0..3 {
+                for column in 0..3 {
+                    assert_eq!(
+                        object.index_at(column, row),
+                        Some(indices[row as usize * 3 + column as usize])
+                    );
+                }
+            }
+            assert_eq!(object.index_at(3, 0), None);
+            assert_eq!(object.index_at(0, 3), None);
+        }
+    }
+
+    #[test]
+    fn palette_usage_counts_pixels_per_palette_entry() {
+        let mut data = [
+            cue(0, (64, 32), (0, 0), 3, &[1, 1, 1, 3, 0, 0]),
+            with_palette_id(pcs(9_000, (64, 32), 1, 0x80, 0, &[(0, 0, 0, 0)]), 1),
+            wds(9_000, &[(0, 0, 0, 2, 2)]),
+            with_palette_id(pds(9_000, 0, &PALETTE), 1),
+            ods(9_000, 0, 2, &[2; 4]),
+            end(9_000),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        assert_eq!(
+            pgs.palette_usage_histogram(),
+            HashMap::from([((0, 0), 2), ((0, 1), 3), ((0, 3), 1), ((1, 2), 4)])
+        );
+    }
+
+    #[test]
+    fn three_colors_need_two_bits() {
+        let bit_depth = |indices: &[u8]| {
+            let pgs = parse_pgs(&mut cue(0, (64, 32), (0, 0), 3, indices)).unwrap();
+            DisplaySetIterator::new(&pgs)
+                .next()
+                .unwrap()
+                .effective_bit_depth()
+        };
+        assert_eq!(bit_depth(&[0, 1, 3, 1, 1, 1]), 2);
+        assert_eq!(bit_depth(&[1; 6]), 0);
+        assert_eq!(bit_depth(&[1, 3, 1, 3, 1, 3]), 1);
+        assert_eq!(bit_depth(&[0, 1, 2, 3, 4, 5]), 3);
+    }
+
+    #[test]
+    fn transparent_border_is_trimmed() {
+        #[rustfmt::skip]
+        let indices = [
+            0, 0, 0, 0, 0,
+            0, 1, 3, 0, 0,
+            0, 0, 2, 0, 0,
+            0, 0, 0, 0, 0,
+        ];
+        let mut data = cue(0, (64, 32), (0, 0), 5, &indices);
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+        let (object, palette) = (
+            display_set.object(0).unwrap(),
+            display_set.palette(0).unwrap(),
+        );
+
+        assert_eq!(
+            object.trim_transparent(palette),
+            (
+                Rect {
+                    x: 1,
+                    y: 1,
+                    width: 2,
+                    height: 2,
+                },
+                vec![1, 3, 0, 2],
+            )
+        );
+    }
+
+    #[test]
+    fn enclosing_window_covers_every_object() {
+        let mut data = [
+            pcs_with_objects(
+                0,
+                (64, 32),
+                0,
+                0x80,
+                0,
+                &[
+                    composition_object(0, 0, (2, 2), 0, None),
+                    composition_object(1, 0, (20, 10), 0, None),
+                    // Only the 2x2 crop at (32, 2) on screen is shown.
+                    composition_object(2, 0, (30, 1), 0x40, Some((2, 1, 2, 2))),
+                ],
+            ),
+            wds(0, &[(0, 0, 0, 64, 32)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 4, &[1; 8]),
+            ods(0, 1, 2, &[1; 4]),
+            ods(0, 2, 6, &[1; 24]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+        assert_eq!(
+            display_set.enclosing_window(),
+            Some(Window {
+                id: 0,
+                horizontal_position: 2,
+                vertical_position: 2,
+                width: 32,
+                height: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn rle_stats_count_runs_and_bytes() {
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[]),
+            ods(0, 0, 8, &[1, 3, 1, 3, 2, 2, 2, 2, 0, 0, 0, 0, 0, 0, 0, 0]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let SegmentContents::ObjectDefinition(object) = &pgs.segments[1].contents else {
+            panic!("expected an object definition");
+        };
+        // Four single pixels and a run of four on the first line, a run of
+        // eight on the second, and an end of line code after each.
+        assert_eq!(
+            object.rle_stats(),
+            RleStats {
+                encoded_bytes: 4 + 3 + 2 + 2 + 2,
+                pixels: 16,
+                bytes_per_pixel: 13.0 / 16.0,
+                runs: 6,
+                single_pixel_runs: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn crop_rect_is_translated_to_the_screen() {
+        let mut data = [
+            pcs_with_objects(
+                0,
+                (64, 32),
+                0,
+                0x80,
+                0,
+                &[
+                    composition_object(0, 0, (20, 10), 0x40, Some((3, 2, 4, 5))),
+                    composition_object(1, 0, (0, 0), 0, None),
+                ],
+            ),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let SegmentContents::PresentationComposition(composition) = &pgs.segments[0].contents
+        else {
+            panic!("expected a presentation composition");
+        };
+        let rects: Vec<Option<Rect>> = composition
+            .composition_objects
+            .iter()
+            .map(CompositionObject::screen_crop_rect)
+            .collect();
+        assert_eq!(
+            rects,
+            [
+                Some(Rect {
+                    x: 23,
+                    y: 12,
+                    width: 4,
+                    height: 5,
+                }),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn half_opaque_objects_have_an_opacity_ratio_of_about_half() {
+        // Half of the pixels opaque, then every pixel at half alpha.
+        for indices in [[1, 3, 0, 0], [2; 4]] {
+            let mut data = cue(0, (16, 8), (3, 2), 2, &indices);
+            let pgs = parse_pgs(&mut data).unwrap();
+            let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+            let ratio = display_set.opacity_ratio().unwrap();
+            assert!((ratio - 0.5).abs() < 0.01, "{indices:?} gave {ratio}");
+        }
+    }
+}
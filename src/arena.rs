@@ -0,0 +1,629 @@
+use std::collections::HashMap;
+
+use bumpalo::Bump;
+use bumpalo::collections::Vec;
+use winnow::Result as PResult;
+use winnow::binary::{be_u8, be_u16, be_u24, be_u32, length_and_then};
+use winnow::combinator::{dispatch, fail, repeat};
+use winnow::error::{ContextError, StrContext};
+use winnow::prelude::*;
+use winnow::stream::Range;
+use winnow::token::{rest, take_while};
+
+use crate::{
+    error::{PgsError, PgsResult},
+    parse::{
+        CompositionObject, CompositionState, LastInSequence, MAGIC_CONTEXT, ObjectDefinition,
+        PaletteDefinition, PaletteEntry, Pgs, PresentationComposition, RawFragment, RawObjectData,
+        RlEncodedPixels, RunLengthEncodedData, Segment, SegmentContents, Window, WindowDefinition,
+        composition_state, parse_composition_object, parse_end_of_display_set_segment,
+        parse_last_in_sequence, parse_palette_entry, parse_single_encoded_pixel, parse_window,
+        segment_error,
+    },
+};
+
+/// A stream parsed by [`parse_pgs_in`]. Mirrors [`Pgs`], but every segment,
+/// and every list a segment decodes to, is borrowed from the arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaPgs<'b> {
+    pub segments: &'b [ArenaSegment<'b>],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaSegment<'b> {
+    pub pts: u32,
+    pub dts: u32,
+    pub contents: ArenaSegmentContents<'b>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArenaSegmentContents<'b> {
+    PresentationComposition(ArenaPresentationComposition<'b>),
+    WindowDefinition(&'b [Window]),
+    PaletteDefinition(ArenaPaletteDefinition<'b>),
+    ObjectDefinition(ArenaObjectDefinition<'b>),
+    End,
+}
+
+/// See [`PresentationComposition`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaPresentationComposition<'b> {
+    pub width: u16,
+    pub height: u16,
+    pub frame_rate: u8,
+    pub composition_number: u16,
+    pub composition_state: CompositionState,
+    pub raw_composition_state: u8,
+    pub palette_update: bool,
+    pub raw_palette_update_flag: u8,
+    pub palette_id: u8,
+    pub composition_objects: &'b [CompositionObject],
+}
+
+/// See [`PaletteDefinition`]. Entries are kept in the order they were read,
+/// so when an id repeats the last entry with that id is the one in effect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaPaletteDefinition<'b> {
+    pub id: u8,
+    pub version: u8,
+    pub entries: &'b [PaletteEntry],
+}
+
+/// See [`ObjectDefinition`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct ArenaObjectDefinition<'b> {
+    pub id: u16,
+    pub version: u8,
+    pub last_in_sequence: LastInSequence,
+    pub width: u16,
+    pub height: u16,
+    pub data: &'b [RlEncodedPixels],
+    pub raw_data_len: Option<usize>,
+    pub raw: Option<ArenaRawObjectData<'b>>,
+}
+
+/// See [`RawObjectData`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaRawObjectData<'b> {
+    pub bytes: &'b [u8],
+    pub fragments: &'b [RawFragment],
+}
+
+impl std::fmt::Debug for ArenaObjectDefinition<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArenaObjectDefinition")
+            .field("id", &self.id)
+            .field("version", &self.version)
+            .field("last_in_sequence", &self.last_in_sequence)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field(
+                "data",
+                &self.data.iter().map(|p| p.count as u64).sum::<u64>(),
+            )
+            .field("raw_data_len", &self.raw_data_len)
+            .field("raw", &self.raw)
+            .finish()
+    }
+}
+
+impl ArenaPgs<'_> {
+    /// Copies the stream onto the heap, e.g. to render it with
+    /// [`get_display_sets`](crate::render::get_display_sets). The copy owns
+    /// its storage, so it outlives a [`Bump::reset`].
+    pub fn to_pgs(&self) -> Pgs {
+        Pgs {
+            segments: self.segments.iter().map(ArenaSegment::to_segment).collect(),
+        }
+    }
+}
+
+impl ArenaSegment<'_> {
+    /// Copies the segment onto the heap.
+    pub fn to_segment(&self) -> Segment {
+        let contents = match &self.contents {
+            ArenaSegmentContents::PresentationComposition(composition) => {
+                SegmentContents::PresentationComposition(PresentationComposition {
+                    width: composition.width,
+                    height: composition.height,
+                    frame_rate: composition.frame_rate,
+                    composition_number: composition.composition_number,
+                    composition_state: composition.composition_state,
+                    raw_composition_state: composition.raw_composition_state,
+                    palette_update: composition.palette_update,
+                    raw_palette_update_flag: composition.raw_palette_update_flag,
+                    palette_id: composition.palette_id,
+                    composition_objects: composition.composition_objects.to_vec(),
+                })
+            }
+            ArenaSegmentContents::WindowDefinition(windows) => {
+                SegmentContents::WindowDefinition(WindowDefinition {
+                    windows: windows.to_vec(),
+                })
+            }
+            ArenaSegmentContents::PaletteDefinition(palette) => {
+                SegmentContents::PaletteDefinition(PaletteDefinition {
+                    id: palette.id,
+                    version: palette.version,
+                    entries: palette
+                        .entries
+                        .iter()
+                        .map(|entry| (entry.id, entry.clone()))
+                        .collect::<HashMap<_, _>>(),
+                })
+            }
+            ArenaSegmentContents::ObjectDefinition(object) => {
+                SegmentContents::ObjectDefinition(ObjectDefinition {
+                    id: object.id,
+                    version: object.version,
+                    last_in_sequence: object.last_in_sequence.clone(),
+                    width: object.width,
+                    height: object.height,
+                    data: RunLengthEncodedData(object.data.to_vec()),
+                    raw_data_len: object.raw_data_len,
+                    raw: object.raw.map(|raw| RawObjectData {
+                        bytes: raw.bytes.to_vec(),
+                        fragments: raw.fragments.to_vec(),
+                    }),
+                })
+            }
+            ArenaSegmentContents::End => SegmentContents::End,
+        };
+        Segment {
+            pts: self.pts,
+            dts: self.dts,
+            contents,
+        }
+    }
+}
+
+/// Like [`parse_pgs`](crate::parse_pgs), but allocates the stream in `arena`
+/// so a service parsing many small files can free everything a parse left
+/// behind at once with [`Bump::reset`] instead of fragmenting the heap.
+///
+/// The segments, windows, palette entries, composition objects and object
+/// data, including fragments being assembled, are all allocated in the
+/// arena; parsing allocates nothing on the heap unless it fails. Use
+/// [`ArenaPgs::to_pgs`] to copy the stream out where an owned [`Pgs`] is
+/// needed.
+pub fn parse_pgs_in<'b>(arena: &'b Bump, input: &[u8]) -> PgsResult<ArenaPgs<'b>> {
+    let mut segments = Vec::new_in(arena);
+    let mut pending = Vec::new_in(arena);
+    // As in `parse_pgs`, the whole stream is parsed before a fragment error
+    // is reported, so parse errors take precedence.
+    let mut assembled: PgsResult<()> = Ok(());
+    (|input: &mut &[u8]| -> PResult<()> {
+        loop {
+            let segment = parse_segment_in(arena, input)?;
+            if assembled.is_ok() {
+                assembled = assemble_segment(arena, &mut segments, &mut pending, segment);
+            }
+            if input.is_empty() {
+                return Ok(());
+            }
+        }
+    })
+    .parse(input)
+    .map_err(segment_error)?;
+    assembled?;
+    if let Some(object_id) = pending.iter().map(|object| object.id).min() {
+        return Err(PgsError::IncompleteObject { object_id });
+    }
+    Ok(ArenaPgs {
+        segments: segments.into_bump_slice(),
+    })
+}
+
+/// An object whose last fragment hasn't been read yet.
+struct PendingObject<'b> {
+    id: u16,
+    /// Index of the segment holding the first fragment.
+    index: usize,
+    bytes: Vec<'b, u8>,
+    fragments: Vec<'b, RawFragment>,
+}
+
+type ArenaSegmentWithFragment<'b, 'i> = (ArenaSegment<'b>, Option<&'i [u8]>, usize);
+
+/// Appends `segment` to `segments`, gathering the data of fragmented objects
+/// into the segment holding their first fragment once the last one is read.
+fn assemble_segment<'b>(
+    arena: &'b Bump,
+    segments: &mut Vec<'b, ArenaSegment<'b>>,
+    pending: &mut Vec<'b, PendingObject<'b>>,
+    (mut segment, fragment, padding): ArenaSegmentWithFragment<'b, '_>,
+) -> PgsResult<()> {
+    match &mut segment.contents {
+        ArenaSegmentContents::ObjectDefinition(object) => {
+            let out_of_order = || PgsError::FragmentOutOfOrder {
+                object_id: object.id,
+                fragment: object.last_in_sequence.clone(),
+            };
+            let position = pending.iter().position(|pending| pending.id == object.id);
+            if object.last_in_sequence.is_first() && position.is_some() {
+                return Err(out_of_order());
+            }
+            let fragment = fragment.unwrap_or_default();
+            let raw_fragment = RawFragment {
+                len: fragment.len(),
+                padding,
+            };
+            match object.last_in_sequence {
+                LastInSequence::First => {
+                    let mut bytes = Vec::new_in(arena);
+                    bytes.extend_from_slice(fragment);
+                    let mut fragments = Vec::new_in(arena);
+                    fragments.push(raw_fragment);
+                    pending.push(PendingObject {
+                        id: object.id,
+                        index: segments.len(),
+                        bytes,
+                        fragments,
+                    });
+                }
+                LastInSequence::Middle => {
+                    let pending = &mut pending[position.ok_or_else(out_of_order)?];
+                    pending.bytes.extend_from_slice(fragment);
+                    pending.fragments.push(raw_fragment);
+                }
+                LastInSequence::Last => {
+                    let mut pending = pending.swap_remove(position.ok_or_else(out_of_order)?);
+                    pending.bytes.extend_from_slice(fragment);
+                    pending.fragments.push(raw_fragment);
+                    let bytes = pending.bytes.into_bump_slice();
+                    // The first fragment holds at least the declared length
+                    // and dimensions preceding the run-length data.
+                    let data =
+                        (|input: &mut &[u8]| parse_runs_in(arena, input)).parse(&bytes[7..])?;
+                    if let ArenaSegmentContents::ObjectDefinition(first) =
+                        &mut segments[pending.index].contents
+                    {
+                        first.data = data;
+                        first.raw_data_len = Some(bytes.len() - 7);
+                        first.raw = Some(ArenaRawObjectData {
+                            bytes,
+                            fragments: pending.fragments.into_bump_slice(),
+                        });
+                    }
+                }
+                LastInSequence::FirstAndLast => {
+                    object.raw = Some(ArenaRawObjectData {
+                        bytes: arena.alloc_slice_copy(fragment),
+                        fragments: arena.alloc_slice_copy(&[raw_fragment]),
+                    });
+                }
+            }
+        }
+        ArenaSegmentContents::End => {
+            if let Some(object_id) = pending.iter().map(|object| object.id).min() {
+                return Err(PgsError::IncompleteObject { object_id });
+            }
+        }
+        _ => {}
+    }
+    segments.push(segment);
+    Ok(())
+}
+
+/// Like winnow's `repeat`, but collects into a slice allocated in `arena`.
+fn repeat_in<'b, 'i, O, P>(
+    arena: &'b Bump,
+    occurrences: impl Into<Range>,
+    parser: P,
+) -> impl Parser<&'i [u8], &'b mut [O], ContextError>
+where
+    O: 'b,
+    P: Parser<&'i [u8], O, ContextError>,
+{
+    repeat(occurrences, parser)
+        .fold(
+            move || Vec::new_in(arena),
+            |mut items, item| {
+                items.push(item);
+                items
+            },
+        )
+        .map(Vec::into_bump_slice_mut)
+}
+
+/// Arena counterpart of the segment parser in [`crate::parse`], returning the
+/// same errors.
+fn parse_segment_in<'b, 'i>(
+    arena: &'b Bump,
+    input: &mut &'i [u8],
+) -> PResult<ArenaSegmentWithFragment<'b, 'i>> {
+    // Verify magic number "PG" is present.
+    be_u16
+        .verify(|&v| v == 0x5047)
+        .context(MAGIC_CONTEXT)
+        .parse_next(input)?;
+    let (pts, dts, (contents, fragment)) = (be_u32, be_u32, |input: &mut &'i [u8]| {
+        parse_segment_contents_in(arena, input)
+    })
+        .context(StrContext::Label("segment"))
+        .parse_next(input)?;
+    let padding = take_while(0.., 0).parse_next(input)?.len();
+    Ok((ArenaSegment { pts, dts, contents }, fragment, padding))
+}
+
+fn parse_segment_contents_in<'b, 'i>(
+    arena: &'b Bump,
+    input: &mut &'i [u8],
+) -> PResult<(ArenaSegmentContents<'b>, Option<&'i [u8]>)> {
+    dispatch! {be_u8;
+        0x14 => length_and_then(be_u16, |input: &mut &'i [u8]| parse_palette_definition_in(arena, input))
+            .map(|v| (ArenaSegmentContents::PaletteDefinition(v), None))
+            .context(StrContext::Label("palette definition segment")),
+        0x15 => length_and_then(be_u16, |input: &mut &'i [u8]| parse_object_definition_in(arena, input))
+            .map(|(v, fragment)| (ArenaSegmentContents::ObjectDefinition(v), fragment))
+            .context(StrContext::Label("object definition segment")),
+        0x16 => length_and_then(be_u16, |input: &mut &'i [u8]| parse_presentation_composition_in(arena, input))
+            .map(|v| (ArenaSegmentContents::PresentationComposition(v), None))
+            .context(StrContext::Label("presentation composition segment")),
+        0x17 => length_and_then(be_u16, |input: &mut &'i [u8]| parse_window_definition_in(arena, input))
+            .map(|v| (ArenaSegmentContents::WindowDefinition(v), None))
+            .context(StrContext::Label("window definition segment")),
+        0x80 => parse_end_of_display_set_segment
+            .map(|_| (ArenaSegmentContents::End, None))
+            .context(StrContext::Label("end of display set segment")),
+        _ => fail::<_, (ArenaSegmentContents<'b>, Option<&[u8]>), _>
+            .context(StrContext::Label("segment type")),
+    }
+    .parse_next(input)
+}
+
+fn parse_palette_definition_in<'b>(
+    arena: &'b Bump,
+    input: &mut &[u8],
+) -> PResult<ArenaPaletteDefinition<'b>> {
+    let (id, version, entries) =
+        (be_u8, be_u8, repeat_in(arena, 0.., parse_palette_entry)).parse_next(input)?;
+    Ok(ArenaPaletteDefinition {
+        id,
+        version,
+        entries,
+    })
+}
+
+fn parse_window_definition_in<'b>(arena: &'b Bump, input: &mut &[u8]) -> PResult<&'b [Window]> {
+    let count = be_u8.parse_next(input)?;
+    Ok(repeat_in(
+        arena,
+        count as usize,
+        parse_window.context(StrContext::Label("window")),
+    )
+    .parse_next(input)?)
+}
+
+fn parse_presentation_composition_in<'b>(
+    arena: &'b Bump,
+    input: &mut &[u8],
+) -> PResult<ArenaPresentationComposition<'b>> {
+    let (
+        width,
+        height,
+        frame_rate,
+        composition_number,
+        raw_composition_state,
+        raw_palette_update_flag,
+        palette_id,
+        count,
+    ) = (be_u16, be_u16, be_u8, be_u16, be_u8, be_u8, be_u8, be_u8).parse_next(input)?;
+    let composition_objects = repeat_in(
+        arena,
+        count as usize,
+        parse_composition_object.context(StrContext::Label("composition object")),
+    )
+    .parse_next(input)?;
+    for (order, composition_object) in composition_objects.iter_mut().enumerate() {
+        composition_object.order = order;
+    }
+    Ok(ArenaPresentationComposition {
+        width,
+        height,
+        frame_rate,
+        composition_number,
+        composition_state: composition_state(raw_composition_state),
+        raw_composition_state,
+        palette_update: raw_palette_update_flag & 0x80 != 0,
+        raw_palette_update_flag,
+        palette_id,
+        composition_objects,
+    })
+}
+
+fn parse_object_definition_in<'b, 'i>(
+    arena: &'b Bump,
+    input: &mut &'i [u8],
+) -> PResult<(ArenaObjectDefinition<'b>, Option<&'i [u8]>)> {
+    let (id, version, last_in_sequence) =
+        (be_u16, be_u8, parse_last_in_sequence).parse_next(input)?;
+    let (width, height, data, raw_data_len, fragment) = match last_in_sequence {
+        LastInSequence::FirstAndLast => {
+            let ((width, height, (data, raw_data)), object_data) = length_and_then(
+                be_u24,
+                (
+                    be_u16,
+                    be_u16,
+                    (|input: &mut &'i [u8]| parse_runs_in(arena, input))
+                        .with_taken()
+                        .context(StrContext::Label("object data")),
+                ),
+            )
+            .with_taken()
+            .parse_next(input)?;
+            (width, height, data, Some(raw_data.len()), Some(object_data))
+        }
+        LastInSequence::First => {
+            // The declared length spans every fragment, so it can't delimit
+            // this one.
+            let ((_, width, height, _), fragment) = (be_u24, be_u16, be_u16, rest)
+                .with_taken()
+                .parse_next(input)?;
+            (width, height, &[][..], None, Some(fragment))
+        }
+        LastInSequence::Middle | LastInSequence::Last => {
+            (0, 0, &[][..], None, Some(rest.parse_next(input)?))
+        }
+    };
+    Ok((
+        ArenaObjectDefinition {
+            id,
+            version,
+            last_in_sequence,
+            width,
+            height,
+            data,
+            raw_data_len,
+            raw: None,
+        },
+        fragment,
+    ))
+}
+
+fn parse_runs_in<'b>(arena: &'b Bump, input: &mut &[u8]) -> PResult<&'b [RlEncodedPixels]> {
+    Ok(repeat_in(arena, 0.., parse_single_encoded_pixel).parse_next(input)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::fixtures::{PALETTE, cue, end, object_fragments, pcs, pds, rle, wds};
+    use crate::parse::parse_pgs;
+    use crate::render::{get_display_sets, render_display_set};
+
+    /// Counts heap allocations made on the current thread while
+    /// [`heap_allocations`] runs.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get().map(|n| n + 1)));
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn heap_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        ALLOCATIONS.with(|count| count.set(Some(0)));
+        let value = f();
+        let count = ALLOCATIONS.with(|count| count.take());
+        (value, count.unwrap())
+    }
+
+    /// A display set whose 4x1 object is split across three fragments, or
+    /// whose last fragment is lost.
+    fn fragmented_display_set(with_last_fragment: bool) -> std::vec::Vec<u8> {
+        let data = rle(4, &[1, 3, 3, 1]);
+        let mut fragments = object_fragments(0, 0, (4, 1), &data, &[7, 9]);
+        if !with_last_fragment {
+            // The last fragment carries the object data after its 9th byte.
+            fragments.truncate(fragments.len() - (13 + 4 + (7 + data.len() - 9)));
+        }
+        [
+            pcs(0, (8, 4), 0, 0x80, 0, &[(0, 0, 2, 1)]),
+            wds(0, &[(0, 2, 1, 4, 1)]),
+            pds(0, 0, &PALETTE),
+            fragments,
+            end(0),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn stream_parsed_into_an_arena_renders() {
+        let bytes = cue(0, (8, 4), (2, 1), 2, &[1, 3, 3, 1]);
+        let arena = Bump::new();
+        let pgs = parse_pgs_in(&arena, &bytes).unwrap().to_pgs();
+
+        let display_set = get_display_sets(&pgs).next().unwrap();
+        let rgba = render_display_set(&display_set).unwrap();
+        let pixel = |x: usize, y: usize| &rgba[(y * 8 + x) * 4..(y * 8 + x + 1) * 4];
+        assert_eq!(pixel(2, 1), [255, 255, 255, 255]);
+        assert_eq!(pixel(3, 1), [0, 0, 0, 255]);
+        assert_eq!(pixel(0, 0)[3], 0);
+    }
+
+    #[test]
+    fn arena_parse_copies_out_to_the_same_stream_as_parse_pgs() {
+        let mut bytes = fragmented_display_set(true);
+        let arena = Bump::new();
+        let in_arena = parse_pgs_in(&arena, &bytes).unwrap().to_pgs();
+        assert_eq!(in_arena, parse_pgs(&mut bytes).unwrap());
+    }
+
+    #[test]
+    fn arena_parse_does_not_touch_the_heap() {
+        let mut bytes = fragmented_display_set(true);
+        let mut arena = Bump::with_capacity(64 * 1024);
+
+        let (payloads, allocations) = heap_allocations(|| {
+            let pgs = parse_pgs_in(&arena, &bytes).unwrap();
+            let object = pgs
+                .segments
+                .iter()
+                .find_map(|segment| match &segment.contents {
+                    ArenaSegmentContents::ObjectDefinition(object) => Some(object.clone()),
+                    _ => None,
+                })
+                .unwrap();
+            assert_eq!(object.data.len(), 4);
+            let raw = object.raw.unwrap();
+            [
+                pgs.segments.as_ptr() as usize,
+                object.data.as_ptr() as usize,
+                raw.bytes.as_ptr() as usize,
+            ]
+        });
+        assert_eq!(allocations, 0);
+        assert!(heap_allocations(|| parse_pgs(&mut bytes).unwrap()).1 > 0);
+
+        let chunks: std::vec::Vec<_> = arena
+            .iter_allocated_chunks()
+            .map(|chunk| chunk.as_ptr_range())
+            .collect();
+        for payload in payloads {
+            assert!(
+                chunks
+                    .iter()
+                    .any(|chunk| (chunk.start as usize..chunk.end as usize).contains(&payload))
+            );
+        }
+    }
+
+    #[test]
+    fn arena_parse_reports_errors_like_parse_pgs() {
+        let mut bytes = [cue(0, (8, 4), (2, 1), 2, &[1, 3, 3, 1]), end(0)].concat();
+        let offset = bytes.len() - 13;
+        bytes[offset] = b'X';
+        let arena = Bump::new();
+        assert!(matches!(
+            parse_pgs_in(&arena, &bytes),
+            Err(PgsError::InvalidMagic { offset: found, .. }) if found == offset
+        ));
+
+        let mut truncated = fragmented_display_set(false);
+        let arena = Bump::new();
+        assert!(matches!(
+            parse_pgs_in(&arena, &truncated),
+            Err(PgsError::IncompleteObject { object_id: 0 })
+        ));
+        assert!(matches!(
+            parse_pgs(&mut truncated),
+            Err(PgsError::IncompleteObject { object_id: 0 })
+        ));
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use struple::Struple;
+use winnow::Result as PResult;
+use winnow::binary::{be_u8, be_u16, be_u32, length_repeat};
+use winnow::combinator::{dispatch, empty, fail};
+use winnow::prelude::*;
+use winnow::token::take;
+
+use crate::{
+    error::{PgsError, PgsResult},
+    parse::{
+        CompositionObject, CompositionState, CropInfo, LastInSequence, ObjectDefinition,
+        PaletteDefinition, PaletteEntry, Pgs, PresentationComposition, RawFragment, RawObjectData,
+        RlEncodedPixels, RunLengthEncodedData, Segment, SegmentContents, Window, WindowDefinition,
+    },
+};
+
+const CACHE_MAGIC: &[u8; 4] = b"PGSC";
+
+/// Bumped whenever the layout of the cache changes, so caches written by an
+/// older version are rejected rather than misread.
+pub const CACHE_VERSION: u16 = 5;
+
+impl Pgs {
+    /// Serializes the parsed stream into a compact binary cache. Run-length
+    /// data is stored decoded, so [`Pgs::from_cache`] can skip the bit-level
+    /// parsing done by [`crate::parse_pgs`].
+    pub fn to_cache(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(CACHE_MAGIC);
+        out.extend_from_slice(&CACHE_VERSION.to_be_bytes());
+        out.extend_from_slice(&(self.segments.len() as u32).to_be_bytes());
+        for segment in &self.segments {
+            write_segment(&mut out, segment);
+        }
+        out
+    }
+
+    /// Loads a stream written by [`Pgs::to_cache`]. Caches written with a
+    /// different [`CACHE_VERSION`] are rejected.
+    pub fn from_cache(bytes: &[u8]) -> PgsResult<Pgs> {
+        let Some(input) = bytes.strip_prefix(CACHE_MAGIC) else {
+            return Err(PgsError::ParseError("not a PGS cache".to_string()));
+        };
+        let Some((version, input)) = input.split_first_chunk::<2>() else {
+            return Err(PgsError::ParseError("truncated PGS cache".to_string()));
+        };
+        let found = u16::from_be_bytes(*version);
+        if found != CACHE_VERSION {
+            return Err(PgsError::CacheVersionMismatch {
+                found,
+                expected: CACHE_VERSION,
+            });
+        }
+        let segments = length_repeat(be_u32, read_segment)
+            .parse(input)
+            .map_err(|e| PgsError::ParseError(format!("invalid cache: {e}")))?;
+        Ok(Pgs { segments })
+    }
+}
+
+fn write_segment(out: &mut Vec<u8>, segment: &Segment) {
+    out.extend_from_slice(&segment.pts.to_be_bytes());
+    out.extend_from_slice(&segment.dts.to_be_bytes());
+    match &segment.contents {
+        SegmentContents::PresentationComposition(composition) => {
+            out.push(0x16);
+            out.extend_from_slice(&composition.width.to_be_bytes());
+            out.extend_from_slice(&composition.height.to_be_bytes());
+            out.push(composition.frame_rate);
+            out.extend_from_slice(&composition.composition_number.to_be_bytes());
+            out.push(match composition.composition_state {
+                CompositionState::Normal => 0,
+                CompositionState::AcquisitionPoint => 1,
+                CompositionState::EpochStart => 2,
+            });
+            out.push(composition.raw_composition_state);
+            out.push(composition.palette_update as u8);
+            out.push(composition.raw_palette_update_flag);
+            out.push(composition.palette_id);
+            out.push(composition.composition_objects.len() as u8);
+            for composition_object in &composition.composition_objects {
+                out.extend_from_slice(&composition_object.id.to_be_bytes());
+                out.push(composition_object.window_id);
+                out.extend_from_slice(&composition_object.horizontal_position.to_be_bytes());
+                out.extend_from_slice(&composition_object.vertical_position.to_be_bytes());
+                out.push(composition_object.forced as u8);
+                out.push(composition_object.raw_flags);
+                match &composition_object.cropped {
+                    Some(crop) => {
+                        out.push(1);
+                        for value in [
+                            crop.horizontal_position,
+                            crop.vertical_position,
+                            crop.width,
+                            crop.height,
+                        ] {
+                            out.extend_from_slice(&value.to_be_bytes());
+                        }
+                    }
+                    None => out.push(0),
+                }
+            }
+        }
+        SegmentContents::WindowDefinition(window_definition) => {
+            out.push(0x17);
+            out.push(window_definition.windows.len() as u8);
+            for window in &window_definition.windows {
+                out.push(window.id);
+                for value in [
+                    window.horizontal_position,
+                    window.vertical_position,
+                    window.width,
+                    window.height,
+                ] {
+                    out.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+        SegmentContents::PaletteDefinition(palette_definition) => {
+            out.push(0x14);
+            out.push(palette_definition.id);
+            out.push(palette_definition.version);
+            let mut entries: Vec<&PaletteEntry> = palette_definition.entries.values().collect();
+            entries.sort_by_key(|entry| entry.id);
+            out.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+            for entry in entries {
+                out.extend_from_slice(&[
+                    entry.id,
+                    entry.luminance,
+                    entry.color_difference_red,
+                    entry.color_difference_blue,
+                    entry.alpha,
+                ]);
+            }
+        }
+        SegmentContents::ObjectDefinition(object) => {
+            out.push(0x15);
+            out.extend_from_slice(&object.id.to_be_bytes());
+            out.push(object.version);
+            out.push(match object.last_in_sequence {
+                LastInSequence::Middle => 0x00,
+                LastInSequence::Last => 0x40,
+                LastInSequence::First => 0x80,
+                LastInSequence::FirstAndLast => 0xC0,
+            });
+            out.extend_from_slice(&object.width.to_be_bytes());
+            out.extend_from_slice(&object.height.to_be_bytes());
+            // Run-length data never reaches 4GiB, so the maximum marks `None`.
+            let raw_data_len = object.raw_data_len.map_or(u32::MAX, |len| len as u32);
+            out.extend_from_slice(&raw_data_len.to_be_bytes());
+            out.extend_from_slice(&(object.data.0.len() as u32).to_be_bytes());
+            for run in &object.data.0 {
+                out.extend_from_slice(&run.count.to_be_bytes());
+                out.push(run.color);
+            }
+            // As with the data length, the maximum marks `None`.
+            match &object.raw {
+                Some(raw) => {
+                    out.extend_from_slice(&(raw.bytes.len() as u32).to_be_bytes());
+                    out.extend_from_slice(&raw.bytes);
+                    out.extend_from_slice(&(raw.fragments.len() as u32).to_be_bytes());
+                    for fragment in &raw.fragments {
+                        out.extend_from_slice(&(fragment.len as u32).to_be_bytes());
+                        out.extend_from_slice(&(fragment.padding as u32).to_be_bytes());
+                    }
+                }
+                None => out.extend_from_slice(&u32::MAX.to_be_bytes()),
+            }
+        }
+        SegmentContents::End => out.push(0x80),
+    }
+}
+
+fn read_segment(input: &mut &[u8]) -> PResult<Segment> {
+    let (pts, dts) = (be_u32, be_u32).parse_next(input)?;
+    let contents = dispatch! {be_u8;
+        0x14 => read_palette_definition.map(SegmentContents::PaletteDefinition),
+        0x15 => read_object_definition.map(SegmentContents::ObjectDefinition),
+        0x16 => read_presentation_composition.map(SegmentContents::PresentationComposition),
+        0x17 => length_repeat(be_u8, read_window)
+            .map(|windows| SegmentContents::WindowDefinition(WindowDefinition { windows })),
+        0x80 => empty.map(|()| SegmentContents::End),
+        _ => fail::<_, SegmentContents, _>,
+    }
+    .parse_next(input)?;
+    Ok(Segment { pts, dts, contents })
+}
+
+fn read_presentation_composition(input: &mut &[u8]) -> PResult<PresentationComposition> {
+    let (width, height, frame_rate, composition_number) =
+        (be_u16, be_u16, be_u8, be_u16).parse_next(input)?;
+    let composition_state = dispatch! {be_u8;
+        0 => empty.value(CompositionState::Normal),
+        1 => empty.value(CompositionState::AcquisitionPoint),
+        2 => empty.value(CompositionState::EpochStart),
+        _ => fail::<_, CompositionState, _>,
+    }
+    .parse_next(input)?;
+    let (
+        raw_composition_state,
+        palette_update,
+        raw_palette_update_flag,
+        palette_id,
+        composition_objects,
+    ) = (
+        be_u8,
+        be_u8.map(|flag| flag != 0),
+        be_u8,
+        be_u8,
+        length_repeat(be_u8, read_composition_object),
+    )
+        .parse_next(input)?;
+    let mut composition = PresentationComposition {
+        width,
+        height,
+        frame_rate,
+        composition_number,
+        composition_state,
+        raw_composition_state,
+        palette_update,
+        raw_palette_update_flag,
+        palette_id,
+        composition_objects,
+    };
+    for (order, composition_object) in composition.composition_objects.iter_mut().enumerate() {
+        composition_object.order = order;
+    }
+    Ok(composition)
+}
+
+fn read_composition_object(input: &mut &[u8]) -> PResult<CompositionObject> {
+    let (id, window_id, horizontal_position, vertical_position, forced, raw_flags, cropped) =
+        (be_u16, be_u8, be_u16, be_u16, be_u8, be_u8, be_u8).parse_next(input)?;
+    let cropped = match cropped {
+        0 => None,
+        _ => Some(CropInfo::from_tuple(
+            (be_u16, be_u16, be_u16, be_u16).parse_next(input)?,
+        )),
+    };
+    Ok(CompositionObject {
+        id,
+        window_id,
+        horizontal_position,
+        vertical_position,
+        forced: forced != 0,
+        cropped,
+        raw_flags,
+        order: 0,
+    })
+}
+
+fn read_window(input: &mut &[u8]) -> PResult<Window> {
+    Ok(Window::from_tuple(
+        (be_u8, be_u16, be_u16, be_u16, be_u16).parse_next(input)?,
+    ))
+}
+
+fn read_palette_definition(input: &mut &[u8]) -> PResult<PaletteDefinition> {
+    let (id, version, entries) = (
+        be_u8,
+        be_u8,
+        length_repeat(be_u16, (be_u8, be_u8, be_u8, be_u8, be_u8)),
+    )
+        .parse_next(input)?;
+    let entries: Vec<(u8, u8, u8, u8, u8)> = entries;
+    Ok(PaletteDefinition {
+        id,
+        version,
+        entries: entries
+            .into_iter()
+            .map(|entry| (entry.0, PaletteEntry::from_tuple(entry)))
+            .collect::<HashMap<_, _>>(),
+    })
+}
+
+fn read_object_definition(input: &mut &[u8]) -> PResult<ObjectDefinition> {
+    let (id, version, last_in_sequence, width, height, raw_data_len) =
+        (be_u16, be_u8, be_u8, be_u16, be_u16, be_u32).parse_next(input)?;
+    let last_in_sequence = match last_in_sequence {
+        0x00 => LastInSequence::Middle,
+        0x40 => LastInSequence::Last,
+        0x80 => LastInSequence::First,
+        _ => LastInSequence::FirstAndLast,
+    };
+    let data = length_repeat(
+        be_u32,
+        (be_u16, be_u8).map(|(count, color)| RlEncodedPixels { count, color }),
+    )
+    .parse_next(input)?;
+    let raw = match be_u32.parse_next(input)? {
+        u32::MAX => None,
+        len => Some(RawObjectData {
+            bytes: take(len).parse_next(input)?.to_vec(),
+            fragments: length_repeat(
+                be_u32,
+                (be_u32, be_u32).map(|(len, padding)| RawFragment {
+                    len: len as usize,
+                    padding: padding as usize,
+                }),
+            )
+            .parse_next(input)?,
+        }),
+    };
+    Ok(ObjectDefinition {
+        id,
+        version,
+        last_in_sequence,
+        width,
+        height,
+        data: RunLengthEncodedData(data),
+        raw_data_len: (raw_data_len != u32::MAX).then_some(raw_data_len as usize),
+        raw,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        PALETTE, clear, composition_object, cue, end, object_fragments, pcs_with_objects, pds, rle,
+        wds,
+    };
+    use crate::parse_pgs;
+
+    #[test]
+    fn cache_round_trips_a_stream() {
+        let mut data = [
+            pcs_with_objects(
+                0,
+                (64, 32),
+                0,
+                0x80,
+                0,
+                &[
+                    composition_object(0, 0, (4, 4), 0x40, Some((1, 0, 2, 2))),
+                    composition_object(1, 0, (12, 4), 0x80, None),
+                ],
+            ),
+            wds(0, &[(0, 4, 4, 16, 4)]),
+            pds(0, 0, &PALETTE),
+            object_fragments(0, 0, (4, 2), &rle(4, &[1, 2, 3, 1, 3, 3, 2, 1]), &[9]),
+            object_fragments(0, 1, (2, 2), &rle(2, &[1; 4]), &[]),
+            end(0),
+            cue(90_000, (64, 32), (0, 0), 2, &[3; 4]),
+            clear(180_000, (64, 32)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        assert_eq!(Pgs::from_cache(&pgs.to_cache()).unwrap(), pgs);
+    }
+
+    #[test]
+    fn cache_of_another_version_is_rejected() {
+        let mut data = cue(0, (64, 32), (0, 0), 2, &[1; 4]);
+        let mut cache = parse_pgs(&mut data).unwrap().to_cache();
+        cache[4..6].copy_from_slice(&(CACHE_VERSION - 1).to_be_bytes());
+
+        assert!(matches!(
+            Pgs::from_cache(&cache),
+            Err(PgsError::CacheVersionMismatch {
+                found,
+                expected: CACHE_VERSION,
+            }) if found == CACHE_VERSION - 1
+        ));
+        assert!(matches!(
+            Pgs::from_cache(b"PGSX\0\x03"),
+            Err(PgsError::ParseError(_))
+        ));
+    }
+}
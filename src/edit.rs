@@ -0,0 +1,444 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+
+use crate::parse::{CompositionState, Pgs, Segment, SegmentContents, Window, WindowDefinition};
+
+impl Pgs {
+    /// Ranges of segment indices making up each epoch, split at every
+    /// presentation composition starting a new epoch.
+    fn epochs(&self) -> Vec<Range<usize>> {
+        let mut starts: Vec<usize> = self
+            .segments
+            .iter()
+            .enumerate()
+            .filter(|(_, segment)| {
+                matches!(
+                    &segment.contents,
+                    SegmentContents::PresentationComposition(composition)
+                        if composition.composition_state == CompositionState::EpochStart
+                )
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if starts.first() != Some(&0) {
+            starts.insert(0, 0);
+        }
+        starts.push(self.segments.len());
+        starts
+            .windows(2)
+            .map(|bounds| bounds[0]..bounds[1])
+            .filter(|range| !range.is_empty())
+            .collect()
+    }
+
+    /// Collapses objects whose dimensions and run-length data are identical
+    /// to an earlier object of the same epoch onto that object's id, dropping
+    /// the duplicate definitions and rewriting the composition objects that
+    /// referred to them. Only objects defined exactly once in their epoch are
+    /// considered, since a redefinition could change what an id refers to
+    /// part way through. Returns the number of objects removed.
+    pub fn deduplicate_objects(&mut self) -> usize {
+        let mut removed = 0;
+        let mut dropped = vec![false; self.segments.len()];
+        for epoch in self.epochs() {
+            // Segment index of the single definition of each object id, or
+            // `None` if the id is defined more than once.
+            let mut definitions: HashMap<u16, Option<usize>> = HashMap::new();
+            let mut order = Vec::new();
+            for index in epoch.clone() {
+                if let SegmentContents::ObjectDefinition(object) = &self.segments[index].contents
+                    && object.last_in_sequence.is_first()
+                {
+                    definitions
+                        .entry(object.id)
+                        .and_modify(|definition| *definition = None)
+                        .or_insert_with(|| {
+                            order.push(object.id);
+                            Some(index)
+                        });
+                }
+            }
+
+            let mut canonical: Vec<usize> = Vec::new();
+            let mut remap: HashMap<u16, u16> = HashMap::new();
+            for id in order {
+                let Some(Some(index)) = definitions.get(&id) else {
+                    continue;
+                };
+                let SegmentContents::ObjectDefinition(object) = &self.segments[*index].contents
+                else {
+                    continue;
+                };
+                let duplicate_of = canonical.iter().find_map(|other| {
+                    let SegmentContents::ObjectDefinition(other) = &self.segments[*other].contents
+                    else {
+                        return None;
+                    };
+                    (other.width == object.width
+                        && other.height == object.height
+                        && other.data == object.data)
+                        .then_some(other.id)
+                });
+                match duplicate_of {
+                    Some(other) => {
+                        remap.insert(id, other);
+                    }
+                    None => canonical.push(*index),
+                }
+            }
+            if remap.is_empty() {
+                continue;
+            }
+            removed += remap.len();
+
+            for index in epoch {
+                match &mut self.segments[index].contents {
+                    SegmentContents::ObjectDefinition(object) if remap.contains_key(&object.id) => {
+                        dropped[index] = true;
+                    }
+                    SegmentContents::PresentationComposition(composition) => {
+                        for composition_object in &mut composition.composition_objects {
+                            if let Some(id) = remap.get(&composition_object.id) {
+                                composition_object.id = *id;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut dropped = dropped.into_iter();
+        self.segments
+            .retain(|_| !dropped.next().unwrap_or_default());
+        removed
+    }
+
+    /// Replaces each group of windows whose rectangles overlap or share an
+    /// edge with a single window covering the group's bounding box, keeping
+    /// the lowest id of the group, and rewrites the composition objects that
+    /// referred to the other ids. Windows touching only at a corner are left
+    /// apart. Returns the number of windows removed.
+    pub fn merge_adjacent_windows(&mut self) -> usize {
+        let mut removed = 0;
+        let mut remap: HashMap<u8, u8> = HashMap::new();
+        // The composition of the current display set, rewritten once the
+        // window definitions following it have been seen.
+        let mut pending = None;
+        for index in 0..self.segments.len() {
+            match &mut self.segments[index].contents {
+                SegmentContents::PresentationComposition(composition) => {
+                    let epoch_start = composition.composition_state == CompositionState::EpochStart;
+                    if let Some(previous) = pending.replace(index) {
+                        self.remap_window_ids(previous, &remap);
+                    }
+                    if epoch_start {
+                        remap.clear();
+                    }
+                }
+                SegmentContents::WindowDefinition(definition) => {
+                    let (windows, merged) = merge_windows(&definition.windows);
+                    removed += definition.windows.len() - windows.len();
+                    definition.windows = windows;
+                    remap = merged;
+                }
+                SegmentContents::End => {
+                    if let Some(previous) = pending.take() {
+                        self.remap_window_ids(previous, &remap);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(previous) = pending {
+            self.remap_window_ids(previous, &remap);
+        }
+        removed
+    }
+
+    fn remap_window_ids(&mut self, index: usize, remap: &HashMap<u8, u8>) {
+        if let SegmentContents::PresentationComposition(composition) =
+            &mut self.segments[index].contents
+        {
+            for composition_object in &mut composition.composition_objects {
+                if let Some(id) = remap.get(&composition_object.window_id) {
+                    composition_object.window_id = *id;
+                }
+            }
+        }
+    }
+
+    /// Splits the stream into one stream per window that composition objects
+    /// are shown in, keyed by window id. Each stream keeps every display set
+    /// with its timing, so cues are cleared at the same times as in the
+    /// original, but only carries the composition objects shown in its
+    /// window, that window's definition and the objects those compositions
+    /// refer to within each epoch. Palettes are kept as is.
+    pub fn split_by_window(&self) -> HashMap<u8, Pgs> {
+        let window_ids: BTreeSet<u8> = self
+            .segments
+            .iter()
+            .filter_map(|segment| match &segment.contents {
+                SegmentContents::PresentationComposition(composition) => {
+                    Some(composition.composition_objects.iter())
+                }
+                _ => None,
+            })
+            .flatten()
+            .map(|composition_object| composition_object.window_id)
+            .collect();
+        let epochs = self.epochs();
+
+        let mut streams = HashMap::new();
+        for window_id in window_ids {
+            let mut segments = Vec::new();
+            for epoch in &epochs {
+                let epoch = &self.segments[epoch.clone()];
+                let object_ids: HashSet<u16> = epoch
+                    .iter()
+                    .filter_map(|segment| match &segment.contents {
+                        SegmentContents::PresentationComposition(composition) => {
+                            Some(composition.composition_objects.iter())
+                        }
+                        _ => None,
+                    })
+                    .flatten()
+                    .filter(|composition_object| composition_object.window_id == window_id)
+                    .map(|composition_object| composition_object.id)
+                    .collect();
+                for segment in epoch {
+                    let contents = match &segment.contents {
+                        SegmentContents::PresentationComposition(composition) => {
+                            let mut composition = composition.clone();
+                            composition
+                                .composition_objects
+                                .retain(|composition_object| {
+                                    composition_object.window_id == window_id
+                                });
+                            SegmentContents::PresentationComposition(composition)
+                        }
+                        SegmentContents::WindowDefinition(window_definition) => {
+                            SegmentContents::WindowDefinition(WindowDefinition {
+                                windows: window_definition
+                                    .windows
+                                    .iter()
+                                    .filter(|window| window.id == window_id)
+                                    .cloned()
+                                    .collect(),
+                            })
+                        }
+                        SegmentContents::ObjectDefinition(object)
+                            if !object_ids.contains(&object.id) =>
+                        {
+                            continue;
+                        }
+                        contents => contents.clone(),
+                    };
+                    segments.push(Segment {
+                        pts: segment.pts,
+                        dts: segment.dts,
+                        contents,
+                    });
+                }
+            }
+            streams.insert(window_id, Pgs { segments });
+        }
+        streams
+    }
+}
+
+/// Merges touching windows until none are left, returning the remaining
+/// windows in their original order along with the id each removed window
+/// was merged into.
+fn merge_windows(windows: &[Window]) -> (Vec<Window>, HashMap<u8, u8>) {
+    let mut windows = windows.to_vec();
+    let mut merged = HashMap::new();
+    'search: loop {
+        for first in 0..windows.len() {
+            for second in first + 1..windows.len() {
+                if !windows_touch(&windows[first], &windows[second]) {
+                    continue;
+                }
+                let other = windows.remove(second);
+                let window = &mut windows[first];
+                let left = window.horizontal_position.min(other.horizontal_position);
+                let top = window.vertical_position.min(other.vertical_position);
+                let right = window_end(window).0.max(window_end(&other).0);
+                let bottom = window_end(window).1.max(window_end(&other).1);
+                let (kept, dropped) = (window.id.min(other.id), window.id.max(other.id));
+                *window = Window {
+                    id: kept,
+                    horizontal_position: left,
+                    vertical_position: top,
+                    width: (right - left as u32).min(u16::MAX as u32) as u16,
+                    height: (bottom - top as u32).min(u16::MAX as u32) as u16,
+                };
+                for target in merged.values_mut() {
+                    if *target == dropped {
+                        *target = kept;
+                    }
+                }
+                merged.insert(dropped, kept);
+                continue 'search;
+            }
+        }
+        return (windows, merged);
+    }
+}
+
+/// The exclusive right and bottom edges of the window.
+fn window_end(window: &Window) -> (u32, u32) {
+    (
+        window.horizontal_position as u32 + window.width as u32,
+        window.vertical_position as u32 + window.height as u32,
+    )
+}
+
+/// Whether the windows overlap or share part of an edge.
+fn windows_touch(a: &Window, b: &Window) -> bool {
+    // Length of the overlap of two spans, negative for the gap between them.
+    let overlap = |start_a: u16, end_a: u32, start_b: u16, end_b: u32| {
+        end_a.min(end_b) as i64 - (start_a.max(start_b) as i64)
+    };
+    let (a_right, a_bottom) = window_end(a);
+    let (b_right, b_bottom) = window_end(b);
+    let horizontal = overlap(
+        a.horizontal_position,
+        a_right,
+        b.horizontal_position,
+        b_right,
+    );
+    let vertical = overlap(a.vertical_position, a_bottom, b.vertical_position, b_bottom);
+    horizontal >= 0 && vertical >= 0 && (horizontal > 0 || vertical > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{PALETTE, clear, end, ods, pcs, pds, wds};
+    use crate::parse_pgs;
+    use crate::render::{DisplaySetIterator, render_display_set};
+
+    fn object_ids(pgs: &Pgs) -> Vec<u16> {
+        pgs.segments
+            .iter()
+            .filter_map(|segment| match &segment.contents {
+                SegmentContents::ObjectDefinition(object) => Some(object.id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_objects_collapse_to_one() {
+        let glyph = [1, 3, 3, 1];
+        let mut data = [
+            pcs(
+                0,
+                (16, 4),
+                0,
+                0x80,
+                0,
+                &[(0, 0, 0, 0), (1, 0, 4, 0), (2, 0, 8, 0)],
+            ),
+            wds(0, &[(0, 0, 0, 16, 4)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 2, &glyph),
+            ods(0, 1, 2, &glyph),
+            ods(0, 2, 2, &[3, 1, 1, 3]),
+            end(0),
+        ]
+        .concat();
+        let mut pgs = parse_pgs(&mut data).unwrap();
+        let before = render_display_set(&DisplaySetIterator::new(&pgs).next().unwrap()).unwrap();
+
+        assert_eq!(pgs.deduplicate_objects(), 1);
+
+        assert_eq!(object_ids(&pgs), [0, 2]);
+        let SegmentContents::PresentationComposition(composition) = &pgs.segments[0].contents
+        else {
+            panic!("expected a presentation composition");
+        };
+        let references: Vec<u16> = composition
+            .composition_objects
+            .iter()
+            .map(|composition_object| composition_object.id)
+            .collect();
+        assert_eq!(references, [0, 0, 2]);
+        let after = render_display_set(&DisplaySetIterator::new(&pgs).next().unwrap()).unwrap();
+        assert_eq!(after, before);
+        assert_eq!(pgs.deduplicate_objects(), 0);
+    }
+
+    #[test]
+    fn objects_in_two_windows_split_into_two_streams() {
+        let mut data = [
+            pcs(0, (4, 4), 0, 0x80, 0, &[(0, 0, 0, 0), (1, 1, 2, 3)]),
+            wds(0, &[(0, 0, 0, 4, 1), (1, 2, 3, 2, 1)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 4, &[1; 4]),
+            ods(0, 1, 2, &[3; 2]),
+            end(0),
+            clear(9_000, (4, 4)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let rendered = |pgs: &Pgs| {
+            let display_sets: Vec<_> = DisplaySetIterator::new(pgs).collect();
+            assert_eq!(display_sets.len(), 2);
+            assert!(display_sets[1].is_empty());
+            render_display_set(&display_sets[0]).unwrap()
+        };
+        let original = rendered(&pgs);
+
+        let streams = pgs.split_by_window();
+        let mut window_ids: Vec<u8> = streams.keys().copied().collect();
+        window_ids.sort_unstable();
+        assert_eq!(window_ids, [0, 1]);
+        assert_eq!(object_ids(&streams[&0]), [0]);
+        assert_eq!(object_ids(&streams[&1]), [1]);
+        // Each stream renders its own window's rows of the original frame.
+        let (top, bottom) = (rendered(&streams[&0]), rendered(&streams[&1]));
+        let transparent = |rgba: &[u8]| rgba.chunks_exact(4).all(|pixel| pixel[3] == 0);
+        let row = 4 * 4;
+        assert_eq!(top[..row], original[..row]);
+        assert!(transparent(&top[row..]));
+        assert!(transparent(&bottom[..3 * row]));
+        assert_eq!(bottom[3 * row..], original[3 * row..]);
+    }
+
+    #[test]
+    fn side_by_side_windows_merge_into_one() {
+        let mut data = [
+            pcs(0, (16, 4), 0, 0x80, 0, &[(0, 0, 2, 1), (1, 1, 6, 1)]),
+            wds(0, &[(0, 2, 1, 4, 2), (1, 6, 1, 4, 2)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 4, &[1; 8]),
+            ods(0, 1, 4, &[3; 8]),
+            end(0),
+        ]
+        .concat();
+        let mut pgs = parse_pgs(&mut data).unwrap();
+        let before = render_display_set(&DisplaySetIterator::new(&pgs).next().unwrap()).unwrap();
+
+        assert_eq!(pgs.merge_adjacent_windows(), 1);
+        let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+        let windows: Vec<&Window> = display_set.windows.values().copied().collect();
+        assert_eq!(
+            windows,
+            [&Window {
+                id: 0,
+                horizontal_position: 2,
+                vertical_position: 1,
+                width: 8,
+                height: 2,
+            }]
+        );
+        let window_ids: Vec<u8> = display_set
+            .composition_objects
+            .iter()
+            .map(|composition_object| composition_object.window_id)
+            .collect();
+        assert_eq!(window_ids, [0, 0]);
+        assert_eq!(render_display_set(&display_set).unwrap(), before);
+    }
+}
@@ -0,0 +1,955 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::{PgsError, PgsResult},
+    parse::{
+        CompositionObject, CompositionState, CropInfo, LastInSequence, ObjectDefinition,
+        PaletteDefinition, PaletteEntry, Pgs, PresentationComposition, RawObjectData,
+        RlEncodedPixels, RunLengthEncodedData, Segment, SegmentContents, Window, WindowDefinition,
+        composition_state,
+    },
+    render::DisplaySet,
+};
+
+/// The longest run a single PGS run-length code can express.
+const MAX_RUN_LENGTH: u16 = 0x3FFF;
+
+/// The largest body a segment's 16-bit length field can declare.
+const MAX_SEGMENT_LENGTH: usize = 0xFFFF;
+
+impl ObjectDefinition {
+    /// Decodes the run-length data into a row-major bitmap of palette indices,
+    /// `width * height` bytes long. Runs spilling past the end of a scanline
+    /// are truncated and missing pixels are left as index 0.
+    pub fn decode_indexed(&self) -> Vec<u8> {
+        let width = self.width as usize;
+        let mut indices = vec![0u8; width * self.height as usize];
+        for (line, range) in self
+            .data
+            .line_ranges()
+            .into_iter()
+            .take(self.height as usize)
+            .enumerate()
+        {
+            let row = &mut indices[line * width..(line + 1) * width];
+            let mut column = 0;
+            for run in &self.data.0[range] {
+                let end = (column + run.count as usize).min(width);
+                row[column..end].fill(run.color);
+                column = end;
+            }
+        }
+        indices
+    }
+
+    /// Builds an unfragmented object from a row-major bitmap of palette
+    /// indices, run-length encoding each scanline and terminating it with an
+    /// end-of-line marker. This is the inverse of
+    /// [`ObjectDefinition::decode_indexed`].
+    ///
+    /// Fails if `indices` does not hold exactly `width * height` entries.
+    pub fn from_bitmap(
+        id: u16,
+        version: u8,
+        width: u16,
+        height: u16,
+        indices: &[u8],
+    ) -> PgsResult<Self> {
+        if indices.len() != width as usize * height as usize {
+            return Err(PgsError::BitmapSizeMismatch {
+                object_id: id,
+                len: indices.len(),
+                width,
+                height,
+            });
+        }
+        let mut runs = Vec::new();
+        if width > 0 {
+            for line in indices.chunks(width as usize) {
+                encode_line(line, &mut runs);
+            }
+        }
+        Ok(ObjectDefinition {
+            id,
+            version,
+            last_in_sequence: LastInSequence::FirstAndLast,
+            width,
+            height,
+            data: RunLengthEncodedData(runs),
+            raw_data_len: None,
+            raw: None,
+        })
+    }
+}
+
+/// Builds a stream one display set at a time, numbering compositions in the
+/// order they are added.
+///
+/// Besides the single-object [`PgsBuilder::show_at`], a display set can be
+/// assembled from definitions and composition objects queued with the
+/// `define_*`, [`PgsBuilder::object`] and [`PgsBuilder::object_cropped`]
+/// methods, then added with [`PgsBuilder::present_at`].
+#[derive(Debug)]
+pub struct PgsBuilder {
+    width: u16,
+    height: u16,
+    frame_rate: u8,
+    composition_number: u16,
+    segments: Vec<Segment>,
+    windows: Vec<Window>,
+    palettes: Vec<PaletteDefinition>,
+    objects: Vec<ObjectDefinition>,
+    composition_objects: Vec<CompositionObject>,
+}
+
+impl PgsBuilder {
+    /// Starts an empty stream for a `width` x `height` video, with
+    /// `frame_rate` given as a PCS frame rate code (see
+    /// [`crate::timing::frame_rate_code`]).
+    pub fn new(width: u16, height: u16, frame_rate: u8) -> Self {
+        Self {
+            width,
+            height,
+            frame_rate,
+            composition_number: 0,
+            segments: Vec::new(),
+            windows: Vec::new(),
+            palettes: Vec::new(),
+            objects: Vec::new(),
+            composition_objects: Vec::new(),
+        }
+    }
+
+    /// Adds a display set showing `object` inside `window` with its top-left
+    /// corner at `(x, y)`, drawn with `palette`. Each cue starts a new epoch,
+    /// so it does not depend on anything defined before it.
+    pub fn show_at(
+        &mut self,
+        pts: u32,
+        window: Window,
+        palette: PaletteDefinition,
+        object: ObjectDefinition,
+        x: u16,
+        y: u16,
+    ) -> &mut Self {
+        self.object(object.id, window.id, x, y)
+            .define_window(window)
+            .define_palette(palette)
+            .define_object(object)
+            .present_at(pts)
+    }
+
+    /// Queues a window definition for the next [`PgsBuilder::present_at`].
+    pub fn define_window(&mut self, window: Window) -> &mut Self {
+        self.windows.push(window);
+        self
+    }
+
+    /// Queues a palette definition for the next [`PgsBuilder::present_at`].
+    /// The first palette queued is the one the display set is drawn with.
+    pub fn define_palette(&mut self, palette: PaletteDefinition) -> &mut Self {
+        self.palettes.push(palette);
+        self
+    }
+
+    /// Queues an object definition for the next [`PgsBuilder::present_at`].
+    pub fn define_object(&mut self, object: ObjectDefinition) -> &mut Self {
+        self.objects.push(object);
+        self
+    }
+
+    /// Queues a composition object showing object `id` in window `window_id`
+    /// with its top-left corner at `(x, y)`.
+    pub fn object(&mut self, id: u16, window_id: u8, x: u16, y: u16) -> &mut Self {
+        self.push_object(id, window_id, x, y, None)
+    }
+
+    /// Like [`PgsBuilder::object`], but only shows the part of the object
+    /// inside `crop`.
+    pub fn object_cropped(
+        &mut self,
+        id: u16,
+        window_id: u8,
+        x: u16,
+        y: u16,
+        crop: CropInfo,
+    ) -> &mut Self {
+        self.push_object(id, window_id, x, y, Some(crop))
+    }
+
+    /// Adds a display set at `pts` made of everything queued since the last
+    /// one. It starts a new epoch, so it does not depend on anything defined
+    /// before it.
+    pub fn present_at(&mut self, pts: u32) -> &mut Self {
+        let palette_id = self.palettes.first().map_or(0, |palette| palette.id);
+        let composition_objects = std::mem::take(&mut self.composition_objects);
+        self.push_composition(
+            pts,
+            CompositionState::EpochStart,
+            palette_id,
+            composition_objects,
+        );
+        let mut contents = vec![SegmentContents::WindowDefinition(WindowDefinition {
+            windows: std::mem::take(&mut self.windows),
+        })];
+        contents.extend(
+            self.palettes
+                .drain(..)
+                .map(SegmentContents::PaletteDefinition),
+        );
+        contents.extend(
+            self.objects
+                .drain(..)
+                .map(SegmentContents::ObjectDefinition),
+        );
+        contents.push(SegmentContents::End);
+        for contents in contents {
+            self.segments.push(Segment {
+                pts,
+                dts: pts,
+                contents,
+            });
+        }
+        self
+    }
+
+    /// Adds an empty display set at `pts`, clearing whatever is on screen.
+    pub fn clear_at(&mut self, pts: u32) -> &mut Self {
+        self.push_composition(pts, CompositionState::Normal, 0, Vec::new());
+        self.segments.push(Segment {
+            pts,
+            dts: pts,
+            contents: SegmentContents::End,
+        });
+        self
+    }
+
+    pub fn build(self) -> Pgs {
+        Pgs {
+            segments: self.segments,
+        }
+    }
+
+    fn push_object(
+        &mut self,
+        id: u16,
+        window_id: u8,
+        x: u16,
+        y: u16,
+        cropped: Option<CropInfo>,
+    ) -> &mut Self {
+        let raw_flags = if cropped.is_some() { 0x40 } else { 0 };
+        self.composition_objects.push(CompositionObject {
+            id,
+            window_id,
+            horizontal_position: x,
+            vertical_position: y,
+            forced: false,
+            cropped,
+            raw_flags,
+            order: self.composition_objects.len(),
+        });
+        self
+    }
+
+    fn push_composition(
+        &mut self,
+        pts: u32,
+        composition_state: CompositionState,
+        palette_id: u8,
+        composition_objects: Vec<CompositionObject>,
+    ) {
+        self.segments.push(Segment {
+            pts,
+            dts: pts,
+            contents: SegmentContents::PresentationComposition(PresentationComposition {
+                width: self.width,
+                height: self.height,
+                frame_rate: self.frame_rate,
+                composition_number: self.composition_number,
+                composition_state,
+                raw_composition_state: 0,
+                palette_update: false,
+                raw_palette_update_flag: 0,
+                palette_id,
+                composition_objects,
+            }),
+        });
+        self.composition_number = self.composition_number.wrapping_add(1);
+    }
+}
+
+/// Returns the segments that take a decoder showing `from` to showing `to`,
+/// relying on the window, palette and object definitions `from` left in
+/// place: only windows, palettes and objects that are new or differ from
+/// those of `from` are redefined. The segments form a single display set at
+/// `to`'s timestamps: a presentation composition, followed by any window,
+/// palette and object definitions and an end segment. When only the palette
+/// changed the composition is flagged as a palette update.
+///
+/// A change of frame size starts a new epoch, discarding every definition,
+/// so in that case all of `to`'s definitions are included.
+pub fn diff_to_segments(from: &DisplaySet, to: &DisplaySet) -> Vec<Segment> {
+    let new_epoch = (from.width, from.height) != (to.width, to.height);
+    let mut window_ids: Vec<u8> = to
+        .windows
+        .iter()
+        .filter(|(id, window)| new_epoch || from.windows.get(id) != Some(*window))
+        .map(|(id, _)| *id)
+        .collect();
+    window_ids.sort_unstable();
+    let mut palette_ids: Vec<u8> = to
+        .palettes
+        .iter()
+        .filter(|(id, palette)| new_epoch || from.palettes.get(id) != Some(*palette))
+        .map(|(id, _)| *id)
+        .collect();
+    palette_ids.sort_unstable();
+    let mut object_ids: Vec<u16> = to
+        .objects
+        .iter()
+        .filter(|(id, object)| new_epoch || from.objects.get(id) != Some(*object))
+        .map(|(id, _)| *id)
+        .collect();
+    object_ids.sort_unstable();
+
+    let palette_update = !new_epoch
+        && window_ids.is_empty()
+        && object_ids.is_empty()
+        && !palette_ids.is_empty()
+        && from.composition_objects == to.composition_objects;
+    let mut contents = vec![SegmentContents::PresentationComposition(
+        PresentationComposition {
+            width: to.width,
+            height: to.height,
+            frame_rate: to.frame_rate,
+            composition_number: to.composition_number,
+            composition_state: if new_epoch {
+                CompositionState::EpochStart
+            } else {
+                CompositionState::Normal
+            },
+            raw_composition_state: if new_epoch { 0x80 } else { 0 },
+            palette_update,
+            raw_palette_update_flag: if palette_update { 0x80 } else { 0 },
+            palette_id: to.palette_id,
+            composition_objects: to.composition_objects.to_vec(),
+        },
+    )];
+    if !window_ids.is_empty() {
+        contents.push(SegmentContents::WindowDefinition(WindowDefinition {
+            windows: window_ids.iter().map(|id| to.windows[id].clone()).collect(),
+        }));
+    }
+    contents.extend(
+        palette_ids
+            .iter()
+            .map(|id| SegmentContents::PaletteDefinition(to.palettes[id].clone())),
+    );
+    contents.extend(object_ids.iter().map(|id| {
+        SegmentContents::ObjectDefinition(ObjectDefinition {
+            last_in_sequence: LastInSequence::FirstAndLast,
+            ..to.objects[id].clone()
+        })
+    }));
+    contents.push(SegmentContents::End);
+
+    contents
+        .into_iter()
+        .map(|contents| Segment {
+            pts: to.presentation_timestamp,
+            dts: to.decoding_timestamp,
+            contents,
+        })
+        .collect()
+}
+
+/// Serializes the stream into the PGS (`.sup`) format read by
+/// [`crate::parse_pgs`]. Flag bytes are written exactly as they were read,
+/// including reserved bits, unless the flags they carry have been changed
+/// since. Palette entries are written in id order.
+///
+/// A parsed object that hasn't been edited is written back from its
+/// [`RawObjectData`], keeping its run-length codes, its fragments and the
+/// padding after them, with each fragment taking its timestamps from the
+/// segment it was read from. Other objects are written with the shortest
+/// code for each run and, when too large for a single segment, split into
+/// fragments of the largest size a segment allows. A parsed fragmented
+/// object keeps its data on the first fragment, so segments holding its
+/// remaining fragments only supply their timestamps, and are skipped when
+/// the object is re-encoded.
+pub fn write_pgs(pgs: &Pgs) -> Vec<u8> {
+    let mut out = Vec::new();
+    // Raw fragments of objects written from their raw data that are still
+    // to be written, by object id.
+    let mut pending: HashMap<u16, (&ObjectDefinition, Vec<Vec<u8>>)> = HashMap::new();
+    for segment in &pgs.segments {
+        let mut write = |segment_type: u8, body: &[u8], padding: usize| {
+            out.extend_from_slice(b"PG");
+            out.extend_from_slice(&segment.pts.to_be_bytes());
+            out.extend_from_slice(&segment.dts.to_be_bytes());
+            out.push(segment_type);
+            out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+            out.extend_from_slice(body);
+            out.resize(out.len() + padding, 0);
+        };
+        match &segment.contents {
+            SegmentContents::PresentationComposition(composition) => {
+                write(0x16, &presentation_composition_body(composition), 0);
+            }
+            SegmentContents::WindowDefinition(window_definition) => {
+                let mut body = vec![window_definition.windows.len() as u8];
+                for window in &window_definition.windows {
+                    body.push(window.id);
+                    for value in [
+                        window.horizontal_position,
+                        window.vertical_position,
+                        window.width,
+                        window.height,
+                    ] {
+                        body.extend_from_slice(&value.to_be_bytes());
+                    }
+                }
+                write(0x17, &body, 0);
+            }
+            SegmentContents::PaletteDefinition(palette_definition) => {
+                let mut body = vec![palette_definition.id, palette_definition.version];
+                let mut entries: Vec<&PaletteEntry> = palette_definition.entries.values().collect();
+                entries.sort_by_key(|entry| entry.id);
+                for entry in entries {
+                    body.extend_from_slice(&[
+                        entry.id,
+                        entry.luminance,
+                        entry.color_difference_red,
+                        entry.color_difference_blue,
+                        entry.alpha,
+                    ]);
+                }
+                write(0x14, &body, 0);
+            }
+            SegmentContents::ObjectDefinition(object) => match object.last_in_sequence {
+                LastInSequence::First | LastInSequence::FirstAndLast => {
+                    match object.raw.as_ref().filter(|raw| raw.matches(object)) {
+                        Some(raw) => {
+                            let mut bodies = raw_fragment_bodies(object, raw);
+                            let first = bodies.remove(0);
+                            write(0x15, &first, raw.fragments[0].padding);
+                            if !bodies.is_empty() {
+                                pending.insert(object.id, (object, bodies));
+                            }
+                        }
+                        None => {
+                            for body in object_fragment_bodies(object) {
+                                write(0x15, &body, 0);
+                            }
+                        }
+                    }
+                }
+                LastInSequence::Middle | LastInSequence::Last => {
+                    if let Some((first, bodies)) = pending.get_mut(&object.id) {
+                        let raw = first.raw.as_ref().expect("pending objects are raw");
+                        let index = raw.fragments.len() - bodies.len();
+                        write(0x15, &bodies.remove(0), raw.fragments[index].padding);
+                        if bodies.is_empty() {
+                            pending.remove(&object.id);
+                        }
+                    }
+                }
+            },
+            SegmentContents::End => {
+                // Fragments whose segments have been removed from the stream
+                // are written before the display set ends.
+                let mut ids: Vec<u16> = pending.keys().copied().collect();
+                ids.sort_unstable();
+                for id in ids {
+                    let (first, bodies) = pending.remove(&id).expect("listed id");
+                    let raw = first.raw.as_ref().expect("pending objects are raw");
+                    let start = raw.fragments.len() - bodies.len();
+                    for (body, fragment) in bodies.iter().zip(&raw.fragments[start..]) {
+                        write(0x15, body, fragment.padding);
+                    }
+                }
+                write(0x80, &[], 0);
+            }
+        }
+    }
+    out
+}
+
+fn presentation_composition_body(composition: &PresentationComposition) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&composition.width.to_be_bytes());
+    body.extend_from_slice(&composition.height.to_be_bytes());
+    body.push(composition.frame_rate);
+    body.extend_from_slice(&composition.composition_number.to_be_bytes());
+    body.push(
+        if composition_state(composition.raw_composition_state) == composition.composition_state {
+            composition.raw_composition_state
+        } else {
+            match composition.composition_state {
+                CompositionState::Normal => 0x00,
+                CompositionState::AcquisitionPoint => 0x40,
+                CompositionState::EpochStart => 0x80,
+            }
+        },
+    );
+    body.push(preserve_flags(
+        composition.raw_palette_update_flag,
+        0x80,
+        if composition.palette_update { 0x80 } else { 0 },
+    ));
+    body.push(composition.palette_id);
+    body.push(composition.composition_objects.len() as u8);
+    for composition_object in &composition.composition_objects {
+        body.extend_from_slice(&composition_object.id.to_be_bytes());
+        body.push(composition_object.window_id);
+        body.push(preserve_flags(
+            composition_object.raw_flags,
+            0xC0,
+            if composition_object.forced { 0x80 } else { 0 }
+                | if composition_object.cropped.is_some() {
+                    0x40
+                } else {
+                    0
+                },
+        ));
+        body.extend_from_slice(&composition_object.horizontal_position.to_be_bytes());
+        body.extend_from_slice(&composition_object.vertical_position.to_be_bytes());
+        if let Some(crop) = &composition_object.cropped {
+            for value in [
+                crop.horizontal_position,
+                crop.vertical_position,
+                crop.width,
+                crop.height,
+            ] {
+                body.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+    body
+}
+
+/// Returns `raw` if its bits under `mask` still hold `flags`, or `flags`
+/// alone if they have been changed since `raw` was read.
+fn preserve_flags(raw: u8, mask: u8, flags: u8) -> u8 {
+    if raw & mask == flags { raw } else { flags }
+}
+
+/// Encodes an object's dimensions and run-length data and splits them into
+/// the bodies of as many segments as needed.
+fn object_fragment_bodies(object: &ObjectDefinition) -> Vec<Vec<u8>> {
+    let mut data = Vec::new();
+    for run in &object.data.0 {
+        encode_run(run, &mut data);
+    }
+    let mut object_data = Vec::with_capacity(data.len() + 7);
+    object_data.extend_from_slice(&(data.len() as u32 + 4).to_be_bytes()[1..]);
+    object_data.extend_from_slice(&object.width.to_be_bytes());
+    object_data.extend_from_slice(&object.height.to_be_bytes());
+    object_data.extend_from_slice(&data);
+
+    let chunks: Vec<&[u8]> = object_data.chunks(MAX_SEGMENT_LENGTH - 4).collect();
+    fragment_bodies(object, &chunks)
+}
+
+/// Splits an object's raw data into the bodies of the fragments it was read
+/// from.
+fn raw_fragment_bodies(object: &ObjectDefinition, raw: &RawObjectData) -> Vec<Vec<u8>> {
+    let mut rest = &raw.bytes[..];
+    let chunks: Vec<&[u8]> = raw
+        .fragments
+        .iter()
+        .map(|fragment| {
+            let (chunk, remaining) = rest.split_at(fragment.len);
+            rest = remaining;
+            chunk
+        })
+        .collect();
+    fragment_bodies(object, &chunks)
+}
+
+/// Builds the body of a segment for each chunk of an object's data.
+fn fragment_bodies(object: &ObjectDefinition, chunks: &[&[u8]]) -> Vec<Vec<u8>> {
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut body = Vec::with_capacity(chunk.len() + 4);
+            body.extend_from_slice(&object.id.to_be_bytes());
+            body.push(object.version);
+            body.push(match (index == 0, index == chunks.len() - 1) {
+                (true, true) => 0xC0,
+                (true, false) => 0x80,
+                (false, true) => 0x40,
+                (false, false) => 0x00,
+            });
+            body.extend_from_slice(chunk);
+            body
+        })
+        .collect()
+}
+
+/// Writes the shortest code for a run, as measured by
+/// [`RlEncodedPixels::encoded_len`].
+fn encode_run(run: &RlEncodedPixels, out: &mut Vec<u8>) {
+    let count = run.count.min(MAX_RUN_LENGTH);
+    match (run.color, count) {
+        (0, 0..64) => out.extend_from_slice(&[0, count as u8]),
+        (0, _) => out.extend_from_slice(&[0, 0x40 | (count >> 8) as u8, count as u8]),
+        (color, 1) => out.push(color),
+        (color, 0..64) => out.extend_from_slice(&[0, 0x80 | count as u8, color]),
+        (color, _) => out.extend_from_slice(&[0, 0xC0 | (count >> 8) as u8, count as u8, color]),
+    }
+}
+
+fn encode_line(line: &[u8], runs: &mut Vec<RlEncodedPixels>) {
+    let mut pixels = line.iter().peekable();
+    while let Some(&color) = pixels.next() {
+        let mut count = 1;
+        while count < MAX_RUN_LENGTH && pixels.next_if_eq(&&color).is_some() {
+            count += 1;
+        }
+        runs.push(RlEncodedPixels { count, color });
+    }
+    runs.push(RlEncodedPixels { count: 0, color: 0 });
+}
+
+impl DisplaySet<'_> {
+    /// Reduces the composed objects to a shared 4-color palette, as needed
+    /// for DVD (VobSub) subtitles. The four colors used most across all of
+    /// the objects are kept and every other color is mapped to its nearest
+    /// kept color by distance in YCbCr + alpha space. Returns the palette
+    /// along with each distinct object remapped to it, in composition order.
+    pub fn quantize_to_4_colors(&self) -> PgsResult<(PaletteDefinition, Vec<ObjectDefinition>)> {
+        let Some(palette) = self.palettes.get(&self.palette_id) else {
+            return Err(PgsError::PaletteNotFound {
+                palette_id: self.palette_id,
+                entry_id: 0,
+                display_set: format!("{:?}", self),
+            });
+        };
+        let mut objects: Vec<&ObjectDefinition> = Vec::new();
+        for composition_object in self.composition_objects {
+            let Some(object) = self.objects.get(&composition_object.id) else {
+                return Err(PgsError::ObjectNotFound {
+                    object_id: composition_object.id,
+                    display_set: format!("{:?}", self),
+                });
+            };
+            if !objects.iter().any(|seen| seen.id == object.id) {
+                objects.push(object);
+            }
+        }
+
+        let mut usage: HashMap<u8, u64> = HashMap::new();
+        for run in objects
+            .iter()
+            .flat_map(|object| &object.data.0)
+            .filter(|run| run.count > 0)
+        {
+            *usage.entry(run.color).or_default() += run.count as u64;
+        }
+        let mut colors = Vec::with_capacity(usage.len());
+        for (&color, &count) in &usage {
+            let Some(entry) = palette.entries.get(&color) else {
+                return Err(PgsError::PaletteNotFound {
+                    palette_id: palette.id,
+                    entry_id: color,
+                    display_set: format!("{:?}", self),
+                });
+            };
+            colors.push((count, entry));
+        }
+        colors.sort_by(|(a_count, a), (b_count, b)| b_count.cmp(a_count).then(a.id.cmp(&b.id)));
+        let kept: Vec<&PaletteEntry> = colors.iter().take(4).map(|(_, entry)| *entry).collect();
+
+        let remap: HashMap<u8, u8> = colors
+            .iter()
+            .map(|(_, entry)| {
+                let nearest = (0..kept.len())
+                    .min_by_key(|&index| color_distance(entry, kept[index]))
+                    .unwrap_or(0);
+                (entry.id, nearest as u8)
+            })
+            .collect();
+
+        let quantized_palette = PaletteDefinition {
+            id: palette.id,
+            version: palette.version,
+            entries: kept
+                .iter()
+                .enumerate()
+                .map(|(index, entry)| {
+                    (
+                        index as u8,
+                        PaletteEntry {
+                            id: index as u8,
+                            luminance: entry.luminance,
+                            color_difference_red: entry.color_difference_red,
+                            color_difference_blue: entry.color_difference_blue,
+                            alpha: entry.alpha,
+                        },
+                    )
+                })
+                .collect(),
+        };
+        let quantized_objects = objects
+            .into_iter()
+            .map(|object| {
+                let indices: Vec<u8> = object
+                    .decode_indexed()
+                    .into_iter()
+                    .map(|color| remap.get(&color).copied().unwrap_or(0))
+                    .collect();
+                ObjectDefinition::from_bitmap(
+                    object.id,
+                    object.version,
+                    object.width,
+                    object.height,
+                    &indices,
+                )
+            })
+            .collect::<PgsResult<_>>()?;
+        Ok((quantized_palette, quantized_objects))
+    }
+}
+
+fn color_distance(a: &PaletteEntry, b: &PaletteEntry) -> u32 {
+    let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    channel(a.luminance, b.luminance)
+        + channel(a.color_difference_red, b.color_difference_red)
+        + channel(a.color_difference_blue, b.color_difference_blue)
+        + channel(a.alpha, b.alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        PALETTE, composition_object, cue, end, ods, pcs, pcs_with_objects, pds, segment, wds,
+    };
+    use crate::parse_pgs;
+    use crate::render::DisplaySetIterator;
+
+    #[test]
+    fn bitmap_round_trips_through_an_object() {
+        // Long enough runs to need the 14-bit run length codes.
+        let mut indices = vec![0u8; 100];
+        indices.extend([1u8; 100]);
+        indices.extend([3, 2, 2, 0, 1, 1, 1, 1].repeat(25));
+        let object = ObjectDefinition::from_bitmap(7, 1, 100, 4, &indices).unwrap();
+
+        assert_eq!(object.decode_indexed(), indices);
+        assert_eq!(object.data.line_ranges().len(), 4);
+    }
+
+    #[test]
+    fn bitmap_of_the_wrong_size_is_rejected() {
+        let error = ObjectDefinition::from_bitmap(7, 0, 4, 2, &[1; 7]).unwrap_err();
+        assert!(matches!(
+            error,
+            PgsError::BitmapSizeMismatch {
+                object_id: 7,
+                len: 7,
+                width: 4,
+                height: 2,
+            }
+        ));
+    }
+
+    #[test]
+    fn quantizing_keeps_the_four_colors_used_most_across_objects() {
+        let entries: Vec<(u8, u8, u8, u8, u8)> =
+            (0..8).map(|id| (id, id * 32, 128, 128, 255)).collect();
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[(0, 0, 0, 0), (1, 0, 0, 4)]),
+            wds(0, &[(0, 0, 0, 8, 8)]),
+            pds(0, 0, &entries),
+            ods(0, 0, 4, &[0, 1, 2, 3, 4, 5, 6, 7]),
+            ods(0, 1, 4, &[7; 4]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = DisplaySetIterator::new(&pgs).next().unwrap();
+        let (palette, objects) = display_set.quantize_to_4_colors().unwrap();
+
+        assert_eq!(palette.entries.len(), 4);
+        let luminances: Vec<u8> = (0..4).map(|id| palette.entries[&id].luminance).collect();
+        assert_eq!(luminances, [224, 0, 32, 64]);
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].decode_indexed(), [1, 2, 3, 3, 3, 0, 0, 0]);
+        assert_eq!(objects[1].decode_indexed(), [0; 4]);
+    }
+
+    #[test]
+    fn built_cue_and_clear_yield_a_shown_then_an_empty_set() {
+        let window = Window {
+            id: 0,
+            horizontal_position: 4,
+            vertical_position: 2,
+            width: 2,
+            height: 2,
+        };
+        let palette = PaletteDefinition {
+            id: 0,
+            version: 0,
+            entries: HashMap::from([(
+                1,
+                PaletteEntry {
+                    id: 1,
+                    luminance: 235,
+                    color_difference_red: 128,
+                    color_difference_blue: 128,
+                    alpha: 255,
+                },
+            )]),
+        };
+        let object = ObjectDefinition::from_bitmap(0, 0, 2, 2, &[1; 4]).unwrap();
+        let mut builder = PgsBuilder::new(64, 32, 0x10);
+        builder
+            .show_at(90_000, window, palette, object, 4, 2)
+            .clear_at(180_000);
+        let pgs = builder.build();
+
+        // The stream also survives being written out and parsed back.
+        let reparsed = parse_pgs(&mut write_pgs(&pgs)).unwrap();
+        for pgs in [&pgs, &reparsed] {
+            let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(pgs).collect();
+            assert_eq!(display_sets.len(), 2);
+            assert!(!display_sets[0].is_empty());
+            assert_eq!(display_sets[0].presentation_timestamp, 90_000);
+            assert!(display_sets[1].is_empty());
+            assert_eq!(display_sets[1].presentation_timestamp, 180_000);
+            assert_eq!(display_sets[1].composition_state, CompositionState::Normal);
+        }
+    }
+
+    #[test]
+    fn palette_only_change_diffs_to_a_palette_and_a_composition() {
+        let recolored = PALETTE.map(|(id, y, cr, cb, alpha)| (id, 255 - y, cr, cb, alpha));
+        let mut data = [
+            cue(0, (16, 8), (2, 1), 2, &[1, 3, 3, 1]),
+            pcs(90, (16, 8), 1, 0x00, 0x80, &[(0, 0, 2, 1)]),
+            pds(90, 1, &recolored),
+            end(90),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(&pgs).collect();
+
+        let segments = diff_to_segments(&display_sets[0], &display_sets[1]);
+        assert!(matches!(
+            segments.as_slice(),
+            [
+                Segment { contents: SegmentContents::PresentationComposition(composition), .. },
+                Segment { contents: SegmentContents::PaletteDefinition(palette), .. },
+                Segment { contents: SegmentContents::End, .. },
+            ] if composition.palette_update
+                && composition.composition_objects == display_sets[1].composition_objects
+                && palette == display_sets[1].palettes[&0]
+        ));
+        assert!(segments.iter().all(|segment| segment.pts == 90));
+    }
+
+    #[test]
+    fn non_canonical_stream_is_written_back_byte_for_byte() {
+        // A 4x2 object of three white pixels and a black one over a
+        // transparent line, using longer codes than needed for every run.
+        let runs = [0, 0xC0, 3, 1, 0, 0x81, 3, 0, 0, 0, 0x40, 4, 0, 0];
+        let object_data = [&[0, 0, runs.len() as u8 + 4][..], &[0, 4, 0, 2], &runs].concat();
+        let fragment =
+            |pts, flag, bytes: &[u8]| segment(pts, 0x15, &[&[0, 0, 0, flag], bytes].concat());
+        let data = [
+            // Reserved bits set in the composition state, palette update and
+            // object flag bytes.
+            pcs_with_objects(
+                0,
+                (16, 8),
+                0,
+                0x81,
+                0x01,
+                &[composition_object(0, 0, (2, 1), 0x05, None)],
+            ),
+            wds(0, &[(0, 2, 1, 4, 2)]),
+            pds(0, 0, &PALETTE),
+            // Fragments at their own timestamps, two of them padded.
+            fragment(0, 0x80, &object_data[..9]),
+            vec![0; 2],
+            fragment(1, 0x00, &object_data[9..15]),
+            fragment(2, 0x40, &object_data[15..]),
+            vec![0; 3],
+            end(2),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data.clone()).unwrap();
+        let SegmentContents::PresentationComposition(composition) = &pgs.segments[0].contents
+        else {
+            panic!("expected a presentation composition");
+        };
+        assert_eq!(composition.composition_state, CompositionState::EpochStart);
+        assert_eq!(write_pgs(&pgs), data);
+        assert_eq!(write_pgs(&Pgs::from_cache(&pgs.to_cache()).unwrap()), data);
+
+        // Once edited, the object is re-encoded with the shortest codes.
+        let mut edited = pgs.clone();
+        let SegmentContents::ObjectDefinition(object) = &mut edited.segments[3].contents else {
+            panic!("expected the first object fragment");
+        };
+        let indices = [1, 1, 1, 1, 0, 0, 0, 0];
+        object.data = ObjectDefinition::from_bitmap(0, 0, 4, 2, &indices)
+            .unwrap()
+            .data;
+        let written = write_pgs(&edited);
+        assert!(written.len() < data.len());
+        let reparsed = parse_pgs(&mut written.clone()).unwrap();
+        let display_set = DisplaySetIterator::new(&reparsed).next().unwrap();
+        assert_eq!(display_set.objects[&0].decode_indexed(), indices);
+    }
+
+    #[test]
+    fn cropped_object_survives_being_written_and_parsed() {
+        let window = Window {
+            id: 0,
+            horizontal_position: 4,
+            vertical_position: 2,
+            width: 4,
+            height: 2,
+        };
+        let palette = PaletteDefinition {
+            id: 0,
+            version: 0,
+            entries: HashMap::from(PALETTE.map(|(id, y, cr, cb, alpha)| {
+                (
+                    id,
+                    PaletteEntry {
+                        id,
+                        luminance: y,
+                        color_difference_red: cr,
+                        color_difference_blue: cb,
+                        alpha,
+                    },
+                )
+            })),
+        };
+        let crop = CropInfo {
+            horizontal_position: 1,
+            vertical_position: 0,
+            width: 2,
+            height: 2,
+        };
+        let mut builder = PgsBuilder::new(16, 8, 0x10);
+        builder
+            .define_window(window)
+            .define_palette(palette)
+            .define_object(ObjectDefinition::from_bitmap(0, 0, 4, 2, &[1; 8]).unwrap())
+            .object_cropped(0, 0, 4, 2, crop.clone())
+            .present_at(0);
+
+        let reparsed = parse_pgs(&mut write_pgs(&builder.build())).unwrap();
+        let display_set = DisplaySetIterator::new(&reparsed).next().unwrap();
+        let composition_object = &display_set.composition_objects[0];
+        assert_eq!(composition_object.cropped, Some(crop));
+        assert_eq!(composition_object.raw_flags, 0x40);
+    }
+}
@@ -1,6 +1,8 @@
 use thiserror::Error;
 use winnow::error::{ContextError, ParseError};
 
+use crate::parse::LastInSequence;
+
 pub type PgsResult<T> = Result<T, PgsError>;
 
 #[derive(Error, Debug)]
@@ -15,6 +17,70 @@ pub enum PgsError {
         entry_id: u8,
         display_set: String,
     },
+    #[error("Display set {display_set} has invalid dimensions {width}x{height}")]
+    InvalidDimensions {
+        width: u16,
+        height: u16,
+        display_set: String,
+    },
+    #[error("Object {object_id} has invalid dimensions {width}x{height}")]
+    InvalidObjectDimensions {
+        object_id: u16,
+        width: u16,
+        height: u16,
+    },
+    #[error(
+        "Object {object_id} run-length data overruns its {width}x{height} bounds on line {line}"
+    )]
+    RunLengthOverrun {
+        object_id: u16,
+        width: u16,
+        height: u16,
+        line: usize,
+    },
+    #[error("Bitmap of {len} indices doesn't match object {object_id}'s {width}x{height} size")]
+    BitmapSizeMismatch {
+        object_id: u16,
+        len: usize,
+        width: u16,
+        height: u16,
+    },
+    #[error("Stride of {stride} bytes is too small for a row of {width} pixels")]
+    InvalidStride { stride: usize, width: u16 },
+    #[error("Output buffer of {len} bytes is too small, {required} bytes are required")]
+    BufferTooSmall { len: usize, required: usize },
+    #[error("Object {object_id} has an out-of-order {fragment:?} fragment")]
+    FragmentOutOfOrder {
+        object_id: u16,
+        fragment: LastInSequence,
+    },
+    #[error("Object {object_id} is missing its last fragment")]
+    IncompleteObject { object_id: u16 },
+    #[error("Segment {segment} has different timestamps from its display set's composition")]
+    TimestampMismatch { segment: usize },
+    #[error("Display set starting at segment {segment} has no end segment")]
+    UnterminatedDisplaySet { segment: usize },
+    #[error("Segment {segment} starts a display set without a presentation composition")]
+    MissingComposition { segment: usize },
+    #[error("Expected PGS segment magic 'PG' (0x5047) at offset {offset}, found {found:#06X}")]
+    InvalidMagic { offset: usize, found: u16 },
+    #[error("Unexpected {field} value {value:#04X} at offset {offset}")]
+    UnexpectedValue {
+        field: &'static str,
+        value: u8,
+        offset: usize,
+    },
+    #[error("Cache was written with version {found}, expected version {expected}")]
+    CacheVersionMismatch { found: u16, expected: u16 },
+    #[error("Seek index was written with version {found}, expected version {expected}")]
+    SeekIndexVersionMismatch { found: u16, expected: u16 },
+    #[error("Segment index {index} is too large to store in a seek index")]
+    SeekPointOutOfRange { index: usize },
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "image")]
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
     #[error("YUV error: {0}")]
     YuvError(#[from] yuv::YuvError),
     #[error("Failed to parse PGS data: {0}")]
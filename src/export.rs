@@ -0,0 +1,369 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg(feature = "image")]
+use image::{ImageFormat, Rgba, RgbaImage, imageops};
+
+#[cfg(feature = "image")]
+use crate::render::thumbnails;
+use crate::{
+    error::PgsResult,
+    parse::Pgs,
+    render::{DisplaySet, DisplaySetIterator, PIXEL_SIZE, render_display_set_strided},
+    timing::{PTS_CLOCK_HZ, pts_to_duration},
+};
+
+/// Duration given to an event that nothing later in the stream ends, such as
+/// a cue left on screen by the last display set, in 90 kHz ticks.
+const TRAILING_EVENT_DURATION: u32 = 2 * PTS_CLOCK_HZ as u32;
+
+/// Converts the stream to an ASS script with one event per window shown by
+/// each visible display set. ASS can't show bitmaps, so each event draws the
+/// window's bounds as a `\p1` vector shape positioned with `\pos` at its
+/// top-left corner, and names its bitmap `{index:05}.png` in the event's
+/// Name field, where `index` is the display set's position in the stream as
+/// in [`crate::render::Rendered::index`], so the images can be written
+/// alongside the script and overlaid on the shapes. An event that nothing
+/// later in the stream ends lasts two seconds.
+pub fn to_ass(pgs: &Pgs) -> String {
+    let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(pgs).collect();
+    let (width, height) = display_sets.first().map_or((1920, 1080), |display_set| {
+        (display_set.width, display_set.height)
+    });
+    let stream_end = pgs.segments.last().map_or(0, |segment| segment.pts);
+
+    let mut ass = String::new();
+    ass.push_str("[Script Info]\nScriptType: v4.00+\n");
+    let _ = writeln!(ass, "PlayResX: {width}\nPlayResY: {height}\n");
+    ass.push_str("[V4+ Styles]\n");
+    ass.push_str("Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n");
+    ass.push_str("Style: Default,Arial,20,&H00FFFFFF,&H000000FF,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,0,0,7,0,0,0,1\n\n");
+    ass.push_str("[Events]\n");
+    ass.push_str(
+        "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+    );
+
+    for (index, display_set) in display_sets.iter().enumerate() {
+        if display_set.is_empty() {
+            continue;
+        }
+        let start = display_set.presentation_timestamp;
+        let end = display_sets
+            .get(index + 1)
+            .map_or(stream_end, |next| next.presentation_timestamp);
+        let end = if end > start {
+            end
+        } else {
+            start.saturating_add(TRAILING_EVENT_DURATION)
+        };
+        let mut window_ids: Vec<u8> = display_set
+            .composition_objects
+            .iter()
+            .map(|composition_object| composition_object.window_id)
+            .collect();
+        window_ids.sort_unstable();
+        window_ids.dedup();
+        for window in window_ids
+            .into_iter()
+            .filter_map(|window_id| display_set.windows.get(&window_id))
+        {
+            let _ = writeln!(
+                ass,
+                "Dialogue: 0,{},{},Default,{:05}.png,0,0,0,,{{\\an7\\pos({},{})\\p1}}m 0 0 l {w} 0 {w} {h} 0 {h}{{\\p0}}",
+                ass_time(pts_to_duration(start)),
+                ass_time(pts_to_duration(end)),
+                index,
+                window.horizontal_position,
+                window.vertical_position,
+                w = window.width,
+                h = window.height,
+            );
+        }
+    }
+    ass
+}
+
+/// Formats a time as ASS's `H:MM:SS.cc`.
+fn ass_time(time: Duration) -> String {
+    let centiseconds = time.as_millis() / 10;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centiseconds / 360_000,
+        centiseconds / 6_000 % 60,
+        centiseconds / 100 % 60,
+        centiseconds % 100
+    )
+}
+
+/// Renders every visible display set and writes the RGBA frames back to back
+/// into the file at `path`, one frame at a time so the export never needs
+/// more than a single frame in memory. An index is written next to it, at
+/// `path` with `.idx` appended, holding one line per frame:
+/// `<byte offset> <width> <height> <presentation timestamp>`.
+///
+/// Frames are rendered with [`render_display_set_strided`] into one buffer
+/// reused for every frame and streamed through a buffered writer, rather
+/// than rendered straight into a memory-mapped file. Mapping would need an
+/// extra dependency and `unsafe` code, since the file can change under the
+/// mapping, and a first pass over the stream to size the file, while the
+/// writes already go through the page cache with no per-frame allocation.
+pub fn export_raw_concatenated(pgs: &Pgs, path: impl AsRef<Path>) -> PgsResult<()> {
+    let path = path.as_ref();
+    let mut data = BufWriter::new(File::create(path)?);
+    let mut index_path = path.as_os_str().to_owned();
+    index_path.push(".idx");
+    let mut index = BufWriter::new(File::create(index_path)?);
+
+    let mut offset = 0u64;
+    let mut frame = Vec::new();
+    let mut display_sets = DisplaySetIterator::new(pgs);
+    while let Some(display_set) = display_sets.try_next() {
+        let display_set = display_set?;
+        if display_set.is_empty() {
+            continue;
+        }
+        let stride = display_set.width as usize * PIXEL_SIZE;
+        frame.resize(stride * display_set.height as usize, 0);
+        render_display_set_strided(&display_set, &mut frame, stride)?;
+        data.write_all(&frame)?;
+        writeln!(
+            index,
+            "{} {} {} {}",
+            offset, display_set.width, display_set.height, display_set.presentation_timestamp
+        )?;
+        offset += frame.len() as u64;
+    }
+    data.flush()?;
+    index.flush()?;
+    Ok(())
+}
+
+/// Scale applied to the 3x5 glyphs of [`GLYPHS`] when labelling cues.
+#[cfg(feature = "image")]
+const LABEL_SCALE: u32 = 2;
+/// Space left around labels and between cues, in pixels.
+#[cfg(feature = "image")]
+const LABEL_PADDING: u32 = 4;
+#[cfg(feature = "image")]
+const SHEET_BACKGROUND: Rgba<u8> = Rgba([48, 48, 48, 255]);
+#[cfg(feature = "image")]
+const LABEL_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Glyphs for the characters of a timecode, each 5 rows of 3 pixels with
+/// the leftmost pixel in bit 2.
+#[cfg(feature = "image")]
+const GLYPHS: [(char, [u8; 5]); 12] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+];
+
+/// Writes a PNG at `path` stacking every visible cue vertically, each
+/// cropped to its visible pixels and labelled above with the time it is
+/// shown as `H:MM:SS.mmm`, for scanning a whole track at a glance.
+///
+/// Cues are rendered one at a time, twice: once to size the sheet and once
+/// to draw it, so only the sheet itself is held in memory. It still takes 4
+/// bytes per pixel of every cue's bounding box, which for a feature-length
+/// track can run to hundreds of megabytes.
+#[cfg(feature = "image")]
+pub fn export_contact_sheet(pgs: &Pgs, path: impl AsRef<Path>) -> PgsResult<()> {
+    let label_height = 5 * LABEL_SCALE + 2 * LABEL_PADDING;
+    let (mut width, mut height) = (0, 0);
+    for cue in thumbnails(pgs, u32::MAX) {
+        let (time, image) = cue?;
+        width = width.max(image.width().max(label_width(&timecode(time))));
+        height += label_height + image.height() + LABEL_PADDING;
+    }
+
+    let mut sheet = RgbaImage::from_pixel(width.max(1), height.max(1), SHEET_BACKGROUND);
+    let mut y = 0;
+    for cue in thumbnails(pgs, u32::MAX) {
+        let (time, image) = cue?;
+        draw_label(
+            &mut sheet,
+            LABEL_PADDING,
+            y + LABEL_PADDING,
+            &timecode(time),
+        );
+        y += label_height;
+        imageops::overlay(&mut sheet, &image, 0, y as i64);
+        y += image.height() + LABEL_PADDING;
+    }
+    sheet.save_with_format(path, ImageFormat::Png)?;
+    Ok(())
+}
+
+/// Formats a time as `H:MM:SS.mmm`.
+#[cfg(feature = "image")]
+fn timecode(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(feature = "image")]
+fn label_width(label: &str) -> u32 {
+    label.chars().count() as u32 * 4 * LABEL_SCALE + 2 * LABEL_PADDING
+}
+
+/// Draws `label` with its top-left corner at `(x, y)`, skipping characters
+/// without a glyph and pixels outside the image.
+#[cfg(feature = "image")]
+fn draw_label(image: &mut RgbaImage, x: u32, y: u32, label: &str) {
+    for (index, character) in label.chars().enumerate() {
+        let Some((_, rows)) = GLYPHS.iter().find(|(glyph, _)| *glyph == character) else {
+            continue;
+        };
+        let left = x + index as u32 * 4 * LABEL_SCALE;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for dy in 0..LABEL_SCALE {
+                    for dx in 0..LABEL_SCALE {
+                        let (px, py) = (
+                            left + column * LABEL_SCALE + dx,
+                            y + row as u32 * LABEL_SCALE + dy,
+                        );
+                        if px < image.width() && py < image.height() {
+                            image.put_pixel(px, py, LABEL_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{clear, cue};
+    use crate::parse_pgs;
+    use crate::render::render_display_set;
+
+    #[test]
+    fn ass_events_are_placed_at_their_windows() {
+        let mut data = [
+            cue(90_000, (64, 32), (10, 20), 2, &[1; 4]),
+            clear(180_000, (64, 32)),
+            cue(270_000, (64, 32), (30, 4), 2, &[3; 4]),
+            clear(315_000, (64, 32)),
+        ]
+        .concat();
+        let ass = to_ass(&parse_pgs(&mut data).unwrap());
+
+        let events: Vec<&str> = ass
+            .lines()
+            .filter(|line| line.starts_with("Dialogue:"))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                "Dialogue: 0,0:00:01.00,0:00:02.00,Default,00000.png,0,0,0,,{\\an7\\pos(10,20)\\p1}m 0 0 l 2 0 2 2 0 2{\\p0}",
+                "Dialogue: 0,0:00:03.00,0:00:03.50,Default,00002.png,0,0,0,,{\\an7\\pos(30,4)\\p1}m 0 0 l 2 0 2 2 0 2{\\p0}",
+            ]
+        );
+        assert!(ass.contains("PlayResX: 64\nPlayResY: 32\n"));
+    }
+
+    #[test]
+    fn ass_event_left_on_screen_gets_the_trailing_duration() {
+        let mut data = [
+            cue(90_000, (64, 32), (10, 20), 2, &[1; 4]),
+            clear(180_000, (64, 32)),
+            cue(270_000, (64, 32), (30, 4), 2, &[3; 4]),
+        ]
+        .concat();
+        let ass = to_ass(&parse_pgs(&mut data).unwrap());
+
+        let last = ass
+            .lines()
+            .rfind(|line| line.starts_with("Dialogue:"))
+            .unwrap();
+        assert!(last.starts_with("Dialogue: 0,0:00:03.00,0:00:05.00,Default,00002.png,"));
+    }
+
+    #[test]
+    fn raw_index_offsets_point_at_each_frame() {
+        let mut data = [
+            cue(0, (4, 2), (0, 0), 2, &[1, 3, 3, 1]),
+            clear(9_000, (4, 2)),
+            cue(18_000, (8, 4), (2, 1), 2, &[3, 1, 1, 3]),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let path = std::env::temp_dir().join(format!("pgs-rs-raw-{}.rgba", std::process::id()));
+        export_raw_concatenated(&pgs, &path).unwrap();
+        let index_path = path.with_extension("rgba.idx");
+        let (raw, index) = (
+            std::fs::read(&path).unwrap(),
+            std::fs::read_to_string(&index_path).unwrap(),
+        );
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+
+        let expected: Vec<Vec<u8>> = DisplaySetIterator::new(&pgs)
+            .skip_empty()
+            .map(|display_set| render_display_set(&display_set).unwrap())
+            .collect();
+        assert_eq!(
+            index.lines().collect::<Vec<_>>(),
+            ["0 4 2 0", "32 8 4 18000"]
+        );
+        for (line, frame) in index.lines().zip(&expected) {
+            let fields: Vec<usize> = line
+                .split(' ')
+                .map(|field| field.parse().unwrap())
+                .collect();
+            let (offset, width, height) = (fields[0], fields[1], fields[2]);
+            assert_eq!(&raw[offset..offset + width * height * 4], &frame[..]);
+        }
+        assert_eq!(raw.len(), expected.iter().map(Vec::len).sum::<usize>());
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn contact_sheet_grows_with_each_cue() {
+        let cues = [
+            cue(90_000, (64, 32), (4, 4), 8, &[1; 8 * 3]),
+            clear(180_000, (64, 32)),
+            cue(270_000, (64, 32), (10, 2), 2, &[3; 2 * 5]),
+            clear(360_000, (64, 32)),
+        ];
+        let path = std::env::temp_dir().join(format!("pgs-rs-sheet-{}.png", std::process::id()));
+        let mut heights = Vec::new();
+        for count in [2, 4] {
+            let mut data = cues[..count].concat();
+            export_contact_sheet(&parse_pgs(&mut data).unwrap(), &path).unwrap();
+            let (width, height) = image::image_dimensions(&path).unwrap();
+            assert!(width >= label_width(&timecode(Duration::from_secs(1))));
+            heights.push(height);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        // Each cue adds its label, its bounding box and the space below it.
+        let label_height = 5 * LABEL_SCALE + 2 * LABEL_PADDING;
+        assert_eq!(heights[0], label_height + 3 + LABEL_PADDING);
+        assert_eq!(heights[1], heights[0] + label_height + 5 + LABEL_PADDING);
+    }
+}
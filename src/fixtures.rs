@@ -0,0 +1,229 @@
+//! Builders for the raw bytes of PGS segments, so tests can parse real
+//! streams instead of assembling parsed values by hand.
+
+/// A segment with the `PG` magic, `pts` as both timestamps and `body`.
+pub(crate) fn segment(pts: u32, segment_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut bytes = b"PG".to_vec();
+    bytes.extend_from_slice(&pts.to_be_bytes());
+    bytes.extend_from_slice(&pts.to_be_bytes());
+    bytes.push(segment_type);
+    bytes.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// A presentation composition segment at 23.976fps with palette 0 and
+/// unflagged composition objects given as `(object_id, window_id, x, y)`.
+pub(crate) fn pcs(
+    pts: u32,
+    frame: (u16, u16),
+    composition_number: u16,
+    state: u8,
+    palette_update: u8,
+    objects: &[(u16, u8, u16, u16)],
+) -> Vec<u8> {
+    let objects: Vec<Vec<u8>> = objects
+        .iter()
+        .map(|&(id, window_id, x, y)| composition_object(id, window_id, (x, y), 0, None))
+        .collect();
+    pcs_with_objects(
+        pts,
+        frame,
+        composition_number,
+        state,
+        palette_update,
+        &objects,
+    )
+}
+
+/// Like [`pcs`], with composition objects built by [`composition_object`].
+pub(crate) fn pcs_with_objects(
+    pts: u32,
+    (width, height): (u16, u16),
+    composition_number: u16,
+    state: u8,
+    palette_update: u8,
+    objects: &[Vec<u8>],
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&width.to_be_bytes());
+    body.extend_from_slice(&height.to_be_bytes());
+    body.push(0x10);
+    body.extend_from_slice(&composition_number.to_be_bytes());
+    body.extend_from_slice(&[state, palette_update, 0, objects.len() as u8]);
+    body.extend(objects.concat());
+    segment(pts, 0x16, &body)
+}
+
+/// A composition object entry with the given flag byte, followed by `crop`
+/// as `(x, y, width, height)` when given.
+pub(crate) fn composition_object(
+    id: u16,
+    window_id: u8,
+    (x, y): (u16, u16),
+    flags: u8,
+    crop: Option<(u16, u16, u16, u16)>,
+) -> Vec<u8> {
+    let mut bytes = id.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&[window_id, flags]);
+    bytes.extend_from_slice(&x.to_be_bytes());
+    bytes.extend_from_slice(&y.to_be_bytes());
+    if let Some((x, y, width, height)) = crop {
+        for value in [x, y, width, height] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    bytes
+}
+
+/// A window definition segment with windows given as
+/// `(id, x, y, width, height)`.
+pub(crate) fn wds(pts: u32, windows: &[(u8, u16, u16, u16, u16)]) -> Vec<u8> {
+    let mut body = vec![windows.len() as u8];
+    for (id, x, y, width, height) in windows {
+        body.push(*id);
+        for value in [x, y, width, height] {
+            body.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    segment(pts, 0x17, &body)
+}
+
+/// A palette definition segment for palette 0 with entries given as
+/// `(id, y, cr, cb, alpha)`.
+pub(crate) fn pds(pts: u32, version: u8, entries: &[(u8, u8, u8, u8, u8)]) -> Vec<u8> {
+    let mut body = vec![0, version];
+    for (id, y, cr, cb, alpha) in entries {
+        body.extend_from_slice(&[*id, *y, *cr, *cb, *alpha]);
+    }
+    segment(pts, 0x14, &body)
+}
+
+/// Sets the palette id selected by a segment built by [`pcs`] or defined by
+/// one built by [`pds`].
+pub(crate) fn with_palette_id(mut segment: Vec<u8>, palette_id: u8) -> Vec<u8> {
+    let offset = match segment[10] {
+        0x16 => 13 + 9,
+        0x14 => 13,
+        segment_type => panic!("segment type {segment_type:#04X} has no palette id"),
+    };
+    segment[offset] = palette_id;
+    segment
+}
+
+/// An unfragmented object definition segment holding `indices`, a row-major
+/// bitmap of palette indices `width` pixels wide.
+pub(crate) fn ods(pts: u32, id: u16, width: u16, indices: &[u8]) -> Vec<u8> {
+    let data = rle(width, indices);
+    let height = (indices.len() / width as usize) as u16;
+    let mut body = id.to_be_bytes().to_vec();
+    body.extend_from_slice(&[0, 0xC0]);
+    body.extend_from_slice(&(data.len() as u32 + 4).to_be_bytes()[1..]);
+    body.extend_from_slice(&width.to_be_bytes());
+    body.extend_from_slice(&height.to_be_bytes());
+    body.extend_from_slice(&data);
+    segment(pts, 0x15, &body)
+}
+
+/// Object definition segments carrying `data` as the run-length data of a
+/// `width` x `height` object, split into fragments at each offset in `at`
+/// into the object data (the 7 bytes of length and dimensions followed by
+/// `data`). The first fragment must hold at least those 7 bytes.
+pub(crate) fn object_fragments(
+    pts: u32,
+    id: u16,
+    (width, height): (u16, u16),
+    data: &[u8],
+    at: &[usize],
+) -> Vec<u8> {
+    let mut object_data = (data.len() as u32 + 4).to_be_bytes()[1..].to_vec();
+    object_data.extend_from_slice(&width.to_be_bytes());
+    object_data.extend_from_slice(&height.to_be_bytes());
+    object_data.extend_from_slice(data);
+    let bounds: Vec<usize> = [0]
+        .into_iter()
+        .chain(at.iter().copied())
+        .chain([object_data.len()])
+        .collect();
+    let mut bytes = Vec::new();
+    for (index, range) in bounds.windows(2).enumerate() {
+        let flag = match (index == 0, range[1] == object_data.len()) {
+            (true, true) => 0xC0,
+            (true, false) => 0x80,
+            (false, true) => 0x40,
+            (false, false) => 0x00,
+        };
+        let mut body = id.to_be_bytes().to_vec();
+        body.extend_from_slice(&[0, flag]);
+        body.extend_from_slice(&object_data[range[0]..range[1]]);
+        bytes.extend(segment(pts, 0x15, &body));
+    }
+    bytes
+}
+
+/// An end of display set segment.
+pub(crate) fn end(pts: u32) -> Vec<u8> {
+    segment(pts, 0x80, &[])
+}
+
+/// Run-length encodes a bitmap, using the shortest code for each run and
+/// ending every scanline with an end of line code.
+pub(crate) fn rle(width: u16, indices: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for line in indices.chunks(width as usize) {
+        let mut start = 0;
+        while start < line.len() {
+            let color = line[start];
+            let count = line[start..]
+                .iter()
+                .take_while(|index| **index == color)
+                .count()
+                .min(0x3FFF);
+            match (color, count) {
+                (0, 1..64) => data.extend_from_slice(&[0, count as u8]),
+                (0, _) => data.extend_from_slice(&[0, 0x40 | (count >> 8) as u8, count as u8]),
+                (_, 1) => data.push(color),
+                (_, 2..64) => data.extend_from_slice(&[0, 0x80 | count as u8, color]),
+                _ => data.extend_from_slice(&[0, 0xC0 | (count >> 8) as u8, count as u8, color]),
+            }
+            start += count;
+        }
+        data.extend_from_slice(&[0, 0]);
+    }
+    data
+}
+
+/// Palette entries used by [`cue`]: 0 is transparent, 1 opaque white, 2
+/// half transparent white and 3 opaque black.
+pub(crate) const PALETTE: [(u8, u8, u8, u8, u8); 4] = [
+    (0, 0, 128, 128, 0),
+    (1, 255, 128, 128, 255),
+    (2, 255, 128, 128, 128),
+    (3, 0, 128, 128, 255),
+];
+
+/// An epoch start display set in a `frame` sized video showing object 0,
+/// built from `indices` as in [`ods`], at `(x, y)` in window 0 fitted to
+/// it, with [`PALETTE`].
+pub(crate) fn cue(
+    pts: u32,
+    frame: (u16, u16),
+    (x, y): (u16, u16),
+    width: u16,
+    indices: &[u8],
+) -> Vec<u8> {
+    let height = (indices.len() / width as usize) as u16;
+    [
+        pcs(pts, frame, 0, 0x80, 0, &[(0, 0, x, y)]),
+        wds(pts, &[(0, x, y, width, height)]),
+        pds(pts, 0, &PALETTE),
+        ods(pts, 0, width, indices),
+        end(pts),
+    ]
+    .concat()
+}
+
+/// A display set removing everything from the screen.
+pub(crate) fn clear(pts: u32, frame: (u16, u16)) -> Vec<u8> {
+    [pcs(pts, frame, 0, 0x00, 0, &[]), end(pts)].concat()
+}
@@ -1,6 +1,21 @@
+pub mod analysis;
+#[cfg(feature = "arena")]
+pub mod arena;
+pub mod cache;
+pub mod edit;
+pub mod encode;
 pub mod error;
+pub mod export;
 pub mod parse;
 pub mod render;
+pub mod seek;
+pub mod stream;
+pub mod timing;
+pub mod validate;
 
+#[cfg(test)]
+mod fixtures;
+
+pub use encode::write_pgs;
 pub use parse::parse_pgs;
 pub use render::render_display_set;
@@ -1,49 +1,72 @@
 use std::collections::HashMap;
+use std::ops::Range;
 
 use struple::Struple;
 use winnow::Result as PResult;
 use winnow::binary::{be_u8, be_u16, be_u24, be_u32, length_and_then, length_repeat};
 use winnow::combinator::{alt, dispatch, fail, repeat};
+use winnow::error::{ContextError, ParseError, StrContext};
 use winnow::prelude::*;
+use winnow::token::{rest, take, take_while};
 
-use crate::error::PgsResult;
+use crate::error::{PgsError, PgsResult};
+use crate::timing::PTS_CLOCK_HZ;
 
-#[derive(Debug, PartialEq, Eq, Struple)]
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct Pgs {
     pub segments: Vec<Segment>,
 }
 
-#[derive(Debug, PartialEq, Eq, Struple)]
+impl Pgs {
+    /// Iterates over the raw segments in stream order. Unlike
+    /// [`get_display_sets`](crate::render::get_display_sets), segments are not
+    /// grouped into display sets and no window, palette or object state is
+    /// carried between them.
+    pub fn iter_segments(&self) -> impl Iterator<Item = &Segment> {
+        self.segments.iter()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct Segment {
     pub pts: u32,
     pub dts: u32,
     pub contents: SegmentContents,
 }
 
-#[derive(Debug, PartialEq, Eq, Struple)]
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct PresentationComposition {
     pub width: u16,
     pub height: u16,
     pub frame_rate: u8,
     pub composition_number: u16,
     pub composition_state: CompositionState,
+    /// The composition state byte as read, including its reserved bits, so
+    /// it can be written back unchanged. Only bits 7 and 6 are significant,
+    /// so e.g. `0x81` reads as an epoch start and `0x41` as an acquisition
+    /// point.
+    pub raw_composition_state: u8,
     pub palette_update: bool,
+    /// The palette update flag byte as read, including its reserved bits,
+    /// so it can be written back unchanged. Only bit 7 is significant, so
+    /// e.g. `0x81` reads as a palette update and `0x01` does not.
+    pub raw_palette_update_flag: u8,
     pub palette_id: u8,
     pub composition_objects: Vec<CompositionObject>,
 }
 
-#[derive(Debug, PartialEq, Eq, Struple)]
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct WindowDefinition {
     pub windows: Vec<Window>,
 }
-#[derive(Debug, PartialEq, Eq, Struple)]
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct PaletteDefinition {
     pub id: u8,
     pub version: u8,
     pub entries: HashMap<u8, PaletteEntry>,
 }
 
-#[derive(Debug, PartialEq, Eq, Struple)]
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct ObjectDefinition {
     pub id: u16,
     pub version: u8,
@@ -51,9 +74,63 @@ pub struct ObjectDefinition {
     pub width: u16,
     pub height: u16,
     pub data: RunLengthEncodedData,
+    /// Length in bytes of the run-length data as read, across every fragment
+    /// of the object. Encoders don't always use the shortest code for each
+    /// run, so this can exceed [`RunLengthEncodedData::encoded_len`]. Only
+    /// set on the fragment carrying the decoded data, and `None` for objects
+    /// built in memory. It is not updated when `data` is changed.
+    pub raw_data_len: Option<usize>,
+    /// The object data as read, so [`crate::write_pgs`] can write the object
+    /// back byte for byte. Set on the same fragment as `raw_data_len`.
+    pub raw: Option<RawObjectData>,
+}
+
+/// An object's data exactly as it was read, with the run-length codes that
+/// were used, which aren't always the shortest, and how it was split into
+/// fragments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawObjectData {
+    /// The object data across every fragment: its declared length, its
+    /// dimensions and its run-length data.
+    pub bytes: Vec<u8>,
+    /// The fragments `bytes` was read from, in order.
+    pub fragments: Vec<RawFragment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFragment {
+    /// Number of bytes of the object data carried by the fragment.
+    pub len: usize,
+    /// Number of zero bytes of padding following the fragment's segment.
+    pub padding: usize,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl RawObjectData {
+    /// Whether the bytes still decode to `object`'s dimensions and run-length
+    /// data, i.e. the object hasn't been edited since it was read.
+    pub(crate) fn matches(&self, object: &ObjectDefinition) -> bool {
+        let Some((dimensions, data)) = self
+            .bytes
+            .get(3..)
+            .and_then(|rest| rest.split_at_checked(4))
+        else {
+            return false;
+        };
+        dimensions[..2] == object.width.to_be_bytes()
+            && dimensions[2..] == object.height.to_be_bytes()
+            && self
+                .fragments
+                .iter()
+                .map(|fragment| fragment.len)
+                .sum::<usize>()
+                == self.bytes.len()
+            && parse_run_length_encoded_pixels
+                .parse(data)
+                .is_ok_and(|runs| runs == object.data.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SegmentContents {
     PresentationComposition(PresentationComposition),
     WindowDefinition(WindowDefinition),
@@ -67,10 +144,50 @@ pub enum LastInSequence {
     Last,
     First,
     FirstAndLast,
+    /// A fragment that is neither the first nor the last of its object.
+    Middle,
 }
-#[derive(PartialEq, Eq)]
+
+impl LastInSequence {
+    /// Whether this fragment starts its object, carrying its dimensions.
+    pub fn is_first(&self) -> bool {
+        matches!(self, LastInSequence::First | LastInSequence::FirstAndLast)
+    }
+
+    /// Whether this fragment completes its object.
+    pub fn is_last(&self) -> bool {
+        matches!(self, LastInSequence::Last | LastInSequence::FirstAndLast)
+    }
+}
+#[derive(Clone, PartialEq, Eq)]
 pub struct RunLengthEncodedData(pub Vec<RlEncodedPixels>);
 
+impl RunLengthEncodedData {
+    /// Returns the range of runs making up each scanline, excluding the
+    /// `00 00` end-of-line markers. Lines are independent of one another, so
+    /// they can be decoded in parallel.
+    pub fn line_ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        for (index, run) in self.0.iter().enumerate() {
+            if run.count == 0 {
+                ranges.push(start..index);
+                start = index + 1;
+            }
+        }
+        if start < self.0.len() {
+            ranges.push(start..self.0.len());
+        }
+        ranges
+    }
+
+    /// Number of bytes the runs occupy when encoded with the shortest code
+    /// for each run.
+    pub fn encoded_len(&self) -> usize {
+        self.0.iter().map(RlEncodedPixels::encoded_len).sum()
+    }
+}
+
 impl std::fmt::Debug for RunLengthEncodedData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_tuple("RunLengthEncodedData")
@@ -79,7 +196,7 @@ impl std::fmt::Debug for RunLengthEncodedData {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Struple)]
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct Window {
     pub id: u8,
     pub horizontal_position: u16,
@@ -88,7 +205,7 @@ pub struct Window {
     pub height: u16,
 }
 
-#[derive(Debug, PartialEq, Eq, Struple)]
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct PaletteEntry {
     pub id: u8,
     pub luminance: u8,
@@ -104,16 +221,24 @@ pub enum CompositionState {
     EpochStart,
 }
 
-#[derive(Debug, PartialEq, Eq, Struple)]
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct CompositionObject {
     pub id: u16,
     pub window_id: u8,
     pub horizontal_position: u16,
     pub vertical_position: u16,
+    pub forced: bool,
     pub cropped: Option<CropInfo>,
+    /// The cropped and forced flag byte as read, including its reserved bits,
+    /// so it can be written back unchanged.
+    pub raw_flags: u8,
+    /// Position of the object within its presentation composition, which
+    /// determines draw order, kept so the order can be restored after the
+    /// objects are reordered.
+    pub order: usize,
 }
 
-#[derive(Debug, PartialEq, Eq, Struple)]
+#[derive(Debug, Clone, PartialEq, Eq, Struple)]
 pub struct CropInfo {
     pub horizontal_position: u16,
     pub vertical_position: u16,
@@ -122,66 +247,591 @@ pub struct CropInfo {
 }
 
 pub fn parse_pgs<'a>(input: &'a mut [u8]) -> PgsResult<Pgs> {
-    let segments = repeat(1.., parse_segment).parse(input)?;
-    Ok(Pgs { segments })
+    let segments = parse_segments.parse(input).map_err(segment_error)?;
+    Ok(Pgs {
+        segments: assemble_object_fragments(segments)?,
+    })
+}
+
+/// Like [`parse_pgs`], but recovers from corrupt data: when a segment fails
+/// to parse, the input is scanned forward to the next `PG` magic and parsing
+/// resumes there. Returns the stream along with the byte ranges that were
+/// skipped.
+///
+/// Object definition fragments that can't be assembled are dropped rather
+/// than failing the parse: orphaned middle or last fragments, sequences cut
+/// short by a new first fragment, an end segment or the end of the input,
+/// sequences with bytes skipped between their fragments, and sequences whose
+/// data doesn't decode.
+pub fn parse_pgs_resync(input: &[u8]) -> PgsResult<(Pgs, Vec<Range<usize>>)> {
+    let mut segments = Vec::new();
+    let mut skipped: Vec<Range<usize>> = Vec::new();
+    let mut remaining = input;
+    while !remaining.is_empty() {
+        let offset = input.len() - remaining.len();
+        let mut attempt = remaining;
+        if let Ok(segment) = parse_segment_with_fragment(&mut attempt) {
+            let resynced = skipped.last().is_some_and(|range| range.end == offset);
+            segments.push((segment, resynced));
+            remaining = attempt;
+            continue;
+        }
+        let resume = remaining[1..]
+            .windows(2)
+            .position(|magic| magic == b"PG")
+            .map_or(remaining.len(), |position| position + 1);
+        match skipped.last_mut() {
+            Some(range) if range.end == offset => range.end = offset + resume,
+            _ => skipped.push(offset..offset + resume),
+        }
+        remaining = &remaining[resume..];
+    }
+    Ok((
+        Pgs {
+            segments: assemble_object_fragments(drop_broken_fragments(segments))?,
+        },
+        skipped,
+    ))
+}
+
+/// Drops the object definition fragments [`assemble_object_fragments`] would
+/// reject, given each segment along with whether bytes were skipped right
+/// before it.
+fn drop_broken_fragments(
+    segments: Vec<(SegmentWithFragment<'_>, bool)>,
+) -> Vec<SegmentWithFragment<'_>> {
+    let mut keep = vec![true; segments.len()];
+    let mut pending: HashMap<u16, Vec<usize>> = HashMap::new();
+    let mut discard = |sequence: Vec<usize>| {
+        for index in sequence {
+            keep[index] = false;
+        }
+    };
+    for (index, ((segment, _, _), resynced)) in segments.iter().enumerate() {
+        if *resynced {
+            pending.drain().for_each(|(_, sequence)| discard(sequence));
+        }
+        match &segment.contents {
+            SegmentContents::ObjectDefinition(object) => {
+                if object.last_in_sequence.is_first()
+                    && let Some(sequence) = pending.remove(&object.id)
+                {
+                    discard(sequence);
+                }
+                match object.last_in_sequence {
+                    LastInSequence::First => {
+                        pending.insert(object.id, vec![index]);
+                    }
+                    LastInSequence::Middle => match pending.get_mut(&object.id) {
+                        Some(sequence) => sequence.push(index),
+                        None => discard(vec![index]),
+                    },
+                    LastInSequence::Last => {
+                        let mut sequence = pending.remove(&object.id).unwrap_or_default();
+                        sequence.push(index);
+                        let data: Vec<u8> = sequence
+                            .iter()
+                            .flat_map(|&index| segments[index].0.1.unwrap_or_default())
+                            .copied()
+                            .collect();
+                        // A lone last fragment, or one completing a sequence
+                        // whose data doesn't decode.
+                        if sequence.len() == 1
+                            || parse_run_length_encoded_pixels.parse(&data[7..]).is_err()
+                        {
+                            discard(sequence);
+                        }
+                    }
+                    LastInSequence::FirstAndLast => {}
+                }
+            }
+            SegmentContents::End => pending.drain().for_each(|(_, sequence)| discard(sequence)),
+            _ => {}
+        }
+    }
+    pending.into_values().for_each(discard);
+    segments
+        .into_iter()
+        .zip(keep)
+        .filter_map(|((segment, _), keep)| keep.then_some(segment))
+        .collect()
+}
+
+/// Parses PGS demuxed from a Matroska subtitle track, where each block holds
+/// bare segments without the `PG` magic or timestamps. Every segment takes
+/// its PTS from the block's timestamp in milliseconds; Matroska carries no
+/// decoding timestamp, so DTS is left at 0.
+pub fn parse_pgs_mkv_blocks(blocks: &[(u64, Vec<u8>)]) -> PgsResult<Pgs> {
+    let mut segments = Vec::new();
+    for (pts_ms, block) in blocks {
+        let pts = (pts_ms.saturating_mul(PTS_CLOCK_HZ) / 1000).min(u32::MAX as u64) as u32;
+        let contents: Vec<_> = repeat(0.., parse_segment_contents).parse(&block[..])?;
+        segments.extend(contents.into_iter().map(|(contents, fragment)| {
+            (
+                Segment {
+                    pts,
+                    dts: 0,
+                    contents,
+                },
+                fragment,
+                0,
+            )
+        }));
+    }
+    Ok(Pgs {
+        segments: assemble_object_fragments(segments)?,
+    })
+}
+
+/// Decodes the run-length data of fragmented objects. Fragments split the
+/// data at arbitrary byte boundaries, so it can only be decoded once every
+/// fragment has been read. The complete data is stored on the first
+/// fragment's definition while the following fragments keep empty data.
+///
+/// Fails on a fragment that doesn't continue an object started by a first
+/// fragment, or a first fragment that isn't followed by a last one before
+/// the end of its display set.
+pub(crate) fn assemble_object_fragments<'i>(
+    segments: impl IntoIterator<Item = SegmentWithFragment<'i>>,
+) -> PgsResult<Vec<Segment>> {
+    let segments = segments.into_iter();
+    let mut pending: HashMap<u16, (usize, Vec<u8>, Vec<RawFragment>)> = HashMap::new();
+    let mut assembled: Vec<Segment> = Vec::with_capacity(segments.size_hint().0);
+    for (mut segment, fragment, padding) in segments {
+        match &mut segment.contents {
+            SegmentContents::ObjectDefinition(object) => {
+                let out_of_order = || PgsError::FragmentOutOfOrder {
+                    object_id: object.id,
+                    fragment: object.last_in_sequence.clone(),
+                };
+                if object.last_in_sequence.is_first() && pending.contains_key(&object.id) {
+                    return Err(out_of_order());
+                }
+                let fragment = fragment.unwrap_or_default();
+                let raw_fragment = RawFragment {
+                    len: fragment.len(),
+                    padding,
+                };
+                match object.last_in_sequence {
+                    LastInSequence::First => {
+                        pending.insert(
+                            object.id,
+                            (assembled.len(), fragment.to_vec(), vec![raw_fragment]),
+                        );
+                    }
+                    LastInSequence::Middle => {
+                        let (_, data, fragments) =
+                            pending.get_mut(&object.id).ok_or_else(out_of_order)?;
+                        data.extend_from_slice(fragment);
+                        fragments.push(raw_fragment);
+                    }
+                    LastInSequence::Last => {
+                        let (index, mut data, mut fragments) =
+                            pending.remove(&object.id).ok_or_else(out_of_order)?;
+                        data.extend_from_slice(fragment);
+                        fragments.push(raw_fragment);
+                        // The first fragment holds at least the declared
+                        // length and dimensions preceding the run-length data.
+                        let runs = parse_run_length_encoded_pixels.parse(&data[7..])?;
+                        if let SegmentContents::ObjectDefinition(first) =
+                            &mut assembled[index].contents
+                        {
+                            first.data = RunLengthEncodedData(runs);
+                            first.raw_data_len = Some(data.len() - 7);
+                            first.raw = Some(RawObjectData {
+                                bytes: data,
+                                fragments,
+                            });
+                        }
+                    }
+                    LastInSequence::FirstAndLast => {
+                        object.raw = Some(RawObjectData {
+                            bytes: fragment.to_vec(),
+                            fragments: vec![raw_fragment],
+                        });
+                    }
+                }
+            }
+            SegmentContents::End => {
+                if let Some(object_id) = pending.keys().min() {
+                    return Err(PgsError::IncompleteObject {
+                        object_id: *object_id,
+                    });
+                }
+            }
+            _ => {}
+        }
+        assembled.push(segment);
+    }
+    if let Some(object_id) = pending.keys().min() {
+        return Err(PgsError::IncompleteObject {
+            object_id: *object_id,
+        });
+    }
+    Ok(assembled)
+}
+
+/// Context attached when a segment doesn't start with the `PG` magic.
+pub(crate) const MAGIC_CONTEXT: StrContext = StrContext::Label("segment magic");
+
+/// Converts a failure to parse a stream of segments, reporting a segment
+/// that doesn't start with the `PG` magic as [`PgsError::InvalidMagic`].
+pub(crate) fn segment_error(e: ParseError<&[u8], ContextError>) -> PgsError {
+    let offset = e.offset();
+    let bad_magic = e.inner().context().any(|context| *context == MAGIC_CONTEXT);
+    match e.input().get(offset..offset + 2) {
+        Some(&[a, b]) if bad_magic => PgsError::InvalidMagic {
+            offset,
+            found: u16::from_be_bytes([a, b]),
+        },
+        _ => e.into(),
+    }
+}
+
+/// Stream properties declared by the first presentation composition segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probe {
+    pub width: u16,
+    pub height: u16,
+    pub frame_rate: u8,
+}
+
+/// Reads segments only until the first presentation composition segment and
+/// returns the resolution and frame rate it declares.
+pub fn probe(bytes: &[u8]) -> PgsResult<Probe> {
+    let mut input = bytes;
+    while !input.is_empty() {
+        if let Some(&[a, b]) = input.get(..2)
+            && [a, b] != *b"PG"
+        {
+            return Err(PgsError::InvalidMagic {
+                offset: bytes.len() - input.len(),
+                found: u16::from_be_bytes([a, b]),
+            });
+        }
+        let segment = parse_segment(&mut input).map_err(|e| PgsError::ParseError(e.to_string()))?;
+        if let SegmentContents::PresentationComposition(presentation_composition) = segment.contents
+        {
+            return Ok(Probe {
+                width: presentation_composition.width,
+                height: presentation_composition.height,
+                frame_rate: presentation_composition.frame_rate,
+            });
+        }
+    }
+    Err(PgsError::ParseError(
+        "no presentation composition segment found".to_string(),
+    ))
+}
+
+/// Header fields of a segment, located without parsing its body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentEntry {
+    /// Byte offset of the segment's `PG` magic within the input.
+    pub offset: usize,
+    pub segment_type: u8,
+    /// Declared length of the body, excluding the 13 byte header.
+    pub length: u16,
+    pub pts: u32,
+    pub dts: u32,
+}
+
+/// Lists the header of every segment, skipping over bodies by their declared
+/// length. Zero padding between segments is skipped as in [`parse_pgs`].
+pub fn segment_table(bytes: &[u8]) -> PgsResult<Vec<SegmentEntry>> {
+    let mut input = bytes;
+    let mut table = Vec::new();
+    while !input.is_empty() {
+        let offset = bytes.len() - input.len();
+        if let Some(&[a, b]) = input.get(..2)
+            && [a, b] != *b"PG"
+        {
+            return Err(PgsError::InvalidMagic {
+                offset,
+                found: u16::from_be_bytes([a, b]),
+            });
+        }
+        let (pts, dts, segment_type, length) =
+            skip_segment.parse_next(&mut input).map_err(|e| {
+                PgsError::ParseError(format!("truncated segment at offset {offset}: {e}"))
+            })?;
+        table.push(SegmentEntry {
+            offset,
+            segment_type,
+            length,
+            pts,
+            dts,
+        });
+    }
+    Ok(table)
+}
+
+/// Which deviations [`parse_pgs_strict`] rejects rather than tolerates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StrictOptions {
+    /// Reject composition state and object fragment bytes that match none of
+    /// the values defined by the format.
+    pub unknown_values: bool,
+    /// Reject composition state, palette update and composition object flag
+    /// bytes with any of their reserved bits set.
+    pub reserved_bits: bool,
+}
+
+impl StrictOptions {
+    /// Enables every check.
+    pub fn all() -> Self {
+        Self {
+            unknown_values: true,
+            reserved_bits: true,
+        }
+    }
+}
+
+/// Like [`parse_pgs`], but first checks every flag and enumerated byte,
+/// reporting the field, byte value and offset of the first one rejected by
+/// `options` instead of failing opaquely or coercing it.
+pub fn parse_pgs_strict(input: &mut [u8], options: &StrictOptions) -> PgsResult<Pgs> {
+    for entry in segment_table(input)? {
+        let start = entry.offset + 13;
+        let body = &input[start..start + entry.length as usize];
+        if let Some(issue) = flag_issues(&entry, body).into_iter().find(|issue| {
+            if issue.reserved_bits {
+                options.reserved_bits
+            } else {
+                options.unknown_values
+            }
+        }) {
+            return Err(PgsError::UnexpectedValue {
+                field: issue.field,
+                value: issue.value,
+                offset: issue.offset,
+            });
+        }
+    }
+    parse_pgs(input)
+}
+
+/// A flag or enumerated byte holding a value the format does not define.
+pub(crate) struct FlagIssue {
+    pub field: &'static str,
+    pub value: u8,
+    /// Byte offset of the value within the input.
+    pub offset: usize,
+    /// Whether the value is only at fault for setting reserved bits, rather
+    /// than matching none of the defined values.
+    pub reserved_bits: bool,
+}
+
+/// Lists the flag and enumerated bytes of a segment's body, in order, whose
+/// values the format does not define.
+pub(crate) fn flag_issues(entry: &SegmentEntry, body: &[u8]) -> Vec<FlagIssue> {
+    let start = entry.offset + 13;
+    let mut issues = Vec::new();
+    let mut check = |field, index: usize, reserved_bits, valid: fn(u8) -> bool| {
+        if let Some(&value) = body.get(index)
+            && !valid(value)
+        {
+            issues.push(FlagIssue {
+                field,
+                value,
+                offset: start + index,
+                reserved_bits,
+            });
+        }
+    };
+    match entry.segment_type {
+        0x16 => {
+            check("composition_state", 7, false, |v| v & 0xC0 != 0xC0);
+            check("composition_state", 7, true, |v| v & 0x3F == 0);
+            check("palette_update_flag", 8, true, |v| v & 0x7F == 0);
+            let count = body.get(10).copied().unwrap_or(0);
+            let mut index = 11;
+            for _ in 0..count {
+                check("object_cropped_flag", index + 3, true, |v| v & 0x3F == 0);
+                let cropped = body.get(index + 3).is_some_and(|flag| flag & 0x40 != 0);
+                index += if cropped { 16 } else { 8 };
+            }
+        }
+        0x15 => check("last_in_sequence", 3, false, |v| v & 0x3F == 0),
+        _ => {}
+    }
+    issues
+}
+
+/// Reads a segment's header and skips its body and any trailing padding,
+/// returning the PTS, DTS, type and declared length.
+fn skip_segment(input: &mut &[u8]) -> PResult<(u32, u32, u8, u16)> {
+    let (_, pts, dts, segment_type, length) =
+        (be_u16, be_u32, be_u32, be_u8, be_u16).parse_next(input)?;
+    take(length).void().parse_next(input)?;
+    take_while(0.., 0).void().parse_next(input)?;
+    Ok((pts, dts, segment_type, length))
 }
 
 fn parse_segment(input: &mut &[u8]) -> PResult<Segment> {
+    parse_segment_with_fragment
+        .map(|(segment, _, _)| segment)
+        .parse_next(input)
+}
+
+/// A segment along with the raw object data of an object definition
+/// fragment and the number of zero bytes of padding following it.
+pub(crate) type SegmentWithFragment<'i> = (Segment, Option<&'i [u8]>, usize);
+
+fn parse_segments<'i>(input: &mut &'i [u8]) -> PResult<Vec<SegmentWithFragment<'i>>> {
+    let mut segments = Vec::new();
+    parse_segments_into(input, &mut segments)?;
+    Ok(segments)
+}
+
+/// Parses segments until the input is exhausted, adding each to `segments`.
+/// Unlike `repeat`, a segment failing part way through its body fails the
+/// whole parse with its own error, so the context describing where it failed
+/// is kept.
+pub(crate) fn parse_segments_into<'i>(
+    input: &mut &'i [u8],
+    segments: &mut impl Extend<SegmentWithFragment<'i>>,
+) -> PResult<()> {
+    loop {
+        segments.extend([parse_segment_with_fragment(input)?]);
+        if input.is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Parses a segment, also returning the raw object data of object
+/// definition fragments, which cannot be decoded on their own.
+///
+/// Some authoring tools pad segments to an alignment boundary with zero bytes
+/// after the declared body. As a lenient extension to the format, any zero
+/// bytes following a segment are skipped, since no segment can start with
+/// one, and counted.
+fn parse_segment_with_fragment<'i>(input: &mut &'i [u8]) -> PResult<SegmentWithFragment<'i>> {
     // Verify magic number "PG" is present.
-    be_u16.verify(|&v| v == 0x5047).parse_next(input)?;
-    let (pts, dts, contents) = (
-        be_u32,
-        be_u32,
-        dispatch! {be_u8;
-            0x14 => parse_palette_definition_segment.map(|v| SegmentContents::PaletteDefinition(v)),
-            0x15 => parse_object_definition_segment.map(|v| SegmentContents::ObjectDefinition(v)),
-            0x16 => parse_presentation_composition_segment.map(|v| SegmentContents::PresentationComposition(v)),
-            0x17 => parse_window_definition_segment.map(|v| SegmentContents::WindowDefinition(v)),
-            0x80 => parse_end_of_display_set_segment.map(|_| SegmentContents::End),
-            _ => fail::<_, SegmentContents, _>,
-        },
-    )
+    be_u16
+        .verify(|&v| v == 0x5047)
+        .context(MAGIC_CONTEXT)
         .parse_next(input)?;
-    Ok(Segment { pts, dts, contents })
+    let (pts, dts, (contents, fragment)) = (be_u32, be_u32, parse_segment_contents)
+        .context(StrContext::Label("segment"))
+        .parse_next(input)?;
+    let padding = take_while(0.., 0).parse_next(input)?.len();
+    Ok((Segment { pts, dts, contents }, fragment, padding))
+}
+
+/// Parses a segment's type, length and body, i.e. everything following the
+/// magic number and timestamps.
+fn parse_segment_contents<'i>(
+    input: &mut &'i [u8],
+) -> PResult<(SegmentContents, Option<&'i [u8]>)> {
+    dispatch! {be_u8;
+        0x14 => parse_palette_definition_segment
+            .map(|v| (SegmentContents::PaletteDefinition(v), None))
+            .context(StrContext::Label("palette definition segment")),
+        0x15 => parse_object_definition_segment
+            .map(|(v, fragment)| (SegmentContents::ObjectDefinition(v), fragment))
+            .context(StrContext::Label("object definition segment")),
+        0x16 => parse_presentation_composition_segment
+            .map(|v| (SegmentContents::PresentationComposition(v), None))
+            .context(StrContext::Label("presentation composition segment")),
+        0x17 => parse_window_definition_segment
+            .map(|v| (SegmentContents::WindowDefinition(v), None))
+            .context(StrContext::Label("window definition segment")),
+        0x80 => parse_end_of_display_set_segment
+            .map(|_| (SegmentContents::End, None))
+            .context(StrContext::Label("end of display set segment")),
+        _ => fail::<_, (SegmentContents, Option<&[u8]>), _>
+            .context(StrContext::Label("segment type")),
+    }
+    .parse_next(input)
 }
 
-fn parse_end_of_display_set_segment(input: &mut &[u8]) -> PResult<()> {
+pub(crate) fn parse_end_of_display_set_segment(input: &mut &[u8]) -> PResult<()> {
     be_u16.verify(|&v| v == 0x0000).parse_next(input)?;
     Ok(())
 }
 
-fn parse_object_definition_segment(input: &mut &[u8]) -> PResult<ObjectDefinition> {
-    let (id, version, last_in_sequence, (width, height, data)) = length_and_then(
-        be_u16,
-        (
-            be_u16,
-            be_u8,
-            parse_last_in_sequence,
-            // length_and_then(be_u24.map(|l| l + 4), (be_u16, be_u16, repeat(0.., be_u8))),
-            length_and_then(be_u24, (be_u16, be_u16, parse_run_length_encoded_pixels)),
-        ),
-    )
-    .parse_next(input)?;
-    Ok(ObjectDefinition {
-        id,
-        version,
-        last_in_sequence,
-        width,
-        height,
-        data: RunLengthEncodedData(data),
-    })
+fn parse_object_definition_segment<'i>(
+    input: &mut &'i [u8],
+) -> PResult<(ObjectDefinition, Option<&'i [u8]>)> {
+    length_and_then(be_u16, parse_object_definition_body).parse_next(input)
+}
+
+/// Only the first fragment of an object carries its dimensions. The data of
+/// partial fragments is returned raw rather than decoded, as is the object
+/// data of complete objects so it can be kept alongside the decoded runs.
+fn parse_object_definition_body<'i>(
+    input: &mut &'i [u8],
+) -> PResult<(ObjectDefinition, Option<&'i [u8]>)> {
+    let (id, version, last_in_sequence) =
+        (be_u16, be_u8, parse_last_in_sequence).parse_next(input)?;
+    let (width, height, data, raw_data_len, fragment) = match last_in_sequence {
+        LastInSequence::FirstAndLast => {
+            let ((width, height, (data, raw_data)), object_data) = length_and_then(
+                be_u24,
+                (
+                    be_u16,
+                    be_u16,
+                    parse_run_length_encoded_pixels
+                        .with_taken()
+                        .context(StrContext::Label("object data")),
+                ),
+            )
+            .with_taken()
+            .parse_next(input)?;
+            (width, height, data, Some(raw_data.len()), Some(object_data))
+        }
+        LastInSequence::First => {
+            // The declared length spans every fragment, so it can't delimit
+            // this one.
+            let ((_, width, height, _), fragment) = (be_u24, be_u16, be_u16, rest)
+                .with_taken()
+                .parse_next(input)?;
+            (width, height, Vec::new(), None, Some(fragment))
+        }
+        LastInSequence::Middle | LastInSequence::Last => {
+            (0, 0, Vec::new(), None, Some(rest.parse_next(input)?))
+        }
+    };
+    Ok((
+        ObjectDefinition {
+            id,
+            version,
+            last_in_sequence,
+            width,
+            height,
+            data: RunLengthEncodedData(data),
+            raw_data_len,
+            raw: None,
+        },
+        fragment,
+    ))
 }
 
-#[derive(PartialEq, Eq, Struple)]
+#[derive(Clone, PartialEq, Eq, Struple)]
 pub struct RlEncodedPixels {
     pub count: u16,
     pub color: u8,
 }
+impl RlEncodedPixels {
+    /// Number of bytes of the shortest code representing this run.
+    pub fn encoded_len(&self) -> usize {
+        match (self.color, self.count) {
+            (0, 0..64) => 2,
+            (0, _) => 3,
+            (_, 1) => 1,
+            (_, 0..64) => 3,
+            (_, _) => 4,
+        }
+    }
+}
+
 fn parse_run_length_encoded_pixels(input: &mut &[u8]) -> PResult<Vec<RlEncodedPixels>> {
     Ok(repeat(0.., parse_single_encoded_pixel).parse_next(input)?)
 }
 
-fn parse_single_encoded_pixel(input: &mut &[u8]) -> PResult<RlEncodedPixels> {
+pub(crate) fn parse_single_encoded_pixel(input: &mut &[u8]) -> PResult<RlEncodedPixels> {
     alt((
         // Single pixel
         be_u8
@@ -222,13 +872,14 @@ fn parse_single_encoded_pixel(input: &mut &[u8]) -> PResult<RlEncodedPixels> {
     .parse_next(input)
 }
 
-fn parse_last_in_sequence(input: &mut &[u8]) -> PResult<LastInSequence> {
+pub(crate) fn parse_last_in_sequence(input: &mut &[u8]) -> PResult<LastInSequence> {
     Ok(alt((
         be_u8.verify(|v| *v == 0x40).value(LastInSequence::Last),
         be_u8.verify(|v| *v == 0x80).value(LastInSequence::First),
         be_u8
             .verify(|v| *v == 0xC0)
             .value(LastInSequence::FirstAndLast),
+        be_u8.verify(|v| *v == 0x00).value(LastInSequence::Middle),
     ))
     .parse_next(input)?)
 }
@@ -248,7 +899,7 @@ fn parse_palette_entries(input: &mut &[u8]) -> PResult<HashMap<u8, PaletteEntry>
     Ok(palette)
 }
 
-fn parse_palette_entry(input: &mut &[u8]) -> PResult<PaletteEntry> {
+pub(crate) fn parse_palette_entry(input: &mut &[u8]) -> PResult<PaletteEntry> {
     Ok(PaletteEntry::from_tuple(
         (be_u8, be_u8, be_u8, be_u8, be_u8).parse_next(input)?,
     ))
@@ -256,79 +907,631 @@ fn parse_palette_entry(input: &mut &[u8]) -> PResult<PaletteEntry> {
 
 fn parse_window_definition_segment(input: &mut &[u8]) -> PResult<WindowDefinition> {
     Ok(WindowDefinition {
-        windows: (length_and_then(be_u16, length_repeat(be_u8, parse_window))).parse_next(input)?,
+        windows: (length_and_then(
+            be_u16,
+            length_repeat(be_u8, parse_window.context(StrContext::Label("window"))),
+        ))
+        .parse_next(input)?,
     })
 }
 
-fn parse_window(input: &mut &[u8]) -> PResult<Window> {
+pub(crate) fn parse_window(input: &mut &[u8]) -> PResult<Window> {
     Ok(Window::from_tuple(
         (be_u8, be_u16, be_u16, be_u16, be_u16).parse_next(input)?,
     ))
 }
 
 fn parse_presentation_composition_segment(input: &mut &[u8]) -> PResult<PresentationComposition> {
-    Ok(PresentationComposition::from_tuple(
-        length_and_then(
+    let (
+        width,
+        height,
+        frame_rate,
+        composition_number,
+        raw_composition_state,
+        raw_palette_update_flag,
+        palette_id,
+        composition_objects,
+    ) = length_and_then(
+        be_u16,
+        (
             be_u16,
-            (
-                be_u16,
-                be_u16,
-                be_u8,
-                be_u16,
-                parse_composition_state,
-                parse_palette_update_flag,
+            be_u16,
+            be_u8,
+            be_u16,
+            be_u8,
+            be_u8,
+            be_u8,
+            length_repeat(
                 be_u8,
-                length_repeat(be_u8, parse_composition_object),
+                parse_composition_object.context(StrContext::Label("composition object")),
             ),
-        )
-        .parse_next(input)?,
-    ))
-}
-
-fn parse_composition_state(input: &mut &[u8]) -> PResult<CompositionState> {
-    Ok(alt((
-        be_u8.verify(|v| *v == 0x00).value(CompositionState::Normal),
-        be_u8
-            .verify(|v| *v == 0x40)
-            .value(CompositionState::AcquisitionPoint),
-        be_u8
-            .verify(|v| *v == 0x80)
-            .value(CompositionState::EpochStart),
-    ))
-    .parse_next(input)?)
+        ),
+    )
+    .parse_next(input)?;
+    let mut composition = PresentationComposition {
+        width,
+        height,
+        frame_rate,
+        composition_number,
+        composition_state: composition_state(raw_composition_state),
+        raw_composition_state,
+        palette_update: raw_palette_update_flag & 0x80 != 0,
+        raw_palette_update_flag,
+        palette_id,
+        composition_objects,
+    };
+    for (order, composition_object) in composition.composition_objects.iter_mut().enumerate() {
+        composition_object.order = order;
+    }
+    Ok(composition)
 }
 
-fn parse_palette_update_flag(input: &mut &[u8]) -> PResult<bool> {
-    Ok(alt((
-        be_u8.verify(|v| *v == 0x00).value(false),
-        be_u8.verify(|v| *v == 0x80).value(true),
-    ))
-    .parse_next(input)?)
+/// Decodes a composition state byte from its two high bits. Bit 7 marks an
+/// epoch start, even with bit 6 also set, and bit 6 alone an acquisition
+/// point.
+pub(crate) fn composition_state(raw: u8) -> CompositionState {
+    if raw & 0x80 != 0 {
+        CompositionState::EpochStart
+    } else if raw & 0x40 != 0 {
+        CompositionState::AcquisitionPoint
+    } else {
+        CompositionState::Normal
+    }
 }
 
-fn parse_composition_object(input: &mut &[u8]) -> PResult<CompositionObject> {
-    let (id, window_id, cropped, horizontal_position, vertical_position) =
-        (be_u16, be_u8, parse_object_cropped_flag, be_u16, be_u16).parse_next(input)?;
-    if !cropped {
-        return Ok(CompositionObject {
-            id,
-            window_id,
-            horizontal_position,
-            vertical_position,
-            cropped: None,
-        });
-    }
-    let crop_info = CropInfo::from_tuple((be_u16, be_u16, be_u16, be_u16).parse_next(input)?);
+pub(crate) fn parse_composition_object(input: &mut &[u8]) -> PResult<CompositionObject> {
+    let (id, window_id, raw_flags, horizontal_position, vertical_position) =
+        (be_u16, be_u8, be_u8, be_u16, be_u16).parse_next(input)?;
+    let cropped = if raw_flags & 0x40 != 0 {
+        Some(CropInfo::from_tuple(
+            (be_u16, be_u16, be_u16, be_u16).parse_next(input)?,
+        ))
+    } else {
+        None
+    };
     Ok(CompositionObject {
         id,
         window_id,
         horizontal_position,
         vertical_position,
-        cropped: Some(crop_info),
+        forced: raw_flags & 0x80 != 0,
+        cropped,
+        raw_flags,
+        order: 0,
     })
 }
 
-fn parse_object_cropped_flag(input: &mut &[u8]) -> PResult<bool> {
-    let flag = be_u8.parse_next(input)?;
-    Ok(flag == 0x40)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        PALETTE, clear, cue, end, object_fragments, ods, pcs, pds, rle, segment, wds,
+    };
+
+    fn object(pgs: &Pgs) -> &ObjectDefinition {
+        pgs.segments
+            .iter()
+            .find_map(|segment| match &segment.contents {
+                SegmentContents::ObjectDefinition(object) => Some(object),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn line_ranges_cover_every_line_once() {
+        let indices = [1, 1, 2, 2, 0, 0, 0, 0, 3, 1, 3, 1];
+        let pgs = parse_pgs(&mut cue(0, (64, 32), (0, 0), 4, &indices)).unwrap();
+        let data = &object(&pgs).data;
+
+        let ranges = data.line_ranges();
+        assert_eq!(ranges.len(), 3);
+        let mut covered = vec![0; data.0.len()];
+        for range in &ranges {
+            let pixels: u32 = data.0[range.clone()]
+                .iter()
+                .map(|run| run.count as u32)
+                .sum();
+            assert_eq!(pixels, 4);
+            for index in range.clone() {
+                covered[index] += 1;
+            }
+        }
+        for (run, covered) in data.0.iter().zip(covered) {
+            assert_eq!(covered, if run.count == 0 { 0 } else { 1 });
+        }
+    }
+
+    fn palette_update_flags(pgs: &Pgs) -> Vec<(bool, u8)> {
+        pgs.segments
+            .iter()
+            .filter_map(|segment| match &segment.contents {
+                SegmentContents::PresentationComposition(composition) => Some((
+                    composition.palette_update,
+                    composition.raw_palette_update_flag,
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_bit_7_of_the_palette_update_flag_counts() {
+        let mut data = [
+            clear(0, (64, 32)),
+            pcs(9_000, (64, 32), 1, 0x00, 0x81, &[]),
+            end(9_000),
+            pcs(18_000, (64, 32), 2, 0x00, 0x01, &[]),
+            end(18_000),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        assert_eq!(
+            palette_update_flags(&pgs),
+            [(false, 0x00), (true, 0x81), (false, 0x01)]
+        );
+    }
+
+    #[test]
+    fn only_the_high_bits_of_the_composition_state_count() {
+        let mut data = [0x81, 0x41, 0x01, 0xC0]
+            .iter()
+            .enumerate()
+            .flat_map(|(index, &state)| {
+                let pts = index as u32 * 9_000;
+                [pcs(pts, (64, 32), index as u16, state, 0, &[]), end(pts)].concat()
+            })
+            .collect::<Vec<_>>();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let states: Vec<_> = pgs
+            .segments
+            .iter()
+            .filter_map(|segment| match &segment.contents {
+                SegmentContents::PresentationComposition(composition) => Some((
+                    composition.composition_state,
+                    composition.raw_composition_state,
+                )),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            states,
+            [
+                (CompositionState::EpochStart, 0x81),
+                (CompositionState::AcquisitionPoint, 0x41),
+                (CompositionState::Normal, 0x01),
+                (CompositionState::EpochStart, 0xC0),
+            ]
+        );
+    }
+
+    #[test]
+    fn probe_stops_after_the_first_composition() {
+        let mut data = pcs(0, (1920, 1080), 0, 0x80, 0, &[]);
+        data.extend_from_slice(b"not a segment");
+
+        assert_eq!(
+            probe(&data).unwrap(),
+            Probe {
+                width: 1920,
+                height: 1080,
+                frame_rate: 0x10,
+            }
+        );
+        assert!(parse_pgs(&mut data).is_err());
+    }
+
+    #[test]
+    fn iter_segments_yields_every_segment() {
+        let mut data = [cue(0, (64, 32), (0, 0), 2, &[1; 4]), clear(9_000, (64, 32))].concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        let mut counts = HashMap::new();
+        for segment in pgs.iter_segments() {
+            let kind = match segment.contents {
+                SegmentContents::PresentationComposition(_) => "PCS",
+                SegmentContents::WindowDefinition(_) => "WDS",
+                SegmentContents::PaletteDefinition(_) => "PDS",
+                SegmentContents::ObjectDefinition(_) => "ODS",
+                SegmentContents::End => "END",
+            };
+            *counts.entry(kind).or_insert(0) += 1;
+        }
+        assert_eq!(
+            counts,
+            HashMap::from([("PCS", 2), ("WDS", 1), ("PDS", 1), ("ODS", 1), ("END", 2)])
+        );
+    }
+
+    /// An object definition fragment of object 1 with the given sequence flag
+    /// and raw body following it.
+    fn fragment(flag: u8, data: &[u8]) -> Vec<u8> {
+        segment(0, 0x15, &[&[0, 1, 0, flag], data].concat())
+    }
+
+    #[test]
+    fn fragments_are_assembled_in_order() {
+        // A 2x2 object of color 1, split into first, middle and last
+        // fragments.
+        let data = [0, 0x82, 1, 0, 0].repeat(2);
+        let mut stream = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[]),
+            object_fragments(0, 1, (2, 2), &data, &[8, 12]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut stream).unwrap();
+        let kinds: Vec<&LastInSequence> = pgs
+            .iter_segments()
+            .filter_map(|segment| match &segment.contents {
+                SegmentContents::ObjectDefinition(object) => Some(&object.last_in_sequence),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                &LastInSequence::First,
+                &LastInSequence::Middle,
+                &LastInSequence::Last
+            ]
+        );
+        assert_eq!(object(&pgs).decode_indexed(), [1; 4]);
+    }
+
+    #[test]
+    fn fragments_out_of_order_are_rejected() {
+        let first = fragment(0x80, &[0, 0, 10, 0, 2, 0, 2, 0, 0x82, 1]);
+        let middle = fragment(0x00, &[0, 0]);
+        let last = fragment(0x40, &[0, 0x82, 1, 0, 0]);
+        let parse = |fragments: &[&Vec<u8>]| {
+            let mut stream = pcs(0, (64, 32), 0, 0x80, 0, &[]);
+            stream.extend(fragments.iter().flat_map(|fragment| fragment.iter()));
+            stream.extend(end(0));
+            parse_pgs(&mut stream)
+        };
+
+        assert!(parse(&[&first, &middle, &last]).is_ok());
+        assert!(matches!(
+            parse(&[&last]),
+            Err(PgsError::FragmentOutOfOrder {
+                object_id: 1,
+                fragment: LastInSequence::Last,
+            })
+        ));
+        assert!(matches!(
+            parse(&[&middle, &last]),
+            Err(PgsError::FragmentOutOfOrder {
+                object_id: 1,
+                fragment: LastInSequence::Middle,
+            })
+        ));
+        assert!(matches!(
+            parse(&[&first, &first, &last]),
+            Err(PgsError::FragmentOutOfOrder {
+                object_id: 1,
+                fragment: LastInSequence::First,
+            })
+        ));
+        assert!(matches!(
+            parse(&[&first, &middle]),
+            Err(PgsError::IncompleteObject { object_id: 1 })
+        ));
+    }
+
+    #[test]
+    fn mkv_blocks_take_their_timestamps_from_the_block() {
+        // Matroska blocks hold segments without the magic and timestamps.
+        let bare = |segments: &[Vec<u8>]| -> Vec<u8> {
+            segments
+                .iter()
+                .flat_map(|segment| segment[10..].to_vec())
+                .collect()
+        };
+        let blocks = [
+            (
+                1_500,
+                bare(&[
+                    pcs(0, (64, 32), 0, 0x80, 0, &[(0, 0, 0, 0)]),
+                    wds(0, &[(0, 0, 0, 2, 2)]),
+                    pds(0, 0, &PALETTE),
+                    ods(0, 0, 2, &[1; 4]),
+                    end(0),
+                ]),
+            ),
+            (4_000, bare(&[pcs(0, (64, 32), 1, 0x00, 0, &[]), end(0)])),
+        ];
+        let pgs = parse_pgs_mkv_blocks(&blocks).unwrap();
+
+        let timestamps: Vec<(u32, u32)> = pgs
+            .iter_segments()
+            .map(|segment| (segment.pts, segment.dts))
+            .collect();
+        assert_eq!(
+            timestamps,
+            [[(135_000, 0); 5].as_slice(), &[(360_000, 0); 2]].concat()
+        );
+        assert_eq!(object(&pgs).decode_indexed(), [1; 4]);
+    }
+
+    #[test]
+    fn missing_magic_is_reported_with_its_offset() {
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F'];
+        let error = parse_pgs(&mut jpeg).unwrap_err();
+        assert!(matches!(
+            error,
+            PgsError::InvalidMagic {
+                offset: 0,
+                found: 0xFFD8
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Expected PGS segment magic 'PG' (0x5047) at offset 0, found 0xFFD8"
+        );
+
+        let mut data = [end(0), b"XX-trailing".to_vec()].concat();
+        assert!(matches!(
+            parse_pgs(&mut data),
+            Err(PgsError::InvalidMagic {
+                offset: 13,
+                found: 0x5858
+            })
+        ));
+    }
+
+    #[test]
+    fn corrupt_fragment_data_is_not_a_magic_error() {
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[]),
+            object_fragments(0, 1, (2, 2), &[1, 0], &[8]),
+            end(0),
+        ]
+        .concat();
+        assert!(matches!(parse_pgs(&mut data), Err(PgsError::ParseError(_))));
+    }
+
+    #[test]
+    fn zero_padding_between_segments_is_skipped() {
+        let segments = [pcs(0, (64, 32), 0, 0x80, 0, &[]), end(0)];
+        let padded = [&segments[0][..], &[0; 3], &segments[1], &[0; 3]].concat();
+        let pgs = parse_pgs(&mut padded.clone()).unwrap();
+        let unpadded = parse_pgs(&mut segments.concat()).unwrap();
+        assert_eq!(pgs.segments, unpadded.segments);
+
+        let offsets: Vec<usize> = segment_table(&padded)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.offset)
+            .collect();
+        assert_eq!(offsets, [0, segments[0].len() + 3]);
+    }
+
+    #[test]
+    fn oversized_object_length_is_an_error() {
+        // The object data declares the largest 24-bit length but holds two
+        // bytes.
+        let body = [0, 1, 0, 0xC0, 0xFF, 0xFF, 0xFF, 0, 2, 0, 1, 1, 1];
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[]),
+            segment(0, 0x15, &body),
+            end(0),
+        ]
+        .concat();
+        assert!(parse_pgs(&mut data).is_err());
+
+        // A segment declaring more body than the input holds.
+        let mut truncated = end(0);
+        truncated[11..13].copy_from_slice(&0xFFFFu16.to_be_bytes());
+        assert!(parse_pgs(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn segment_table_lists_every_header() {
+        let mut bytes = cue(900, (8, 4), (2, 1), 2, &[1, 3, 3, 1]);
+        bytes.extend([0; 3]);
+        bytes.extend(clear(1800, (8, 4)));
+
+        let entry = |offset, segment_type, length, pts| SegmentEntry {
+            offset,
+            segment_type,
+            length,
+            pts,
+            dts: pts,
+        };
+        assert_eq!(
+            segment_table(&bytes).unwrap(),
+            vec![
+                entry(0, 0x16, 19, 900),
+                entry(32, 0x17, 10, 900),
+                entry(55, 0x14, 22, 900),
+                entry(90, 0x15, 19, 900),
+                entry(122, 0x80, 0, 900),
+                entry(138, 0x16, 11, 1800),
+                entry(162, 0x80, 0, 1800),
+            ]
+        );
+    }
+
+    /// The field, value and offset of the error `parse_pgs_strict` reports
+    /// with every check enabled.
+    fn strict_error(bytes: &[u8]) -> (&'static str, u8, usize) {
+        match parse_pgs_strict(&mut bytes.to_vec(), &StrictOptions::all()) {
+            Err(PgsError::UnexpectedValue {
+                field,
+                value,
+                offset,
+            }) => (field, value, offset),
+            other => panic!("expected an unexpected value error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn strict_parse_names_each_unexpected_field() {
+        let stream = cue(0, (8, 4), (2, 1), 2, &[1, 3, 3, 1]);
+        assert!(parse_pgs_strict(&mut stream.clone(), &StrictOptions::all()).is_ok());
+        let with_byte = |offset: usize, value: u8| {
+            let mut bytes = stream.clone();
+            bytes[offset] = value;
+            bytes
+        };
+
+        // The composition's body starts after its 13 byte header, and the
+        // object definition's at byte 90 + 13.
+        assert_eq!(
+            strict_error(&with_byte(13 + 7, 0x20)),
+            ("composition_state", 0x20, 20)
+        );
+        assert_eq!(
+            strict_error(&with_byte(13 + 8, 0x81)),
+            ("palette_update_flag", 0x81, 21)
+        );
+        assert_eq!(
+            strict_error(&with_byte(13 + 11 + 3, 0x01)),
+            ("object_cropped_flag", 0x01, 27)
+        );
+        assert_eq!(
+            strict_error(&with_byte(103 + 3, 0xE0)),
+            ("last_in_sequence", 0xE0, 106)
+        );
+
+        // Reserved bits are tolerated unless checked.
+        let values_only = StrictOptions {
+            unknown_values: true,
+            reserved_bits: false,
+        };
+        assert!(parse_pgs_strict(&mut with_byte(13 + 8, 0x81), &values_only).is_ok());
+        assert!(parse_pgs_strict(&mut with_byte(13 + 7, 0x81), &values_only).is_ok());
+        assert!(matches!(
+            parse_pgs_strict(&mut with_byte(13 + 7, 0xC0), &values_only),
+            Err(PgsError::UnexpectedValue {
+                field: "composition_state",
+                value: 0xC0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn resync_recovers_the_segment_after_garbage() {
+        let first = cue(0, (8, 4), (2, 1), 2, &[1, 3, 3, 1]);
+        let second = clear(9_000, (8, 4));
+        // Garbage including a lone `P`, so the scan can't stop at it.
+        let garbage = b"\xFFP\x01garbage";
+        let data = [&first[..], garbage, &second[..]].concat();
+
+        assert!(parse_pgs(&mut data.clone()).is_err());
+        let (pgs, skipped) = parse_pgs_resync(&data).unwrap();
+        let expected = parse_pgs(&mut [first.clone(), second].concat()).unwrap();
+        assert_eq!(pgs, expected);
+        assert_eq!(skipped, vec![first.len()..first.len() + garbage.len()]);
+    }
+
+    #[test]
+    fn resync_drops_an_object_whose_middle_fragment_is_overwritten() {
+        let data = rle(4, &[1, 3, 3, 1]);
+        let fragments = object_fragments(0, 0, (4, 1), &data, &[7, 9]);
+        // The middle fragment's segment carries 2 bytes of object data.
+        let middle = 13 + 4 + 7..13 + 4 + 7 + 13 + 4 + 2;
+        let mut garbled = fragments.clone();
+        garbled[middle.clone()].fill(0xFF);
+        let first = |fragments: Vec<u8>| {
+            [
+                pcs(0, (8, 4), 0, 0x80, 0, &[(0, 0, 2, 1)]),
+                wds(0, &[(0, 2, 1, 4, 1)]),
+                pds(0, 0, &PALETTE),
+                fragments,
+                end(0),
+            ]
+            .concat()
+        };
+        let second = cue(9_000, (8, 4), (2, 1), 2, &[3, 1, 1, 3]);
+        let input = [first(garbled), second.clone()].concat();
+
+        assert!(parse_pgs(&mut input.clone()).is_err());
+        let (pgs, skipped) = parse_pgs_resync(&input).unwrap();
+        let expected = parse_pgs(&mut [first(Vec::new()), second].concat()).unwrap();
+        assert_eq!(pgs, expected);
+        let offset = first(Vec::new()).len() - 13;
+        assert_eq!(skipped, vec![offset + middle.start..offset + middle.end]);
+    }
+
+    #[test]
+    fn failing_palette_parse_mentions_the_palette() {
+        // A palette definition ending after its id, before its version.
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[]),
+            segment(0, 0x14, &[0]),
+            end(0),
+        ]
+        .concat();
+
+        let Err(PgsError::ParseError(message)) = parse_pgs(&mut data) else {
+            panic!("expected a parse error");
+        };
+        assert!(message.contains("palette"), "{message}");
+    }
+
+    #[test]
+    fn composition_objects_record_their_declaration_order() {
+        let mut data = [
+            pcs(
+                0,
+                (64, 32),
+                0,
+                0x80,
+                0,
+                &[(7, 0, 0, 0), (2, 0, 4, 0), (5, 0, 8, 0)],
+            ),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let SegmentContents::PresentationComposition(composition) = &pgs.segments[0].contents
+        else {
+            panic!("expected a presentation composition");
+        };
+        let mut objects = composition.composition_objects.clone();
+        let order = |objects: &[CompositionObject]| -> Vec<(u16, usize)> {
+            objects
+                .iter()
+                .map(|object| (object.id, object.order))
+                .collect()
+        };
+        assert_eq!(order(&objects), [(7, 0), (2, 1), (5, 2)]);
+
+        objects.sort_by_key(|object| object.id);
+        objects.sort_by_key(|object| object.order);
+        assert_eq!(objects, composition.composition_objects);
+    }
+
+    #[test]
+    fn fragment_predicates_cover_every_variant() {
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[]),
+            ods(0, 0, 2, &[1; 4]),
+            object_fragments(0, 1, (2, 2), &rle(2, &[1, 3, 3, 1]), &[8, 10]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let predicates: Vec<(LastInSequence, bool, bool)> = pgs
+            .segments
+            .iter()
+            .filter_map(|segment| match &segment.contents {
+                SegmentContents::ObjectDefinition(object) => Some(&object.last_in_sequence),
+                _ => None,
+            })
+            .map(|fragment| (fragment.clone(), fragment.is_first(), fragment.is_last()))
+            .collect();
+        assert_eq!(
+            predicates,
+            [
+                (LastInSequence::FirstAndLast, true, true),
+                (LastInSequence::First, true, false),
+                (LastInSequence::Middle, false, false),
+                (LastInSequence::Last, false, true),
+            ]
+        );
+    }
 }
@@ -1,16 +1,22 @@
 use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
 
+#[cfg(feature = "image")]
+use image::{RgbaImage, imageops};
 use yuv::{YuvPackedImage, YuvRange, YuvStandardMatrix};
 
 use crate::{
     error::{PgsError, PgsResult},
     parse::{
-        CompositionObject, CompositionState, ObjectDefinition, PaletteDefinition, Pgs,
-        SegmentContents, Window,
+        CompositionObject, CompositionState, LastInSequence, ObjectDefinition, PaletteDefinition,
+        PaletteEntry, Pgs, SegmentContents, Window,
     },
+    timing::{PTS_CLOCK_HZ, pts_to_duration},
 };
 
-const PIXEL_SIZE: usize = 4;
+pub(crate) const PIXEL_SIZE: usize = 4;
 
 // type MutableImage<'a> = YuvPackedImageMut<'a, u8>;
 // type Image<'a> = YuvPackedImage<'a, u8>;
@@ -32,10 +38,86 @@ pub struct DisplaySet<'a> {
     pub objects: HashMap<u16, &'a ObjectDefinition>,
 }
 
+/// A composition object together with the definitions it refers to. Any
+/// reference that can't be resolved in the display set is `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedObject<'a> {
+    pub composition_object: &'a CompositionObject,
+    pub window: Option<&'a Window>,
+    pub palette: Option<&'a PaletteDefinition>,
+    pub object: Option<&'a ObjectDefinition>,
+}
+
 impl<'a> DisplaySet<'a> {
+    pub fn composition_objects(&self) -> &'a [CompositionObject] {
+        self.composition_objects
+    }
+
+    /// Every window defined in the epoch so far, in no particular order.
+    pub fn windows(&self) -> impl Iterator<Item = &'a Window> + '_ {
+        self.windows.values().copied()
+    }
+
+    /// Every palette defined in the epoch so far, in no particular order.
+    pub fn palettes(&self) -> impl Iterator<Item = &'a PaletteDefinition> + '_ {
+        self.palettes.values().copied()
+    }
+
+    /// Every object defined in the epoch so far, in no particular order.
+    pub fn objects(&self) -> impl Iterator<Item = &'a ObjectDefinition> + '_ {
+        self.objects.values().copied()
+    }
+
+    pub fn window(&self, id: u8) -> Option<&'a Window> {
+        self.windows.get(&id).copied()
+    }
+
+    pub fn palette(&self, id: u8) -> Option<&'a PaletteDefinition> {
+        self.palettes.get(&id).copied()
+    }
+
+    pub fn object(&self, id: u16) -> Option<&'a ObjectDefinition> {
+        self.objects.get(&id).copied()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.composition_objects.is_empty()
     }
+
+    /// Converts the palette used for rendering into a 256-entry RGBA lookup
+    /// table indexed by palette entry id. Undefined entries are fully
+    /// transparent black.
+    pub fn palette_lut(&self) -> PgsResult<[u8; 256 * PIXEL_SIZE]> {
+        let palette = PaletteTable::new(self);
+        let ayuv: Vec<u8> = palette
+            .0
+            .iter()
+            .flat_map(|entry| entry.unwrap_or_default())
+            .collect();
+        let rgba = convert_to_rgba(&ayuv, 256, 1, YuvRange::Full)?;
+        let mut lut = [0u8; 256 * PIXEL_SIZE];
+        for (index, entry) in palette.0.iter().enumerate() {
+            if entry.is_some() {
+                let range = index * PIXEL_SIZE..(index + 1) * PIXEL_SIZE;
+                lut[range.clone()].copy_from_slice(&rgba[range]);
+            }
+        }
+        Ok(lut)
+    }
+
+    /// Resolves the window, active palette and object definition of every
+    /// composition object, in composition order.
+    pub fn resolved_objects(&self) -> Vec<ResolvedObject<'a>> {
+        self.composition_objects
+            .iter()
+            .map(|composition_object| ResolvedObject {
+                composition_object,
+                window: self.windows.get(&composition_object.window_id).copied(),
+                palette: self.palettes.get(&self.palette_id).copied(),
+                object: self.objects.get(&composition_object.id).copied(),
+            })
+            .collect()
+    }
 }
 
 pub struct DisplaySetIterator<'a> {
@@ -55,11 +137,44 @@ impl<'a> DisplaySetIterator<'a> {
             objects: HashMap::new(),
         }
     }
+
+    /// Starts reading display sets at `segment_index`, e.g. a seek point
+    /// from [`crate::seek::SeekIndex`], instead of the start of the stream.
+    /// Definitions from earlier segments are not known, so the segment
+    /// should start an epoch or an acquisition point.
+    pub fn starting_at(pgs: &'a Pgs, segment_index: usize) -> Self {
+        Self {
+            index: segment_index,
+            ..Self::new(pgs)
+        }
+    }
 }
 
-impl<'a> Iterator for DisplaySetIterator<'a> {
-    type Item = DisplaySet<'a>;
-    fn next(&mut self) -> Option<Self::Item> {
+impl<'a> DisplaySetIterator<'a> {
+    /// Yields only display sets that show something. Empty display sets are
+    /// still read, so the definitions they carry are applied to the ones
+    /// that follow.
+    pub fn skip_empty(self) -> impl Iterator<Item = DisplaySet<'a>> {
+        self.filter(|display_set| !display_set.is_empty())
+    }
+
+    /// Like [`Iterator::next`], but reports object fragments that arrive out
+    /// of order or are never completed instead of silently dropping the
+    /// affected object, as well as segments whose timestamps disagree with
+    /// the composition, display sets missing their end segment and segments
+    /// outside of any display set.
+    pub fn try_next(&mut self) -> Option<PgsResult<DisplaySet<'a>>> {
+        self.next_display_set()
+            .map(|display_set| match display_set {
+                Ok((display_set, None)) => Ok(display_set),
+                Ok((_, Some(error))) | Err(error) => Err(error),
+            })
+    }
+
+    /// Reads the next display set along with the first problem found in it.
+    /// Fails if the next segment isn't a presentation composition, after
+    /// skipping every segment up to the start of the next display set.
+    fn next_display_set(&mut self) -> Option<PgsResult<(DisplaySet<'a>, Option<PgsError>)>> {
         if self.index >= self.pgs.segments.len() {
             return None;
         }
@@ -70,7 +185,21 @@ impl<'a> Iterator for DisplaySetIterator<'a> {
             SegmentContents::PresentationComposition(presentation_composition) => {
                 presentation_composition
             }
-            _ => return None,
+            _ => {
+                while let Some(segment) = self.pgs.segments.get(self.index) {
+                    match segment.contents {
+                        SegmentContents::PresentationComposition(_) => break,
+                        SegmentContents::End => {
+                            self.index += 1;
+                            break;
+                        }
+                        _ => self.index += 1,
+                    }
+                }
+                return Some(Err(PgsError::MissingComposition {
+                    segment: start_index,
+                }));
+            }
         };
         self.index += 1;
 
@@ -95,19 +224,37 @@ impl<'a> Iterator for DisplaySetIterator<'a> {
             palettes: self.palettes.clone(),
             objects: self.objects.clone(),
         };
+        // First fragments of objects whose last fragment hasn't been seen yet.
+        // The parser stores the complete data on the first fragment.
+        let mut fragments: HashMap<u16, &'a ObjectDefinition> = HashMap::new();
+        let mut error = None;
         loop {
-            if self.index >= self.pgs.segments.len() {
-                return None;
-            }
-            if self.pgs.segments[self.index].pts != presentation_timestamp {
-                panic!("Presentation timestamp mismatch");
-            }
-            if self.pgs.segments[self.index].dts != decoding_timestamp {
-                panic!("Decoding timestamp mismatch");
+            let Some(segment) = self.pgs.segments.get(self.index) else {
+                // The stream ends before the display set's end segment.
+                error.get_or_insert(PgsError::UnterminatedDisplaySet {
+                    segment: start_index,
+                });
+                self.windows = display_set.windows.clone();
+                self.palettes = display_set.palettes.clone();
+                self.objects = display_set.objects.clone();
+                return Some(Ok((display_set, error)));
+            };
+            if segment.pts != presentation_timestamp || segment.dts != decoding_timestamp {
+                error.get_or_insert(PgsError::TimestampMismatch {
+                    segment: self.index,
+                });
             }
-            match &self.pgs.segments[self.index].contents {
+            match &segment.contents {
                 SegmentContents::PresentationComposition(_) => {
-                    panic!("Presentation composition in the middle of a display set")
+                    // The end segment is missing; the composition starts the
+                    // next display set.
+                    error.get_or_insert(PgsError::UnterminatedDisplaySet {
+                        segment: start_index,
+                    });
+                    self.windows = display_set.windows.clone();
+                    self.palettes = display_set.palettes.clone();
+                    self.objects = display_set.objects.clone();
+                    return Some(Ok((display_set, error)));
                 }
                 SegmentContents::WindowDefinition(window_definition) => {
                     for window in &window_definition.windows {
@@ -120,13 +267,44 @@ impl<'a> Iterator for DisplaySetIterator<'a> {
                         .insert(palette_definition.id, palette_definition);
                 }
                 SegmentContents::ObjectDefinition(object_definition) => {
-                    display_set
-                        .objects
-                        .insert(object_definition.id, object_definition);
+                    let id = object_definition.id;
+                    match (
+                        &object_definition.last_in_sequence,
+                        fragments.contains_key(&id),
+                    ) {
+                        (LastInSequence::FirstAndLast, false) => {
+                            display_set.objects.insert(id, object_definition);
+                        }
+                        (LastInSequence::First, false) => {
+                            fragments.insert(id, object_definition);
+                        }
+                        (LastInSequence::Middle, true) => {}
+                        (LastInSequence::Last, true) => {
+                            if let Some(first) = fragments.remove(&id) {
+                                display_set.objects.insert(id, first);
+                            }
+                        }
+                        (fragment, _) => {
+                            fragments.remove(&id);
+                            error.get_or_insert(PgsError::FragmentOutOfOrder {
+                                object_id: id,
+                                fragment: fragment.clone(),
+                            });
+                        }
+                    }
                 }
                 SegmentContents::End => {
                     self.index += 1;
-                    return Some(display_set);
+                    if let Some(object_id) = fragments.keys().min() {
+                        error.get_or_insert(PgsError::IncompleteObject {
+                            object_id: *object_id,
+                        });
+                    }
+                    // Definitions persist until the next epoch start.
+                    self.windows = display_set.windows.clone();
+                    self.palettes = display_set.palettes.clone();
+                    self.objects = display_set.objects.clone();
+                    return Some(Ok((display_set, error)));
                 }
             }
             self.index += 1;
@@ -134,16 +312,251 @@ impl<'a> Iterator for DisplaySetIterator<'a> {
     }
 }
 
+impl<'a> Iterator for DisplaySetIterator<'a> {
+    type Item = DisplaySet<'a>;
+    /// Objects with malformed fragment sequences are left out of the display
+    /// set and segments outside of any display set are skipped; use
+    /// [`DisplaySetIterator::try_next`] to have them reported.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Ok((display_set, _)) = self.next_display_set()? {
+                return Some(display_set);
+            }
+        }
+    }
+}
+
 pub fn get_display_sets<'a>(pgs: &'a Pgs) -> impl Iterator<Item = DisplaySet<'a>> {
     return DisplaySetIterator::new(pgs);
 }
 
+/// A display set tagged with whether it is the last one of its epoch.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EpochDisplaySet<'a> {
+    pub display_set: DisplaySet<'a>,
+    /// Nothing follows, or the next display set starts a new epoch, so the
+    /// definitions accumulated for this epoch can be freed after it.
+    pub is_last_in_epoch: bool,
+}
+
+impl Pgs {
+    /// Every display set, tagged with whether it ends its epoch.
+    pub fn display_sets_by_epoch(&self) -> impl Iterator<Item = EpochDisplaySet<'_>> {
+        let mut display_sets = DisplaySetIterator::new(self).peekable();
+        std::iter::from_fn(move || {
+            let display_set = display_sets.next()?;
+            let is_last_in_epoch = display_sets
+                .peek()
+                .is_none_or(|next| next.composition_state == CompositionState::EpochStart);
+            Some(EpochDisplaySet {
+                display_set,
+                is_last_in_epoch,
+            })
+        })
+    }
+
+    /// Whether the display set at `display_set_index` is the last of its
+    /// epoch. Returns `false` if there is no such display set.
+    pub fn is_epoch_boundary(&self, display_set_index: usize) -> bool {
+        self.display_sets_by_epoch()
+            .nth(display_set_index)
+            .is_some_and(|tagged| tagged.is_last_in_epoch)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Keep a 1px ring just outside a crop rectangle at half alpha instead of
+    /// cutting it off, softening the hard edge left by cropping.
+    pub feather_crop_edges: bool,
+    /// Surround the subtitle with an outline to keep it legible over bright
+    /// video.
+    pub outline: Option<Outline>,
+    /// Range the palette's YCbCr values are interpreted in when converting
+    /// to RGB. Defaults to full range.
+    pub output_range: YuvRange,
+    /// Clamp the converted RGB channels to the TV range of 16 to 235, for
+    /// subtitles composited before a limited range encode.
+    pub clamp_tv_range: bool,
+    /// Mirroring applied to each object within its own bounds.
+    pub transform: ObjectTransform,
+    /// Gamma applied to the RGB channels after conversion, as
+    /// `255 * (value / 255) ^ (1 / gamma)`. Values above 1.0 brighten the
+    /// subtitle; alpha is left untouched. Defaults to 1.0.
+    pub gamma: f32,
+    /// Draw a 1px [`WINDOW_OUTLINE_COLOR`] rectangle along the inside of
+    /// each window and a [`CROP_OUTLINE_COLOR`] one along each composition
+    /// object's crop rectangle over the rendered subtitle, for checking
+    /// placement.
+    pub debug_outlines: bool,
+}
+
+/// RGBA color of window boundaries drawn by [`RenderOptions::debug_outlines`].
+pub const WINDOW_OUTLINE_COLOR: [u8; PIXEL_SIZE] = [255, 0, 255, 255];
+/// RGBA color of crop boundaries drawn by [`RenderOptions::debug_outlines`].
+pub const CROP_OUTLINE_COLOR: [u8; PIXEL_SIZE] = [0, 255, 255, 255];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectTransform {
+    #[default]
+    None,
+    FlipH,
+    FlipV,
+    Rotate180,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            feather_crop_edges: false,
+            outline: None,
+            output_range: YuvRange::Full,
+            clamp_tv_range: false,
+            transform: ObjectTransform::None,
+            gamma: 1.0,
+            debug_outlines: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outline {
+    /// RGBA color of the outline.
+    pub color: [u8; PIXEL_SIZE],
+    /// Distance in pixels the outline extends past the subtitle's edge, at
+    /// most [`MAX_OUTLINE_THICKNESS`]; thicker outlines are drawn at that
+    /// thickness.
+    pub thickness: u8,
+}
+
+/// The thickest outline [`Outline`] draws.
+pub const MAX_OUTLINE_THICKNESS: u8 = 32;
+
+/// An area of the canvas in pixels, which may extend past its edges.
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
 pub fn render_display_set(display_set: &DisplaySet) -> PgsResult<Vec<u8>> {
-    let width = display_set.width as usize;
-    let height = display_set.height as usize;
-    let stride = width * PIXEL_SIZE;
-    let mut buf = vec![0u8; stride * height];
+    render_display_set_with_options(display_set, &RenderOptions::default())
+}
+
+pub fn render_display_set_with_options(
+    display_set: &DisplaySet,
+    options: &RenderOptions,
+) -> PgsResult<Vec<u8>> {
+    render_with_palette(display_set, &PaletteTable::new(display_set), options)
+}
+
+/// Renders the display set to a packed AYUV buffer (`[a, y, u, v, ...]`,
+/// with `u` = Cb and `v` = Cr as stored in the palette), skipping the
+/// conversion to RGBA.
+pub fn render_display_set_ayuv(display_set: &DisplaySet) -> PgsResult<Vec<u8>> {
+    let options = RenderOptions::default();
+    Ok(compose(display_set, &PaletteTable::new(display_set), &options)?.buf)
+}
+
+/// Renders the display set to a packed RGB buffer premultiplied by alpha,
+/// along with the alpha as a separate coverage plane of one byte per pixel,
+/// for compositors that filter edges using the coverage.
+pub fn render_display_set_rgb_plus_coverage(
+    display_set: &DisplaySet,
+) -> PgsResult<(Vec<u8>, Vec<u8>)> {
+    let rgba = render_display_set(display_set)?;
+    let pixels = rgba.len() / PIXEL_SIZE;
+    let mut rgb = Vec::with_capacity(pixels * 3);
+    let mut coverage = Vec::with_capacity(pixels);
+    for pixel in rgba.chunks_exact(PIXEL_SIZE) {
+        let alpha = pixel[3] as u32;
+        rgb.extend(
+            pixel[..3]
+                .iter()
+                .map(|&channel| ((channel as u32 * alpha + 127) / 255) as u8),
+        );
+        coverage.push(pixel[3]);
+    }
+    Ok((rgb, coverage))
+}
+
+/// Renders the display set composited over black and packed as RGB565, for
+/// displays without an alpha channel.
+pub fn render_display_set_rgb565(display_set: &DisplaySet) -> PgsResult<Vec<u16>> {
+    let rgba = render_display_set(display_set)?;
+    Ok(rgba
+        .chunks_exact(PIXEL_SIZE)
+        .map(|pixel| {
+            let alpha = pixel[3] as u32;
+            let [r, g, b] = [0, 1, 2].map(|channel| (pixel[channel] as u32 * alpha + 127) / 255);
+            ((r >> 3) << 11 | (g >> 2) << 5 | b >> 3) as u16
+        })
+        .collect())
+}
+
+/// A rendered display set split into full-resolution planes, one byte per
+/// pixel each.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanarImage {
+    pub width: u16,
+    pub height: u16,
+    pub y: Vec<u8>,
+    pub cb: Vec<u8>,
+    pub cr: Vec<u8>,
+    pub alpha: Vec<u8>,
+}
+
+/// Renders the display set to separate Y, Cb, Cr and alpha planes, taken
+/// directly from the palette without colour conversion. Chroma is not
+/// subsampled.
+pub fn render_display_set_planar(display_set: &DisplaySet) -> PgsResult<PlanarImage> {
+    let ayuv = render_display_set_ayuv(display_set)?;
+    let pixels = ayuv.len() / PIXEL_SIZE;
+    let mut image = PlanarImage {
+        width: display_set.width,
+        height: display_set.height,
+        y: Vec::with_capacity(pixels),
+        cb: Vec::with_capacity(pixels),
+        cr: Vec::with_capacity(pixels),
+        alpha: Vec::with_capacity(pixels),
+    };
+    for pixel in ayuv.chunks_exact(PIXEL_SIZE) {
+        image.alpha.push(pixel[0]);
+        image.y.push(pixel[1]);
+        image.cb.push(pixel[2]);
+        image.cr.push(pixel[3]);
+    }
+    Ok(image)
+}
+
+/// Renders a one byte per pixel mask of the display set, where pixels whose
+/// alpha is above `alpha_threshold` are 255 and all others are 0, e.g. as a
+/// binarized input for OCR. Returns the mask's width, height and data.
+pub fn render_binary_mask(
+    display_set: &DisplaySet,
+    alpha_threshold: u8,
+) -> PgsResult<(u16, u16, Vec<u8>)> {
+    let ayuv = render_display_set_ayuv(display_set)?;
+    let mask = ayuv
+        .chunks_exact(PIXEL_SIZE)
+        .map(|pixel| if pixel[0] > alpha_threshold { 255 } else { 0 })
+        .collect();
+    Ok((display_set.width, display_set.height, mask))
+}
 
+/// Renders each palette entry the display set shows as its own frame-sized
+/// mask holding the entry's alpha wherever it is drawn, so individual colors
+/// can be recolored when compositing. Where objects overlap, the pixel
+/// belongs to the topmost one, so the masks never overlap. Entries that are
+/// fully transparent or cropped away get no layer. Layers are ordered by
+/// palette entry id.
+pub fn render_color_layers(display_set: &DisplaySet) -> PgsResult<Vec<(PaletteEntry, Vec<u8>)>> {
+    let (frame_width, frame_height) = (display_set.width as usize, display_set.height as usize);
+    // Palette entry shown at each pixel.
+    let mut owners: Vec<Option<&PaletteEntry>> = vec![None; frame_width * frame_height];
+    let palette = display_set.palettes.get(&display_set.palette_id);
     for composition_object in display_set.composition_objects {
         let Some(object) = display_set.objects.get(&composition_object.id) else {
             return Err(PgsError::ObjectNotFound {
@@ -151,98 +564,2291 @@ pub fn render_display_set(display_set: &DisplaySet) -> PgsResult<Vec<u8>> {
                 display_set: format!("{:?}", display_set),
             });
         };
-        let x = composition_object.horizontal_position as usize;
-        let y = composition_object.vertical_position as usize;
-
-        let mut pixel_offset = (y * width + x) * PIXEL_SIZE;
-
-        for pixel in object.data.0.iter() {
-            let Some(pixel_color) = display_set
-                .palettes
-                // TODO: Is multiple palettes allowed?
-                .get(&0)
-                .and_then(|palette| palette.entries.get(&pixel.color))
-            else {
+        let object_width = object.width as usize;
+        for (index, color) in object.decode_indexed().into_iter().enumerate() {
+            let Some(entry) = palette.and_then(|palette| palette.entries.get(&color)) else {
                 return Err(PgsError::PaletteNotFound {
-                    palette_id: 0,
-                    entry_id: pixel.color,
+                    palette_id: display_set.palette_id,
+                    entry_id: color,
                     display_set: format!("{:?}", display_set),
                 });
             };
-            for _ in 0..pixel.count {
-                if !is_cropped(&pixel_offset, composition_object) {
-                    buf[pixel_offset] = pixel_color.alpha;
-                    buf[pixel_offset + 1] = pixel_color.luminance;
-                    buf[pixel_offset + 2] = pixel_color.color_difference_blue;
-                    buf[pixel_offset + 3] = pixel_color.color_difference_red;
-                }
-                move_one_pixel_forward(
-                    &mut pixel_offset,
-                    width,
-                    composition_object.horizontal_position as usize,
-                    object.width as usize,
-                );
+            let (column, row) = (index % object_width, index / object_width);
+            if entry.alpha == 0 || crop_distance(column, row, composition_object) > 0 {
+                continue;
+            }
+            let x = composition_object.horizontal_position as usize + column;
+            let y = composition_object.vertical_position as usize + row;
+            if x < frame_width && y < frame_height {
+                owners[y * frame_width + x] = Some(entry);
             }
         }
     }
 
-    let image = YuvPackedImage {
-        yuy: &buf,
-        yuy_stride: stride as u32,
-        width: display_set.width as u32,
-        height: display_set.height as u32,
-    };
+    let mut layers: Vec<(PaletteEntry, Vec<u8>)> = Vec::new();
+    for (pixel, owner) in owners.iter().enumerate() {
+        let Some(entry) = owner else {
+            continue;
+        };
+        let position = match layers.binary_search_by_key(&entry.id, |(layer, _)| layer.id) {
+            Ok(position) => position,
+            Err(position) => {
+                let mask = vec![0; frame_width * frame_height];
+                layers.insert(position, ((*entry).clone(), mask));
+                position
+            }
+        };
+        layers[position].1[pixel] = entry.alpha;
+    }
+    Ok(layers)
+}
 
-    image.check_constraints444()?;
+fn render_with_palette(
+    display_set: &DisplaySet,
+    palette: &PaletteTable,
+    options: &RenderOptions,
+) -> PgsResult<Vec<u8>> {
+    let mut rgba = compose(display_set, palette, options)?.into_rgba()?;
+    if options.debug_outlines {
+        draw_debug_outlines(&mut rgba, display_set);
+    }
+    Ok(rgba)
+}
 
-    let mut rgba = vec![0u8; stride * height];
+/// Outlines every window of the display set and, on top of them, every
+/// composition object's crop rectangle.
+fn draw_debug_outlines(rgba: &mut [u8], display_set: &DisplaySet) {
+    let mut windows: Vec<&Window> = display_set.windows().collect();
+    windows.sort_by_key(|window| window.id);
+    let windows = windows.into_iter().map(|window| {
+        (
+            window.horizontal_position,
+            window.vertical_position,
+            window.width,
+            window.height,
+            WINDOW_OUTLINE_COLOR,
+        )
+    });
+    let crops = display_set
+        .composition_objects
+        .iter()
+        .filter_map(CompositionObject::screen_crop_rect)
+        .map(|rect| (rect.x, rect.y, rect.width, rect.height, CROP_OUTLINE_COLOR));
+    let (frame_width, frame_height) = (display_set.width as usize, display_set.height as usize);
+    for (x, y, width, height, color) in windows.chain(crops) {
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let (left, top) = (x as usize, y as usize);
+        let (right, bottom) = (left + width as usize - 1, top + height as usize - 1);
+        let mut plot = |x: usize, y: usize| {
+            if x < frame_width && y < frame_height {
+                let offset = (y * frame_width + x) * PIXEL_SIZE;
+                rgba[offset..offset + PIXEL_SIZE].copy_from_slice(&color);
+            }
+        };
+        for x in left..=right {
+            plot(x, top);
+            plot(x, bottom);
+        }
+        for y in top..=bottom {
+            plot(left, y);
+            plot(right, y);
+        }
+    }
+}
 
-    yuv::ayuv_to_rgba(
-        &image,
-        &mut rgba,
-        stride as u32,
-        YuvRange::Full,
-        YuvStandardMatrix::Bt709,
-        false,
-    )?;
+/// Draws every composition object of the display set onto a frame-sized
+/// AYUV canvas.
+fn compose<'o>(
+    display_set: &DisplaySet,
+    palette: &PaletteTable,
+    options: &'o RenderOptions,
+) -> PgsResult<Canvas<'o>> {
+    compose_into(Vec::new(), display_set, palette, options)
+}
+
+/// Like [`compose`], but reuses `buf`'s allocation for the canvas.
+fn compose_into<'o>(
+    buf: Vec<u8>,
+    display_set: &DisplaySet,
+    palette: &PaletteTable,
+    options: &'o RenderOptions,
+) -> PgsResult<Canvas<'o>> {
+    if display_set.width == 0 || display_set.height == 0 {
+        return Err(PgsError::InvalidDimensions {
+            width: display_set.width,
+            height: display_set.height,
+            display_set: format!("{:?}", display_set),
+        });
+    }
+    let mut canvas = Canvas::with_buffer(
+        buf,
+        display_set.width as usize,
+        display_set.height as usize,
+        options,
+    );
+
+    for composition_object in display_set.composition_objects {
+        canvas.draw_composition_object(
+            composition_object.horizontal_position as isize,
+            composition_object.vertical_position as isize,
+            composition_object,
+            display_set,
+            palette,
+        )?;
+    }
+    if let Some(outline) = &options.outline {
+        // Every object was found while drawing, so each has a bounding box.
+        let bounds: Vec<Bounds> = display_set
+            .composition_objects
+            .iter()
+            .filter_map(|composition_object| {
+                let object = display_set.objects.get(&composition_object.id)?;
+                Some(Bounds {
+                    x: composition_object.horizontal_position as usize,
+                    y: composition_object.vertical_position as usize,
+                    width: object.width as usize,
+                    height: object.height as usize,
+                })
+            })
+            .collect();
+        canvas.draw_outline(outline, &bounds);
+    }
+
+    Ok(canvas)
+}
+
+/// Renders the display set into a caller-provided RGBA buffer whose rows are
+/// `stride` bytes apart, e.g. to satisfy a GPU's row alignment. Bytes between
+/// the end of a row and the next stride boundary are left untouched.
+pub fn render_display_set_strided(
+    display_set: &DisplaySet,
+    out: &mut [u8],
+    stride: usize,
+) -> PgsResult<()> {
+    render_display_set_strided_with_options(display_set, &RenderOptions::default(), out, stride)
+}
+
+/// Like [`render_display_set_strided`], rendering with `options`.
+pub fn render_display_set_strided_with_options(
+    display_set: &DisplaySet,
+    options: &RenderOptions,
+    out: &mut [u8],
+    stride: usize,
+) -> PgsResult<()> {
+    let width = display_set.width as usize;
+    let height = display_set.height as usize;
+    if stride < width * PIXEL_SIZE {
+        return Err(PgsError::InvalidStride {
+            stride,
+            width: display_set.width,
+        });
+    }
+    let required = stride * height;
+    if out.len() < required {
+        return Err(PgsError::BufferTooSmall {
+            len: out.len(),
+            required,
+        });
+    }
+    let canvas = compose(display_set, &PaletteTable::new(display_set), options)?;
+    convert_with_options(
+        &canvas.buf,
+        width,
+        height,
+        &mut out[..required],
+        stride,
+        options,
+    )
+}
+
+/// Fill used for fully transparent pixels in debug renders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugBackground {
+    /// A single RGBA color.
+    Solid([u8; PIXEL_SIZE]),
+    /// Alternating squares of `size` pixels in two RGBA colors.
+    Checkerboard {
+        size: usize,
+        colors: [[u8; PIXEL_SIZE]; 2],
+    },
+}
 
+/// Renders the display set and fills every fully transparent pixel with
+/// `background`, making the subtitle's extent visible when inspecting the
+/// output. Pixels with any opacity are left exactly as rendered.
+pub fn render_display_set_debug(
+    display_set: &DisplaySet,
+    background: &DebugBackground,
+) -> PgsResult<Vec<u8>> {
+    let mut rgba = render_display_set(display_set)?;
+    let width = display_set.width as usize;
+    for (index, pixel) in rgba.chunks_exact_mut(PIXEL_SIZE).enumerate() {
+        if pixel[3] != 0 {
+            continue;
+        }
+        let fill = match background {
+            DebugBackground::Solid(color) => color,
+            DebugBackground::Checkerboard { size, colors } => {
+                let size = (*size).max(1);
+                let (x, y) = (index % width, index / width);
+                &colors[(x / size + y / size) % 2]
+            }
+        };
+        pixel.copy_from_slice(fill);
+    }
     Ok(rgba)
 }
 
-fn is_cropped(pixel_offset: &usize, object: &CompositionObject) -> bool {
-    if let Some(cropped) = &object.cropped {
-        return is_cropped_1(
-            pixel_offset as &usize,
-            cropped.horizontal_position as usize,
-            cropped.vertical_position as usize,
-            cropped.width as usize,
-            cropped.height as usize,
+/// Samples the stream every `1 / fps` seconds across [`Pgs::duration`],
+/// starting at the first segment's timestamp, yielding the sample time and
+/// the RGBA frame showing whichever subtitle is active (or a fully
+/// transparent frame when none is). Sampling stops early if a sample time
+/// would overflow the 32-bit timestamp.
+pub fn sampled_frames(
+    pgs: &Pgs,
+    fps: f64,
+) -> impl Iterator<Item = PgsResult<(Duration, Vec<u8>)>> + '_ {
+    let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(pgs).collect();
+    let start = pgs.segments.first().map_or(0, |segment| segment.pts);
+    let frame_count = if fps > 0.0 {
+        (pgs.duration().as_secs_f64() * fps).round() as usize
+    } else {
+        0
+    };
+    let mut next = 0;
+    let mut current: Option<Vec<u8>> = None;
+    (0..frame_count).map_while(move |frame| {
+        let offset = (frame as f64 * PTS_CLOCK_HZ as f64 / fps).round();
+        if offset > u32::MAX as f64 {
+            return None;
+        }
+        let pts = start.checked_add(offset as u32)?;
+        while next < display_sets.len() && display_sets[next].presentation_timestamp <= pts {
+            current = None;
+            next += 1;
+        }
+        let active = next.checked_sub(1).map(|index| &display_sets[index]);
+        let rgba = match (active, &current) {
+            (Some(display_set), None) if !display_set.is_empty() => {
+                let rgba = match render_display_set(display_set) {
+                    Ok(rgba) => rgba,
+                    Err(error) => return Some(Err(error)),
+                };
+                current = Some(rgba.clone());
+                rgba
+            }
+            (_, Some(rgba)) => rgba.clone(),
+            _ => {
+                let frame = active.or(display_sets.first());
+                let (width, height) =
+                    frame.map_or((0, 0), |ds| (ds.width as usize, ds.height as usize));
+                vec![0u8; width * height * PIXEL_SIZE]
+            }
+        };
+        Some(Ok((pts_to_duration(pts), rgba)))
+    })
+}
+
+/// Yields a thumbnail of every non-empty display set for a filmstrip, with
+/// the time it is shown. Each thumbnail covers the bounding box of the
+/// subtitle's visible pixels, or the whole frame if none are visible, and
+/// is downscaled so its longest side is at most `max_dim` pixels.
+#[cfg(feature = "image")]
+pub fn thumbnails(
+    pgs: &Pgs,
+    max_dim: u32,
+) -> impl Iterator<Item = PgsResult<(Duration, RgbaImage)>> + '_ {
+    DisplaySetIterator::new(pgs)
+        .skip_empty()
+        .map(move |display_set| {
+            let rgba = render_display_set(&display_set)?;
+            let (width, height) = (display_set.width as u32, display_set.height as u32);
+            let (len, required) = (rgba.len(), width as usize * height as usize * PIXEL_SIZE);
+            let frame = RgbaImage::from_raw(width, height, rgba)
+                .ok_or(PgsError::BufferTooSmall { len, required })?;
+            let visible = frame
+                .enumerate_pixels()
+                .filter(|(_, _, pixel)| pixel[3] > 0)
+                .map(|(x, y, _)| (x, y, x + 1, y + 1))
+                .reduce(|(left, top, right, bottom), (x, y, x_end, y_end)| {
+                    (left.min(x), top.min(y), right.max(x_end), bottom.max(y_end))
+                });
+            let (left, top, right, bottom) = visible.unwrap_or((0, 0, width, height));
+            let (crop_width, crop_height) = (right - left, bottom - top);
+            let cropped = imageops::crop_imm(&frame, left, top, crop_width, crop_height);
+            let longest = crop_width.max(crop_height);
+            let thumbnail = if longest > max_dim {
+                imageops::thumbnail(
+                    &*cropped,
+                    (crop_width * max_dim / longest).max(1),
+                    (crop_height * max_dim / longest).max(1),
+                )
+            } else {
+                cropped.to_image()
+            };
+            Ok((
+                pts_to_duration(display_set.presentation_timestamp),
+                thumbnail,
+            ))
+        })
+}
+
+/// A display set rendered to RGBA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    /// Index of the display set within the stream.
+    pub index: usize,
+    pub presentation_timestamp: u32,
+    pub width: u16,
+    pub height: u16,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorMode {
+    /// Stop at the first display set that fails to render.
+    #[default]
+    AbortOnFirst,
+    /// Keep rendering the remaining display sets after a failure.
+    CollectAll,
+}
+
+/// Renders every non-empty display set in the stream, returning the
+/// rendered frames along with the errors encountered, each paired with the
+/// index of the display set that caused it.
+pub fn render_all(pgs: &Pgs, mode: ErrorMode) -> (Vec<Rendered>, Vec<(usize, PgsError)>) {
+    let mut rendered = Vec::new();
+    let mut errors = Vec::new();
+    let mut display_sets = DisplaySetIterator::new(pgs);
+    let mut index = 0;
+    while let Some(display_set) = display_sets.try_next() {
+        let result = display_set.and_then(|display_set| {
+            if display_set.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(Rendered {
+                index,
+                presentation_timestamp: display_set.presentation_timestamp,
+                width: display_set.width,
+                height: display_set.height,
+                rgba: render_display_set(&display_set)?,
+            }))
+        });
+        match result {
+            Ok(frame) => rendered.extend(frame),
+            Err(error) => {
+                errors.push((index, error));
+                if mode == ErrorMode::AbortOnFirst {
+                    break;
+                }
+            }
+        }
+        index += 1;
+    }
+    (rendered, errors)
+}
+
+/// Every visible cue of a stream, pre-rendered and indexed by presentation
+/// time.
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    /// Rendered cues in presentation order, each paired with the timestamp of
+    /// the display set that replaces it, or `None` if it is never replaced.
+    cues: Vec<(Rendered, Option<u32>)>,
+}
+
+impl Timeline {
+    pub fn build(pgs: &Pgs) -> PgsResult<Timeline> {
+        let mut cues: Vec<(Rendered, Option<u32>)> = Vec::new();
+        let mut display_sets = DisplaySetIterator::new(pgs);
+        let mut index = 0;
+        while let Some(display_set) = display_sets.try_next() {
+            let display_set = display_set?;
+            if let Some((_, end @ None)) = cues.last_mut() {
+                *end = Some(display_set.presentation_timestamp);
+            }
+            if !display_set.is_empty() {
+                cues.push((
+                    Rendered {
+                        index,
+                        presentation_timestamp: display_set.presentation_timestamp,
+                        width: display_set.width,
+                        height: display_set.height,
+                        rgba: render_display_set(&display_set)?,
+                    },
+                    None,
+                ));
+            }
+            index += 1;
+        }
+        Ok(Timeline { cues })
+    }
+
+    /// The cue on screen at `pts`, if any.
+    pub fn frame_at(&self, pts: u32) -> Option<&Rendered> {
+        let count = self
+            .cues
+            .partition_point(|(rendered, _)| rendered.presentation_timestamp <= pts);
+        let (rendered, end) = self.cues.get(count.checked_sub(1)?)?;
+        end.is_none_or(|end| pts < end).then_some(rendered)
+    }
+
+    pub fn cues(&self) -> impl Iterator<Item = &Rendered> {
+        self.cues.iter().map(|(rendered, _)| rendered)
+    }
+}
+
+/// Difference between two streams' output at the start of a cue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CueDiff {
+    pub presentation_timestamp: u32,
+    /// Largest difference of any single RGBA channel.
+    pub max_difference: u8,
+    /// Mean absolute difference across every RGBA channel of the frame.
+    pub mean_difference: f64,
+}
+
+/// Renders both streams and compares what is on screen at the start of every
+/// cue of either stream. A time where only one stream shows a cue is
+/// compared against a transparent frame; frames of differing sizes are
+/// compared over their union, with missing pixels transparent.
+pub fn compare_rendered(a: &Pgs, b: &Pgs) -> PgsResult<Vec<CueDiff>> {
+    let (a, b) = (Timeline::build(a)?, Timeline::build(b)?);
+    let mut times: Vec<u32> = a
+        .cues()
+        .chain(b.cues())
+        .map(|rendered| rendered.presentation_timestamp)
+        .collect();
+    times.sort_unstable();
+    times.dedup();
+
+    let mut diffs = Vec::new();
+    for presentation_timestamp in times {
+        let (frame_a, frame_b) = (
+            a.frame_at(presentation_timestamp),
+            b.frame_at(presentation_timestamp),
         );
+        let size = |frame: Option<&Rendered>| {
+            frame.map_or((0, 0), |frame| {
+                (frame.width as usize, frame.height as usize)
+            })
+        };
+        let ((width_a, height_a), (width_b, height_b)) = (size(frame_a), size(frame_b));
+        let (width, height) = (width_a.max(width_b), height_a.max(height_b));
+        let channel = |frame: Option<&Rendered>, frame_width: usize, x, y, c| {
+            frame
+                .filter(|frame| x < frame_width && y < frame.height as usize)
+                .map_or(0, |frame| {
+                    frame.rgba[(y * frame_width + x) * PIXEL_SIZE + c]
+                })
+        };
+        let (mut max_difference, mut total) = (0u8, 0u64);
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..PIXEL_SIZE {
+                    let difference = channel(frame_a, width_a, x, y, c)
+                        .abs_diff(channel(frame_b, width_b, x, y, c));
+                    max_difference = max_difference.max(difference);
+                    total += difference as u64;
+                }
+            }
+        }
+        let channels = width * height * PIXEL_SIZE;
+        diffs.push(CueDiff {
+            presentation_timestamp,
+            max_difference,
+            mean_difference: if channels == 0 {
+                0.0
+            } else {
+                total as f64 / channels as f64
+            },
+        });
     }
-    false
+    Ok(diffs)
 }
 
-fn is_cropped_1(
-    pixel_offset: &usize,
-    top_left_x: usize,
-    top_left_y: usize,
-    width: usize,
-    height: usize,
-) -> bool {
-    let x = (*pixel_offset / PIXEL_SIZE) % width;
-    let y = (*pixel_offset / PIXEL_SIZE) / width;
-    x < top_left_x || x >= top_left_x + width || y < top_left_y || y >= top_left_y + height
+/// Renders the objects of a single window onto a canvas the size of that
+/// window, returning the window's width, height and RGBA buffer.
+pub fn render_window(display_set: &DisplaySet, window_id: u8) -> PgsResult<(u16, u16, Vec<u8>)> {
+    render_window_with_options(display_set, window_id, &RenderOptions::default())
+}
+
+/// Like [`render_window`], rendering with `options`.
+pub fn render_window_with_options(
+    display_set: &DisplaySet,
+    window_id: u8,
+    options: &RenderOptions,
+) -> PgsResult<(u16, u16, Vec<u8>)> {
+    let Some(window) = display_set.windows.get(&window_id) else {
+        return Err(PgsError::WindowNotFound {
+            window_id,
+            display_set: format!("{:?}", display_set),
+        });
+    };
+    if window.width == 0 || window.height == 0 {
+        return Ok((window.width, window.height, Vec::new()));
+    }
+    let palette = PaletteTable::new(display_set);
+    let mut canvas = Canvas::new(window.width as usize, window.height as usize, options);
+
+    for composition_object in display_set
+        .composition_objects
+        .iter()
+        .filter(|composition_object| composition_object.window_id == window_id)
+    {
+        canvas.draw_composition_object(
+            composition_object.horizontal_position as isize - window.horizontal_position as isize,
+            composition_object.vertical_position as isize - window.vertical_position as isize,
+            composition_object,
+            display_set,
+            &palette,
+        )?;
+    }
+
+    Ok((window.width, window.height, canvas.into_rgba()?))
+}
+
+/// Renders the display set directly onto a `target_width` x `target_height`
+/// RGBA canvas, for video re-encoded at a different resolution than the
+/// subtitles were authored for. Each object's position and size are scaled
+/// from the composition's resolution and its pixels are sampled
+/// nearest-neighbour, rather than rendering at native size and resizing the
+/// whole frame.
+pub fn render_display_set_at_resolution(
+    display_set: &DisplaySet,
+    target_width: u16,
+    target_height: u16,
+) -> PgsResult<Vec<u8>> {
+    if display_set.width == 0 || display_set.height == 0 {
+        return Err(PgsError::InvalidDimensions {
+            width: display_set.width,
+            height: display_set.height,
+            display_set: format!("{:?}", display_set),
+        });
+    }
+    let (source_width, source_height) = (display_set.width as usize, display_set.height as usize);
+    let (target_width, target_height) = (target_width as usize, target_height as usize);
+    let palette = PaletteTable::new(display_set);
+    let options = RenderOptions::default();
+    let mut canvas = Canvas::new(target_width, target_height, &options);
+
+    for composition_object in display_set.composition_objects {
+        let Some(object) = display_set.objects.get(&composition_object.id) else {
+            return Err(PgsError::ObjectNotFound {
+                object_id: composition_object.id,
+                display_set: format!("{:?}", display_set),
+            });
+        };
+        let (object_width, object_height) = (object.width as usize, object.height as usize);
+        let mut source = Canvas::new(object_width, object_height, &options);
+        source.draw_composition_object(0, 0, composition_object, display_set, &palette)?;
+
+        let x = composition_object.horizontal_position as usize;
+        let y = composition_object.vertical_position as usize;
+        let (left, right) = (
+            x * target_width / source_width,
+            (x + object_width) * target_width / source_width,
+        );
+        let (top, bottom) = (
+            y * target_height / source_height,
+            (y + object_height) * target_height / source_height,
+        );
+        for target_y in top..bottom.min(target_height) {
+            let source_y = (target_y - top) * object_height / (bottom - top);
+            for target_x in left..right.min(target_width) {
+                let source_x = (target_x - left) * object_width / (right - left);
+                let from = (source_y * object_width + source_x) * PIXEL_SIZE;
+                // Transparent pixels include those cropped away, which must
+                // not cover objects drawn earlier.
+                if source.buf[from] == 0 {
+                    continue;
+                }
+                let to = (target_y * target_width + target_x) * PIXEL_SIZE;
+                canvas.buf[to..to + PIXEL_SIZE]
+                    .copy_from_slice(&source.buf[from..from + PIXEL_SIZE]);
+            }
+        }
+    }
+
+    canvas.into_rgba()
+}
+
+/// Renders the display set with every object moved by a fractional offset
+/// of `(offset_x, offset_y)` pixels, so animated or scaled overlays can be
+/// placed at subpixel positions without snapping to whole pixels. The
+/// rendered frame is resampled bilinearly, blending colors weighted by
+/// alpha so edges don't darken; pixels shifted in from outside the frame
+/// are transparent.
+pub fn render_display_set_subpixel(
+    display_set: &DisplaySet,
+    offset_x: f32,
+    offset_y: f32,
+) -> PgsResult<Vec<u8>> {
+    let rgba = render_display_set(display_set)?;
+    let (width, height) = (display_set.width as usize, display_set.height as usize);
+    let mut out = vec![0u8; rgba.len()];
+    for y in 0..height {
+        let source_y = y as f32 - offset_y;
+        let (top, weight_y) = (source_y.floor(), source_y - source_y.floor());
+        for x in 0..width {
+            let source_x = x as f32 - offset_x;
+            let (left, weight_x) = (source_x.floor(), source_x - source_x.floor());
+            let mut sum = [0f32; PIXEL_SIZE];
+            for (dy, weight_row) in [(0, 1.0 - weight_y), (1, weight_y)] {
+                for (dx, weight_column) in [(0, 1.0 - weight_x), (1, weight_x)] {
+                    let (sample_x, sample_y) = (left as isize + dx, top as isize + dy);
+                    if !(0..width as isize).contains(&sample_x)
+                        || !(0..height as isize).contains(&sample_y)
+                    {
+                        continue;
+                    }
+                    let offset = (sample_y as usize * width + sample_x as usize) * PIXEL_SIZE;
+                    let pixel = &rgba[offset..offset + PIXEL_SIZE];
+                    let alpha = pixel[3] as f32 * weight_row * weight_column;
+                    for channel in 0..3 {
+                        sum[channel] += pixel[channel] as f32 * alpha;
+                    }
+                    sum[3] += alpha;
+                }
+            }
+            if sum[3] > 0.0 {
+                let offset = (y * width + x) * PIXEL_SIZE;
+                for channel in 0..3 {
+                    out[offset + channel] = (sum[channel] / sum[3]).round() as u8;
+                }
+                out[offset + 3] = sum[3].round() as u8;
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Renders several display sets, e.g. from two subtitle tracks, onto one
+/// `frame_width` x `frame_height` RGBA canvas, blending each over the ones
+/// before it. Sets authored at a different resolution are scaled to the
+/// frame size with nearest-neighbour sampling.
+pub fn composite_display_sets(
+    sets: &[&DisplaySet],
+    frame_width: u16,
+    frame_height: u16,
+) -> PgsResult<Vec<u8>> {
+    let (width, height) = (frame_width as usize, frame_height as usize);
+    let mut out = vec![0u8; width * height * PIXEL_SIZE];
+    for display_set in sets {
+        if display_set.is_empty() {
+            continue;
+        }
+        draw_scaled(&mut out, width, height, display_set, 1.0)?;
+    }
+    Ok(out)
+}
+
+/// Renders the current cue over a dimmed copy of the previous one, for
+/// reviewing motion between consecutive cues. `prev` is drawn with its
+/// alpha scaled by `prev_opacity`, from 0.0 (hidden) to 1.0 (unchanged). The
+/// image has `cur`'s size; a `prev` authored at a different resolution is
+/// scaled to it with nearest-neighbour sampling.
+pub fn render_onion_skin(
+    prev: &DisplaySet,
+    cur: &DisplaySet,
+    prev_opacity: f32,
+) -> PgsResult<Vec<u8>> {
+    let (width, height) = (cur.width as usize, cur.height as usize);
+    let mut out = vec![0u8; width * height * PIXEL_SIZE];
+    if !prev.is_empty() {
+        draw_scaled(&mut out, width, height, prev, prev_opacity.clamp(0.0, 1.0))?;
+    }
+    if !cur.is_empty() {
+        draw_scaled(&mut out, width, height, cur, 1.0)?;
+    }
+    Ok(out)
 }
 
-fn move_one_pixel_forward(
-    pixel_offset: &mut usize,
+/// Renders the display set and blends it, scaled to `width` x `height` with
+/// nearest-neighbour sampling and its alpha multiplied by `opacity`, over
+/// the RGBA buffer `out`.
+fn draw_scaled(
+    out: &mut [u8],
     width: usize,
-    horizontal_position: usize,
-    object_width: usize,
-) {
-    *pixel_offset += PIXEL_SIZE;
-    let x = (*pixel_offset / PIXEL_SIZE) % width;
-    if x == (horizontal_position + object_width) {
-        *pixel_offset += (width - object_width) * PIXEL_SIZE
+    height: usize,
+    display_set: &DisplaySet,
+    opacity: f32,
+) -> PgsResult<()> {
+    let rgba = render_display_set(display_set)?;
+    let (source_width, source_height) = (display_set.width as usize, display_set.height as usize);
+    for y in 0..height {
+        let source_y = y * source_height / height;
+        for x in 0..width {
+            let source_x = x * source_width / width;
+            let source = (source_y * source_width + source_x) * PIXEL_SIZE;
+            let target = (y * width + x) * PIXEL_SIZE;
+            let mut pixel = [0u8; PIXEL_SIZE];
+            pixel.copy_from_slice(&rgba[source..source + PIXEL_SIZE]);
+            pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+            blend_over(&mut out[target..target + PIXEL_SIZE], &pixel);
+        }
+    }
+    Ok(())
+}
+
+/// Blends a straight-alpha RGBA pixel over another in place.
+fn blend_over(below: &mut [u8], above: &[u8]) {
+    let top = above[3] as u32;
+    let bottom = below[3] as u32 * (255 - top) / 255;
+    let alpha = top + bottom;
+    if alpha == 0 {
+        return;
+    }
+    for channel in 0..3 {
+        below[channel] =
+            ((above[channel] as u32 * top + below[channel] as u32 * bottom) / alpha) as u8;
+    }
+    below[3] = alpha as u8;
+}
+
+/// Renders consecutive display sets while reusing the flattened palette
+/// lookup table, which is only rebuilt when the active palette's id or
+/// version changes or a new epoch starts.
+#[derive(Default)]
+pub struct StreamRenderer {
+    options: RenderOptions,
+    palette: Option<(Option<(u8, u8)>, PaletteTable)>,
+}
+
+impl StreamRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: RenderOptions) -> Self {
+        Self {
+            options,
+            palette: None,
+        }
+    }
+
+    pub fn render(&mut self, display_set: &DisplaySet) -> PgsResult<Vec<u8>> {
+        let key = display_set
+            .palettes
+            .get(&display_set.palette_id)
+            .map(|palette| (palette.id, palette.version));
+        if display_set.composition_state == CompositionState::EpochStart
+            || self
+                .palette
+                .as_ref()
+                .is_some_and(|(cached, _)| *cached != key)
+        {
+            self.palette = None;
+        }
+        let (_, palette) = self
+            .palette
+            .get_or_insert_with(|| (key, PaletteTable::new(display_set)));
+        render_with_palette(display_set, palette, &self.options)
+    }
+}
+
+/// Scratch AYUV and RGBA buffers of a single resolution.
+type RenderBuffers = (Vec<u8>, Vec<u8>);
+
+/// A thread-safe pool of render buffers, keyed by resolution, so rendering
+/// many display sets (e.g. from a thread pool) doesn't allocate fresh
+/// buffers for every frame. Rendered frames hand their buffers back to the
+/// pool when dropped.
+pub struct RenderPool {
+    capacity: usize,
+    options: RenderOptions,
+    idle: Mutex<HashMap<(u16, u16), Vec<RenderBuffers>>>,
+}
+
+impl RenderPool {
+    /// Creates a pool keeping at most `capacity` idle buffer pairs per
+    /// resolution, typically the number of rendering threads.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_options(capacity, RenderOptions::default())
+    }
+
+    /// Like [`RenderPool::new`], rendering every frame with `options`.
+    pub fn with_options(capacity: usize, options: RenderOptions) -> Self {
+        Self {
+            capacity,
+            options,
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn render(&self, display_set: &DisplaySet) -> PgsResult<PooledFrame<'_>> {
+        let key = (display_set.width, display_set.height);
+        let (ayuv, mut rgba) = self
+            .lock()
+            .get_mut(&key)
+            .and_then(Vec::pop)
+            .unwrap_or_default();
+        let palette = PaletteTable::new(display_set);
+        let canvas = compose_into(ayuv, display_set, &palette, &self.options)?;
+        let stride = canvas.width * PIXEL_SIZE;
+        rgba.resize(stride * canvas.height, 0);
+        convert_with_options(
+            &canvas.buf,
+            canvas.width,
+            canvas.height,
+            &mut rgba,
+            stride,
+            &self.options,
+        )?;
+        Ok(PooledFrame {
+            pool: self,
+            key,
+            buffers: (canvas.buf, rgba),
+        })
+    }
+
+    /// Number of idle buffer pairs currently held for the given resolution.
+    pub fn idle_buffers(&self, width: u16, height: u16) -> usize {
+        self.lock().get(&(width, height)).map_or(0, Vec::len)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<(u16, u16), Vec<RenderBuffers>>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// An RGBA frame rendered by a [`RenderPool`], dereferencing to its pixels.
+pub struct PooledFrame<'p> {
+    pool: &'p RenderPool,
+    key: (u16, u16),
+    buffers: RenderBuffers,
+}
+
+impl Deref for PooledFrame<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffers.1
+    }
+}
+
+impl Drop for PooledFrame<'_> {
+    fn drop(&mut self) {
+        let mut idle = self.pool.lock();
+        let buffers = idle.entry(self.key).or_default();
+        if buffers.len() < self.pool.capacity {
+            buffers.push(std::mem::take(&mut self.buffers));
+        }
+    }
+}
+
+/// The palette selected by the composition's `palette_id`, flattened into
+/// AYUV values indexed by entry id.
+struct PaletteTable([Option<[u8; PIXEL_SIZE]>; 256]);
+
+impl PaletteTable {
+    fn new(display_set: &DisplaySet) -> Self {
+        let mut table = [None; 256];
+        if let Some(palette) = display_set.palettes.get(&display_set.palette_id) {
+            for entry in palette.entries.values() {
+                table[entry.id as usize] = Some([
+                    entry.alpha,
+                    entry.luminance,
+                    entry.color_difference_blue,
+                    entry.color_difference_red,
+                ]);
+            }
+        }
+        Self(table)
+    }
+}
+
+/// An AYUV canvas that composition objects are drawn onto before conversion.
+struct Canvas<'o> {
+    buf: Vec<u8>,
+    width: usize,
+    height: usize,
+    options: &'o RenderOptions,
+}
+
+impl<'o> Canvas<'o> {
+    fn new(width: usize, height: usize, options: &'o RenderOptions) -> Self {
+        Self::with_buffer(Vec::new(), width, height, options)
+    }
+
+    fn with_buffer(
+        mut buf: Vec<u8>,
+        width: usize,
+        height: usize,
+        options: &'o RenderOptions,
+    ) -> Self {
+        buf.clear();
+        buf.resize(width * PIXEL_SIZE * height, 0);
+        Self {
+            buf,
+            width,
+            height,
+            options,
+        }
+    }
+
+    /// Writes the pixels of a composition object with its top-left corner at
+    /// `(x, y)`. Pixels falling outside the canvas are clipped.
+    fn draw_composition_object(
+        &mut self,
+        x: isize,
+        y: isize,
+        composition_object: &CompositionObject,
+        display_set: &DisplaySet,
+        palette: &PaletteTable,
+    ) -> PgsResult<()> {
+        let Some(object) = display_set.objects.get(&composition_object.id) else {
+            return Err(PgsError::ObjectNotFound {
+                object_id: composition_object.id,
+                display_set: format!("{:?}", display_set),
+            });
+        };
+        if object.width == 0 || object.height == 0 {
+            return Err(PgsError::InvalidObjectDimensions {
+                object_id: object.id,
+                width: object.width,
+                height: object.height,
+            });
+        }
+        let object_width = object.width as usize;
+        let (flip_h, flip_v) = match self.options.transform {
+            ObjectTransform::None => (false, false),
+            ObjectTransform::FlipH => (true, false),
+            ObjectTransform::FlipV => (false, true),
+            ObjectTransform::Rotate180 => (true, true),
+        };
+
+        for (row, range) in object.data.line_ranges().into_iter().enumerate() {
+            let mut column = 0;
+            for pixel in &object.data.0[range] {
+                let Some(pixel_color) = palette.0[pixel.color as usize] else {
+                    return Err(PgsError::PaletteNotFound {
+                        palette_id: display_set.palette_id,
+                        entry_id: pixel.color,
+                        display_set: format!("{:?}", display_set),
+                    });
+                };
+                // Runs never span scanlines, so anything reaching past the
+                // object's bounds is corrupt and would draw over its neighbours.
+                let end = column + pixel.count as usize;
+                if end > object_width || row >= object.height as usize {
+                    return Err(PgsError::RunLengthOverrun {
+                        object_id: object.id,
+                        width: object.width,
+                        height: object.height,
+                        line: row,
+                    });
+                }
+                for column in column..end {
+                    let canvas_x = x + if flip_h {
+                        object_width - 1 - column
+                    } else {
+                        column
+                    } as isize;
+                    let canvas_y = y + if flip_v {
+                        object.height as usize - 1 - row
+                    } else {
+                        row
+                    } as isize;
+                    let mut color = pixel_color;
+                    let visible = match crop_distance(column, row, composition_object) {
+                        0 => true,
+                        1 if self.options.feather_crop_edges => {
+                            color[0] /= 2;
+                            true
+                        }
+                        _ => false,
+                    };
+                    if visible
+                        && (0..self.width as isize).contains(&canvas_x)
+                        && (0..self.height as isize).contains(&canvas_y)
+                    {
+                        let pixel_offset =
+                            (canvas_y as usize * self.width + canvas_x as usize) * PIXEL_SIZE;
+                        self.buf[pixel_offset..pixel_offset + PIXEL_SIZE].copy_from_slice(&color);
+                    }
+                }
+                column = end;
+            }
+        }
+        Ok(())
+    }
+
+    /// Dilates the alpha channel by the outline's thickness and places the
+    /// outline color behind the drawn pixels. Only the area of each of
+    /// `bounds`, grown by the thickness, is touched.
+    ///
+    /// The disc the alpha is dilated with is split into one horizontal chord
+    /// per row, and the maximum along each chord is found in linear time, so
+    /// the cost grows with the thickness rather than its square.
+    fn draw_outline(&mut self, outline: &Outline, bounds: &[Bounds]) {
+        let radius = outline.thickness.min(MAX_OUTLINE_THICKNESS) as usize;
+        if radius == 0 {
+            return;
+        }
+        let color = rgba_to_ayuv(outline.color);
+        // Half the width of the disc's chord at each vertical distance from
+        // its center.
+        let chords: Vec<usize> = (0..=radius)
+            .map(|dy| ((radius * radius - dy * dy) as f64).sqrt() as usize)
+            .collect();
+
+        // Coverage of the outline at each canvas pixel, gathered from every
+        // area before any is drawn so overlapping areas see the same source.
+        let mut coverage = vec![0u8; self.width * self.height];
+        let mut areas = Vec::with_capacity(bounds.len());
+        let (mut alpha, mut dilated) = (Vec::new(), Vec::new());
+        let mut scratch = (Vec::new(), Vec::new());
+        for bound in bounds {
+            let left = bound.x.saturating_sub(radius).min(self.width);
+            let right = (bound.x + bound.width + radius).min(self.width);
+            let top = bound.y.saturating_sub(radius).min(self.height);
+            let bottom = (bound.y + bound.height + radius).min(self.height);
+            areas.push((left..right, top..bottom));
+            for source_y in top..bottom {
+                let row = source_y * self.width;
+                alpha.clear();
+                alpha.extend(
+                    self.buf[(row + left) * PIXEL_SIZE..(row + right) * PIXEL_SIZE]
+                        .iter()
+                        .step_by(PIXEL_SIZE),
+                );
+                if alpha.iter().all(|&value| value == 0) {
+                    continue;
+                }
+                // Spread this row to every row within the radius.
+                dilated.resize(alpha.len(), 0);
+                let rows =
+                    source_y.saturating_sub(radius).max(top)..(source_y + radius + 1).min(bottom);
+                for y in rows {
+                    let chord = chords[source_y.abs_diff(y)];
+                    dilate_row(&alpha, chord, &mut dilated, &mut scratch);
+                    let target = &mut coverage[y * self.width + left..y * self.width + right];
+                    for (target, &value) in target.iter_mut().zip(&dilated) {
+                        *target = (*target).max(value);
+                    }
+                }
+            }
+        }
+
+        for (columns, rows) in areas {
+            for y in rows {
+                for x in columns.clone() {
+                    // Taking the coverage keeps overlapping areas from drawing
+                    // the outline twice.
+                    let coverage = std::mem::take(&mut coverage[y * self.width + x]) as u32;
+                    if coverage == 0 {
+                        continue;
+                    }
+                    let offset = (y * self.width + x) * PIXEL_SIZE;
+                    let pixel = &mut self.buf[offset..offset + PIXEL_SIZE];
+                    // Composite the subtitle pixel over the outline.
+                    let top = pixel[0] as u32;
+                    let below = coverage * color[0] as u32 / 255 * (255 - top) / 255;
+                    let alpha = top + below;
+                    if alpha == 0 {
+                        continue;
+                    }
+                    for channel in 1..PIXEL_SIZE {
+                        pixel[channel] = ((pixel[channel] as u32 * top
+                            + color[channel] as u32 * below)
+                            / alpha) as u8;
+                    }
+                    pixel[0] = alpha as u8;
+                }
+            }
+        }
+    }
+
+    fn into_rgba(self) -> PgsResult<Vec<u8>> {
+        let stride = self.width * PIXEL_SIZE;
+        let mut rgba = vec![0u8; stride * self.height];
+        convert_with_options(
+            &self.buf,
+            self.width,
+            self.height,
+            &mut rgba,
+            stride,
+            self.options,
+        )?;
+        Ok(rgba)
+    }
+}
+
+/// Sets each `out[i]` to the largest value of `row` within `radius` of `i`,
+/// in time linear in the row's length whatever the radius, by splitting the
+/// row into blocks as wide as the window and combining running maxima from
+/// either side of the block boundary the window straddles (van Herk/Gil-Werman).
+fn dilate_row(row: &[u8], radius: usize, out: &mut [u8], scratch: &mut (Vec<u8>, Vec<u8>)) {
+    let window = 2 * radius + 1;
+    // The row padded with `radius` zeros on either side, so every window
+    // lies fully inside it.
+    let padded = |index: usize| {
+        index
+            .checked_sub(radius)
+            .and_then(|index| row.get(index))
+            .copied()
+            .unwrap_or(0)
+    };
+    let len = row.len() + 2 * radius;
+    let (from_block_start, to_block_end) = scratch;
+    from_block_start.resize(len, 0);
+    to_block_end.resize(len, 0);
+    for index in 0..len {
+        from_block_start[index] = if index % window == 0 {
+            padded(index)
+        } else {
+            from_block_start[index - 1].max(padded(index))
+        };
+    }
+    for index in (0..len).rev() {
+        to_block_end[index] = if index % window == window - 1 || index == len - 1 {
+            padded(index)
+        } else {
+            to_block_end[index + 1].max(padded(index))
+        };
+    }
+    for (index, out) in out.iter_mut().enumerate() {
+        *out = to_block_end[index].max(from_block_start[index + window - 1]);
+    }
+}
+
+/// Converts an RGBA color to the canvas' AYUV layout, using the same full
+/// range BT.709 matrix as the conversion back to RGBA.
+fn rgba_to_ayuv([r, g, b, a]: [u8; PIXEL_SIZE]) -> [u8; PIXEL_SIZE] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let cb = 128.0 + (b - y) / 1.8556;
+    let cr = 128.0 + (r - y) / 1.5748;
+    [
+        a,
+        y.round().clamp(0.0, 255.0) as u8,
+        cb.round().clamp(0.0, 255.0) as u8,
+        cr.round().clamp(0.0, 255.0) as u8,
+    ]
+}
+
+fn convert_to_rgba(buf: &[u8], width: usize, height: usize, range: YuvRange) -> PgsResult<Vec<u8>> {
+    let stride = width * PIXEL_SIZE;
+    let mut rgba = vec![0u8; stride * height];
+    convert_into_rgba(buf, width, height, &mut rgba, stride, range)?;
+    Ok(rgba)
+}
+
+/// Like [`convert_into_rgba`], converting with `options`' output range and
+/// then applying its gamma and TV range clamp to the RGB channels.
+fn convert_with_options(
+    buf: &[u8],
+    width: usize,
+    height: usize,
+    rgba: &mut [u8],
+    rgba_stride: usize,
+    options: &RenderOptions,
+) -> PgsResult<()> {
+    convert_into_rgba(buf, width, height, rgba, rgba_stride, options.output_range)?;
+    let gamma = (options.gamma != 1.0 && options.gamma > 0.0).then(|| {
+        let exponent = 1.0 / options.gamma;
+        std::array::from_fn::<u8, 256, _>(|value| {
+            (255.0 * (value as f32 / 255.0).powf(exponent)).round() as u8
+        })
+    });
+    if gamma.is_none() && !options.clamp_tv_range {
+        return Ok(());
+    }
+    for row in rgba.chunks_mut(rgba_stride).take(height) {
+        for pixel in row[..width * PIXEL_SIZE].chunks_exact_mut(PIXEL_SIZE) {
+            for channel in &mut pixel[..3] {
+                if let Some(table) = &gamma {
+                    *channel = table[*channel as usize];
+                }
+                if options.clamp_tv_range {
+                    *channel = (*channel).clamp(16, 235);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Converts a tightly packed AYUV canvas into `rgba`, writing each row at a
+/// multiple of `rgba_stride` bytes.
+fn convert_into_rgba(
+    buf: &[u8],
+    width: usize,
+    height: usize,
+    rgba: &mut [u8],
+    rgba_stride: usize,
+    range: YuvRange,
+) -> PgsResult<()> {
+    let stride = width * PIXEL_SIZE;
+    let image = YuvPackedImage {
+        yuy: buf,
+        yuy_stride: stride as u32,
+        width: width as u32,
+        height: height as u32,
+    };
+
+    image.check_constraints444()?;
+
+    yuv::ayuv_to_rgba(
+        &image,
+        rgba,
+        rgba_stride as u32,
+        range,
+        YuvStandardMatrix::Bt709,
+        false,
+    )?;
+
+    Ok(())
+}
+
+/// How many pixels the object-local pixel at `(x, y)` lies outside the
+/// composition object's crop rectangle, or 0 if it is inside (or uncropped).
+pub(crate) fn crop_distance(x: usize, y: usize, object: &CompositionObject) -> usize {
+    let Some(cropped) = &object.cropped else {
+        return 0;
+    };
+    let axis_distance = |position: usize, start: u16, length: u16| {
+        let start = start as usize;
+        let end = start + length as usize;
+        if position < start {
+            start - position
+        } else if position >= end {
+            position + 1 - end
+        } else {
+            0
+        }
+    };
+    // An empty crop rectangle hides the whole object.
+    if cropped.width == 0 || cropped.height == 0 {
+        return usize::MAX;
+    }
+    axis_distance(x, cropped.horizontal_position, cropped.width).max(axis_distance(
+        y,
+        cropped.vertical_position,
+        cropped.height,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        PALETTE, clear, composition_object, cue, end, ods, pcs, pcs_with_objects, pds, wds,
+        with_palette_id,
+    };
+    use crate::parse::SegmentContents;
+    use crate::parse_pgs;
+
+    fn first_display_set(pgs: &Pgs) -> DisplaySet<'_> {
+        DisplaySetIterator::new(pgs).next().unwrap()
+    }
+
+    #[test]
+    fn definitions_persist_until_the_next_epoch_start() {
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[(0, 0, 10, 10)]),
+            wds(0, &[(0, 10, 10, 4, 2)]),
+            pds(0, 0, &[(1, 235, 128, 128, 255)]),
+            ods(0, 0, 4, &[1; 8]),
+            end(0),
+            pcs(90_000, (64, 32), 1, 0x00, 0, &[(0, 0, 12, 10)]),
+            end(90_000),
+            pcs(180_000, (64, 32), 2, 0x80, 0, &[]),
+            end(180_000),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(&pgs).collect();
+
+        assert_eq!(display_sets.len(), 3);
+        assert_eq!(display_sets[1].windows, display_sets[0].windows);
+        assert_eq!(display_sets[1].palettes, display_sets[0].palettes);
+        assert_eq!(display_sets[1].objects, display_sets[0].objects);
+        assert!(display_sets[1].objects.contains_key(&0));
+        assert!(display_sets[2].windows.is_empty());
+        assert!(display_sets[2].palettes.is_empty());
+        assert!(display_sets[2].objects.is_empty());
+    }
+
+    #[test]
+    fn zero_width_frame_is_rejected() {
+        let pgs = parse_pgs(&mut cue(0, (0, 32), (0, 0), 2, &[1; 4])).unwrap();
+        let error = render_display_set(&first_display_set(&pgs)).unwrap_err();
+        assert!(matches!(
+            error,
+            PgsError::InvalidDimensions {
+                width: 0,
+                height: 32,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn zero_width_object_is_rejected() {
+        let mut pgs = parse_pgs(&mut cue(0, (64, 32), (0, 0), 2, &[1; 4])).unwrap();
+        for segment in &mut pgs.segments {
+            if let SegmentContents::ObjectDefinition(object) = &mut segment.contents {
+                object.width = 0;
+            }
+        }
+        let error = render_display_set(&first_display_set(&pgs)).unwrap_err();
+        assert!(matches!(
+            error,
+            PgsError::InvalidObjectDimensions {
+                object_id: 0,
+                width: 0,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn window_render_composites_its_objects() {
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0, &[(0, 0, 10, 10), (1, 0, 16, 10)]),
+            wds(0, &[(0, 10, 10, 8, 2)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 2, &[1; 4]),
+            ods(0, 1, 2, &[3; 4]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let (width, height, rgba) = render_window(&first_display_set(&pgs), 0).unwrap();
+
+        assert_eq!((width, height), (8, 2));
+        assert_eq!(rgba.len(), 8 * 2 * PIXEL_SIZE);
+        let pixel = |x: usize, y: usize| &rgba[(y * 8 + x) * PIXEL_SIZE..][..PIXEL_SIZE];
+        assert_eq!(pixel(1, 1), [255, 255, 255, 255]);
+        assert_eq!(pixel(6, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(3, 0)[3], 0);
+    }
+
+    #[test]
+    fn stream_renderer_matches_per_set_rendering() {
+        let mut data = [
+            cue(0, (64, 32), (8, 8), 2, &[1, 2, 3, 0]),
+            pcs(9_000, (64, 32), 1, 0x00, 0x80, &[(0, 0, 8, 8)]),
+            pds(
+                9_000,
+                1,
+                &[
+                    (0, 0, 128, 128, 0),
+                    (1, 0, 128, 128, 255),
+                    (2, 90, 100, 150, 200),
+                    (3, 255, 128, 128, 64),
+                ],
+            ),
+            end(9_000),
+            pcs(18_000, (64, 32), 2, 0x00, 0, &[(0, 0, 10, 8)]),
+            end(18_000),
+            pcs(27_000, (64, 32), 3, 0x80, 0, &[(0, 0, 8, 8)]),
+            wds(27_000, &[(0, 8, 8, 2, 2)]),
+            pds(27_000, 0, &[(1, 128, 200, 60, 255), (2, 0, 128, 128, 0)]),
+            ods(27_000, 0, 2, &[1, 1, 2, 2]),
+            end(27_000),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        let mut renderer = StreamRenderer::new();
+        let mut rendered = 0;
+        for display_set in DisplaySetIterator::new(&pgs) {
+            assert_eq!(
+                renderer.render(&display_set).unwrap(),
+                render_display_set(&display_set).unwrap()
+            );
+            rendered += 1;
+        }
+        assert_eq!(rendered, 4);
+    }
+
+    #[test]
+    fn feathered_crop_keeps_half_alpha_on_the_boundary() {
+        let cropped = composition_object(0, 0, (0, 0), 0x40, Some((0, 0, 2, 1)));
+        let mut data = [
+            pcs_with_objects(0, (8, 1), 0, 0x80, 0, &[cropped]),
+            wds(0, &[(0, 0, 0, 4, 1)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 4, &[1; 4]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = first_display_set(&pgs);
+        let alpha = |rgba: &[u8], x: usize| rgba[x * PIXEL_SIZE + 3];
+
+        let hard = render_display_set(&display_set).unwrap();
+        assert_eq!(
+            [alpha(&hard, 1), alpha(&hard, 2), alpha(&hard, 3)],
+            [255, 0, 0]
+        );
+
+        let options = RenderOptions {
+            feather_crop_edges: true,
+            ..RenderOptions::default()
+        };
+        let feathered = render_display_set_with_options(&display_set, &options).unwrap();
+        assert_eq!(alpha(&feathered, 1), 255);
+        assert_eq!(alpha(&feathered, 2), 127);
+        assert_eq!(alpha(&feathered, 3), 0);
+    }
+
+    #[test]
+    fn ayuv_render_carries_the_palette_entry() {
+        let mut data = [
+            pcs(0, (4, 2), 0, 0x80, 0, &[(0, 0, 0, 0)]),
+            wds(0, &[(0, 0, 0, 2, 1)]),
+            pds(0, 0, &[(1, 90, 200, 60, 170)]),
+            ods(0, 0, 2, &[1; 2]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let ayuv = render_display_set_ayuv(&first_display_set(&pgs)).unwrap();
+
+        assert_eq!(ayuv.len(), 4 * 2 * PIXEL_SIZE);
+        assert_eq!(ayuv[..PIXEL_SIZE], [170, 90, 60, 200]);
+        assert_eq!(ayuv[2 * PIXEL_SIZE..3 * PIXEL_SIZE], [0; PIXEL_SIZE]);
+    }
+
+    #[test]
+    fn sampled_frame_count_matches_duration_times_fps() {
+        let mut data = [
+            cue(9_000, (4, 2), (0, 0), 2, &[1; 4]),
+            clear(54_000, (4, 2)),
+            clear(99_000, (4, 2)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let frames: Vec<(Duration, Vec<u8>)> = sampled_frames(&pgs, 10.0)
+            .collect::<PgsResult<_>>()
+            .unwrap();
+
+        assert_eq!(frames.len(), 10);
+        assert_eq!(frames[0].0, Duration::from_millis(100));
+        assert_eq!(frames[9].0, Duration::from_millis(1000));
+        let shown = |rgba: &[u8]| rgba[3] != 0;
+        assert!(frames[..5].iter().all(|(_, rgba)| shown(rgba)));
+        assert!(frames[5..].iter().all(|(_, rgba)| !shown(rgba)));
+        assert!(
+            frames
+                .iter()
+                .all(|(_, rgba)| rgba.len() == 4 * 2 * PIXEL_SIZE)
+        );
+    }
+
+    #[test]
+    fn run_longer_than_the_object_width_is_rejected() {
+        let mut pgs = parse_pgs(&mut cue(0, (64, 32), (0, 0), 4, &[1; 8])).unwrap();
+        for segment in &mut pgs.segments {
+            if let SegmentContents::ObjectDefinition(object) = &mut segment.contents {
+                object.width = 3;
+            }
+        }
+        let error = render_display_set(&first_display_set(&pgs)).unwrap_err();
+        assert!(matches!(
+            error,
+            PgsError::RunLengthOverrun {
+                object_id: 0,
+                width: 3,
+                height: 2,
+                line: 0,
+            }
+        ));
+    }
+
+    #[test]
+    fn debug_render_only_fills_transparent_pixels() {
+        let pgs = parse_pgs(&mut cue(0, (8, 4), (2, 1), 3, &[1, 2, 0, 3, 0, 1])).unwrap();
+        let display_set = first_display_set(&pgs);
+        let real = render_display_set(&display_set).unwrap();
+        let colors = [[255, 0, 0, 255], [0, 0, 255, 255]];
+        let background = DebugBackground::Checkerboard { size: 2, colors };
+        let debug = render_display_set_debug(&display_set, &background).unwrap();
+
+        assert_eq!(debug.len(), real.len());
+        for (real, debug) in real
+            .chunks_exact(PIXEL_SIZE)
+            .zip(debug.chunks_exact(PIXEL_SIZE))
+        {
+            if real[3] == 0 {
+                assert!(colors.iter().any(|color| color == debug));
+            } else {
+                assert_eq!(debug, real);
+            }
+        }
+        assert_eq!(debug[..PIXEL_SIZE], colors[0]);
+        assert_eq!(debug[2 * PIXEL_SIZE..3 * PIXEL_SIZE], colors[1]);
+    }
+
+    #[test]
+    fn strided_render_places_each_row_at_its_stride() {
+        let pgs = parse_pgs(&mut cue(0, (4, 3), (1, 1), 2, &[1, 3, 3, 1])).unwrap();
+        let display_set = first_display_set(&pgs);
+        let packed = render_display_set(&display_set).unwrap();
+        let row_len = 4 * PIXEL_SIZE;
+        let stride = row_len + 16;
+        let mut out = vec![0xAA; stride * 3];
+        render_display_set_strided(&display_set, &mut out, stride).unwrap();
+
+        for row in 0..3 {
+            let (line, padding) = out[row * stride..(row + 1) * stride].split_at(row_len);
+            assert_eq!(line, &packed[row * row_len..(row + 1) * row_len]);
+            assert!(padding.iter().all(|&byte| byte == 0xAA));
+        }
+
+        assert!(matches!(
+            render_display_set_strided(&display_set, &mut out, row_len - 1),
+            Err(PgsError::InvalidStride { .. })
+        ));
+        assert!(matches!(
+            render_display_set_strided(&display_set, &mut out[..stride * 2], stride),
+            Err(PgsError::BufferTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn resolved_objects_bundle_their_definitions() {
+        let pgs = parse_pgs(&mut cue(0, (64, 32), (6, 4), 3, &[1; 6])).unwrap();
+        let display_set = first_display_set(&pgs);
+        let resolved = display_set.resolved_objects();
+
+        assert_eq!(resolved.len(), 1);
+        let resolved = resolved[0];
+        assert_eq!(
+            resolved.composition_object,
+            &display_set.composition_objects[0]
+        );
+        let window = resolved.window.unwrap();
+        assert_eq!(
+            (window.horizontal_position, window.vertical_position),
+            (6, 4)
+        );
+        assert_eq!((window.width, window.height), (3, 2));
+        assert_eq!(resolved.palette.unwrap().entries.len(), PALETTE.len());
+        let object = resolved.object.unwrap();
+        assert_eq!((object.id, object.width, object.height), (0, 3, 2));
+    }
+
+    #[test]
+    fn render_pool_reuses_buffers() {
+        let pgs = parse_pgs(&mut cue(0, (16, 8), (2, 2), 2, &[1; 4])).unwrap();
+        let display_set = first_display_set(&pgs);
+        let pool = RenderPool::new(2);
+
+        let frame = pool.render(&display_set).unwrap();
+        let pixels = frame.as_ptr();
+        assert_eq!(&*frame, render_display_set(&display_set).unwrap());
+        assert_eq!(pool.idle_buffers(16, 8), 0);
+        drop(frame);
+        assert_eq!(pool.idle_buffers(16, 8), 1);
+
+        for _ in 0..3 {
+            let frame = pool.render(&display_set).unwrap();
+            assert_eq!(frame.as_ptr(), pixels);
+            assert_eq!(pool.idle_buffers(16, 8), 0);
+        }
+        assert_eq!(pool.idle_buffers(16, 8), 1);
+    }
+
+    #[test]
+    fn rendering_uses_the_palette_selected_by_the_composition() {
+        let mut data = [
+            with_palette_id(pcs(0, (4, 1), 0, 0x80, 0, &[(0, 0, 0, 0)]), 1),
+            wds(0, &[(0, 0, 0, 2, 1)]),
+            pds(0, 0, &[(1, 0, 128, 128, 255)]),
+            with_palette_id(
+                pds(0, 0, &[(1, 255, 128, 128, 255), (2, 0, 128, 128, 0)]),
+                1,
+            ),
+            ods(0, 0, 2, &[1, 2]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = first_display_set(&pgs);
+        assert_eq!(display_set.palette_id, 1);
+
+        let lut = display_set.palette_lut().unwrap();
+        assert_eq!(lut[PIXEL_SIZE..2 * PIXEL_SIZE], [255, 255, 255, 255]);
+        assert_eq!(lut[2 * PIXEL_SIZE + 3], 0);
+        assert_eq!(lut[3 * PIXEL_SIZE..4 * PIXEL_SIZE], [0; PIXEL_SIZE]);
+
+        let rgba = render_display_set(&display_set).unwrap();
+        assert_eq!(rgba[..PIXEL_SIZE], [255, 255, 255, 255]);
+        assert_eq!(StreamRenderer::new().render(&display_set).unwrap(), rgba);
+        let layers = render_color_layers(&display_set).unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].0.luminance, 255);
+        assert!(pgs.can_render().is_ok());
+    }
+
+    #[test]
+    fn missing_selected_palette_is_reported_by_its_id() {
+        let mut data = [
+            with_palette_id(pcs(0, (4, 1), 0, 0x80, 0, &[(0, 0, 0, 0)]), 2),
+            wds(0, &[(0, 0, 0, 2, 1)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 2, &[1, 1]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = first_display_set(&pgs);
+
+        let error = render_display_set(&display_set).unwrap_err();
+        assert!(matches!(
+            error,
+            PgsError::PaletteNotFound {
+                palette_id: 2,
+                entry_id: 1,
+                ..
+            }
+        ));
+        let errors = pgs.can_render().unwrap_err();
+        assert!(matches!(
+            errors[..],
+            [PgsError::PaletteNotFound { palette_id: 2, .. }]
+        ));
+    }
+
+    #[test]
+    fn render_all_collects_errors_past_a_broken_set() {
+        let mut data = [
+            cue(0, (8, 4), (0, 0), 2, &[1; 4]),
+            pcs(9_000, (8, 4), 1, 0x00, 0, &[(5, 0, 0, 0)]),
+            end(9_000),
+            cue(18_000, (8, 4), (2, 2), 2, &[3; 4]),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        let (rendered, errors) = render_all(&pgs, ErrorMode::CollectAll);
+        let indices: Vec<usize> = rendered.iter().map(|frame| frame.index).collect();
+        assert_eq!(indices, [0, 2]);
+        assert_eq!(rendered[1].presentation_timestamp, 18_000);
+        assert!(matches!(
+            errors[..],
+            [(1, PgsError::ObjectNotFound { object_id: 5, .. })]
+        ));
+
+        let (rendered, errors) = render_all(&pgs, ErrorMode::AbortOnFirst);
+        assert_eq!(rendered.len(), 1);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn timeline_finds_the_cue_on_screen() {
+        let mut data = [
+            cue(9_000, (8, 4), (0, 0), 2, &[1; 4]),
+            clear(18_000, (8, 4)),
+            cue(27_000, (8, 4), (2, 2), 2, &[3; 4]),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let timeline = Timeline::build(&pgs).unwrap();
+
+        assert_eq!(timeline.cues().count(), 2);
+        assert!(timeline.frame_at(0).is_none());
+        assert_eq!(timeline.frame_at(9_000).unwrap().index, 0);
+        assert_eq!(timeline.frame_at(17_999).unwrap().index, 0);
+        assert!(timeline.frame_at(18_000).is_none());
+        let last = timeline.frame_at(1_000_000).unwrap();
+        assert_eq!(last.index, 2);
+        assert_eq!(
+            last.rgba[(2 * 8 + 2) * PIXEL_SIZE..][..PIXEL_SIZE],
+            [0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn planar_y_plane_holds_palette_luminance() {
+        let mut data = [
+            pcs(0, (3, 2), 0, 0x80, 0, &[(0, 0, 0, 0)]),
+            wds(0, &[(0, 0, 0, 3, 1)]),
+            pds(0, 0, &[(1, 40, 200, 60, 255), (2, 180, 90, 30, 128)]),
+            ods(0, 0, 3, &[1, 2, 1]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let image = render_display_set_planar(&first_display_set(&pgs)).unwrap();
+
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.y, [40, 180, 40, 0, 0, 0]);
+        assert_eq!(image.cb[..3], [60, 30, 60]);
+        assert_eq!(image.cr[..3], [200, 90, 200]);
+        assert_eq!(image.alpha, [255, 128, 255, 0, 0, 0]);
+    }
+
+    #[test]
+    fn stream_ending_without_an_end_segment_is_reported() {
+        let mut data = [
+            pcs(0, (8, 4), 0, 0x80, 0, &[(0, 0, 0, 0)]),
+            wds(0, &[(0, 0, 0, 2, 2)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 2, &[1; 4]),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        let mut display_sets = DisplaySetIterator::new(&pgs);
+        assert!(matches!(
+            display_sets.try_next(),
+            Some(Err(PgsError::UnterminatedDisplaySet { segment: 0 }))
+        ));
+        assert!(display_sets.try_next().is_none());
+
+        let display_set = first_display_set(&pgs);
+        assert!(display_set.objects.contains_key(&0));
+    }
+
+    #[test]
+    fn segments_outside_a_display_set_are_reported() {
+        let mut data = [
+            pds(0, 0, &PALETTE),
+            end(0),
+            cue(9_000, (8, 4), (0, 0), 2, &[1; 4]),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        let mut display_sets = DisplaySetIterator::new(&pgs);
+        assert!(matches!(
+            display_sets.try_next(),
+            Some(Err(PgsError::MissingComposition { segment: 0 }))
+        ));
+        let display_set = display_sets.try_next().unwrap().unwrap();
+        assert_eq!(display_set.presentation_timestamp, 9_000);
+        assert!(display_sets.try_next().is_none());
+
+        let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(&pgs).collect();
+        assert_eq!(display_sets.len(), 1);
+    }
+
+    // Regressions for inputs that used to panic when fuzzing `parse_pgs`.
+
+    #[test]
+    fn mismatched_segment_timestamps_are_reported() {
+        let mut data = [pcs(0, (8, 4), 0, 0x80, 0, &[]), end(1)].concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        assert!(matches!(
+            DisplaySetIterator::new(&pgs).try_next(),
+            Some(Err(PgsError::TimestampMismatch { segment: 1 }))
+        ));
+    }
+
+    #[test]
+    fn composition_in_the_middle_of_a_display_set_starts_the_next() {
+        let mut data = [
+            pcs(0, (8, 4), 0, 0x80, 0, &[]),
+            pcs(0, (8, 4), 1, 0x00, 0, &[]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let mut display_sets = DisplaySetIterator::new(&pgs);
+        assert!(matches!(
+            display_sets.try_next(),
+            Some(Err(PgsError::UnterminatedDisplaySet { segment: 0 }))
+        ));
+        assert_eq!(
+            display_sets.try_next().unwrap().unwrap().composition_number,
+            1
+        );
+    }
+
+    fn outlined(display_set: &DisplaySet, thickness: u8) -> Vec<u8> {
+        let options = RenderOptions {
+            outline: Some(Outline {
+                color: [0, 0, 0, 255],
+                thickness,
+            }),
+            ..RenderOptions::default()
+        };
+        render_display_set_with_options(display_set, &options).unwrap()
+    }
+
+    #[test]
+    fn one_pixel_outline_surrounds_an_opaque_region() {
+        let pgs = parse_pgs(&mut cue(0, (8, 8), (3, 3), 2, &[1; 4])).unwrap();
+        let rgba = outlined(&first_display_set(&pgs), 1);
+        let pixel = |x: usize, y: usize| &rgba[(y * 8 + x) * PIXEL_SIZE..][..PIXEL_SIZE];
+
+        assert_eq!(pixel(3, 3), [255, 255, 255, 255]);
+        for (x, y) in [
+            (2, 3),
+            (2, 4),
+            (5, 3),
+            (5, 4),
+            (3, 2),
+            (4, 2),
+            (3, 5),
+            (4, 5),
+        ] {
+            assert_eq!(pixel(x, y), [0, 0, 0, 255], "({x}, {y})");
+        }
+        for (x, y) in [(2, 2), (5, 5), (1, 3), (3, 6)] {
+            assert_eq!(pixel(x, y)[3], 0, "({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn outline_matches_a_disc_around_overlapping_objects() {
+        let mut data = [
+            pcs(0, (16, 12), 0, 0x80, 0, &[(0, 0, 2, 2), (1, 0, 6, 3)]),
+            wds(0, &[(0, 0, 0, 16, 12)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 3, &[1, 0, 0, 0, 1, 0, 0, 0, 1]),
+            ods(0, 1, 2, &[0, 3, 0, 0]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = first_display_set(&pgs);
+        let drawn = render_display_set(&display_set).unwrap();
+        let radius = 3isize;
+        let rgba = outlined(&display_set, radius as u8);
+
+        for y in 0..12isize {
+            for x in 0..16isize {
+                let covered = (-radius..=radius).any(|dy| {
+                    (-radius..=radius).any(|dx| {
+                        let (sx, sy) = (x + dx, y + dy);
+                        dx * dx + dy * dy <= radius * radius
+                            && (0..16).contains(&sx)
+                            && (0..12).contains(&sy)
+                            && drawn[(sy * 16 + sx) as usize * PIXEL_SIZE + 3] != 0
+                    })
+                });
+                let alpha = rgba[(y * 16 + x) as usize * PIXEL_SIZE + 3];
+                assert_eq!(alpha, if covered { 255 } else { 0 }, "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn outline_thickness_is_capped() {
+        let pgs = parse_pgs(&mut cue(0, (80, 1), (0, 0), 1, &[1])).unwrap();
+        let rgba = outlined(&first_display_set(&pgs), u8::MAX);
+        let reach = MAX_OUTLINE_THICKNESS as usize;
+        assert_eq!(rgba[reach * PIXEL_SIZE + 3], 255);
+        assert_eq!(rgba[(reach + 1) * PIXEL_SIZE + 3], 0);
+    }
+
+    #[test]
+    fn composited_tracks_both_appear_with_the_second_on_top() {
+        let first = parse_pgs(&mut cue(0, (8, 4), (0, 0), 4, &[1; 4])).unwrap();
+        // Authored at twice the frame size, covering the first's left half.
+        let second = parse_pgs(&mut cue(0, (16, 8), (0, 0), 4, &[3; 8])).unwrap();
+        let rgba = composite_display_sets(
+            &[&first_display_set(&first), &first_display_set(&second)],
+            8,
+            4,
+        )
+        .unwrap();
+
+        let pixel = |x: usize, y: usize| &rgba[(y * 8 + x) * PIXEL_SIZE..][..PIXEL_SIZE];
+        assert_eq!(pixel(0, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(1, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(2, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(3, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(4, 0)[3], 0);
+        assert_eq!(pixel(0, 1)[3], 0);
+    }
+
+    #[test]
+    fn tv_range_clamp_applies_to_every_render_path() {
+        let pgs = parse_pgs(&mut cue(0, (4, 2), (1, 0), 2, &[1, 3, 3, 1])).unwrap();
+        let display_set = first_display_set(&pgs);
+        let options = RenderOptions {
+            clamp_tv_range: true,
+            ..RenderOptions::default()
+        };
+        let in_tv_range = |rgba: &[u8]| {
+            rgba.chunks_exact(PIXEL_SIZE)
+                .filter(|pixel| pixel[3] != 0)
+                .all(|pixel| {
+                    pixel[..3]
+                        .iter()
+                        .all(|channel| (16..=235).contains(channel))
+                })
+        };
+
+        let unclamped = render_display_set(&display_set).unwrap();
+        assert!(!in_tv_range(&unclamped));
+
+        let rgba = render_display_set_with_options(&display_set, &options).unwrap();
+        assert!(in_tv_range(&rgba));
+        assert_eq!(&rgba[PIXEL_SIZE..2 * PIXEL_SIZE], [235, 235, 235, 255]);
+        assert_eq!(&rgba[2 * PIXEL_SIZE..3 * PIXEL_SIZE], [16, 16, 16, 255]);
+
+        let mut strided = vec![0; rgba.len()];
+        render_display_set_strided_with_options(&display_set, &options, &mut strided, 16).unwrap();
+        assert_eq!(strided, rgba);
+
+        let (_, _, window) = render_window_with_options(&display_set, 0, &options).unwrap();
+        assert!(in_tv_range(&window));
+
+        let pool = RenderPool::with_options(1, options);
+        assert_eq!(&pool.render(&display_set).unwrap()[..], &rgba[..]);
+    }
+
+    #[test]
+    fn transforms_mirror_the_object_within_its_bounds() {
+        // A white pixel in the top left corner of an otherwise black object.
+        let pgs = parse_pgs(&mut cue(0, (5, 2), (1, 0), 3, &[1, 3, 3, 3, 3, 3])).unwrap();
+        let display_set = first_display_set(&pgs);
+        let white_pixel = |transform| {
+            let options = RenderOptions {
+                transform,
+                ..RenderOptions::default()
+            };
+            let rgba = render_display_set_with_options(&display_set, &options).unwrap();
+            let pixels: Vec<&[u8]> = rgba.chunks_exact(PIXEL_SIZE).collect();
+            assert_eq!(pixels.iter().filter(|pixel| pixel[3] == 255).count(), 6);
+            let index = pixels
+                .iter()
+                .position(|pixel| *pixel == [255, 255, 255, 255])
+                .unwrap();
+            (index % 5, index / 5)
+        };
+
+        assert_eq!(white_pixel(ObjectTransform::None), (1, 0));
+        assert_eq!(white_pixel(ObjectTransform::FlipH), (3, 0));
+        assert_eq!(white_pixel(ObjectTransform::FlipV), (1, 1));
+        assert_eq!(white_pixel(ObjectTransform::Rotate180), (3, 1));
+    }
+
+    #[test]
+    fn accessors_expose_the_definitions_in_use() {
+        let pgs = parse_pgs(&mut cue(0, (64, 32), (6, 4), 3, &[1; 6])).unwrap();
+        let display_set = first_display_set(&pgs);
+
+        assert_eq!(
+            display_set.composition_objects(),
+            display_set.composition_objects
+        );
+        assert_eq!(display_set.composition_objects()[0].horizontal_position, 6);
+        let windows: Vec<&Window> = display_set.windows().collect();
+        assert_eq!(windows, [display_set.window(0).unwrap()]);
+        assert_eq!((windows[0].width, windows[0].height), (3, 2));
+        let palettes: Vec<&PaletteDefinition> = display_set.palettes().collect();
+        assert_eq!(palettes, [display_set.palette(0).unwrap()]);
+        assert_eq!(palettes[0].entries.len(), PALETTE.len());
+        let objects: Vec<&ObjectDefinition> = display_set.objects().collect();
+        assert_eq!(objects, [display_set.object(0).unwrap()]);
+        assert_eq!((objects[0].width, objects[0].height), (3, 2));
+        assert_eq!(display_set.window(1), None);
+        assert_eq!(display_set.palette(1), None);
+        assert_eq!(display_set.object(1), None);
+    }
+
+    #[test]
+    fn gamma_brightens_rgb_but_keeps_alpha_on_every_render_path() {
+        let mut data = [
+            pcs(0, (4, 2), 0, 0x80, 0, &[(0, 0, 1, 0)]),
+            wds(0, &[(0, 1, 0, 2, 2)]),
+            pds(0, 0, &[(1, 128, 128, 128, 255), (2, 64, 128, 128, 100)]),
+            ods(0, 0, 2, &[1, 2, 2, 1]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = first_display_set(&pgs);
+        let options = RenderOptions {
+            gamma: 2.0,
+            ..RenderOptions::default()
+        };
+
+        let plain = render_display_set(&display_set).unwrap();
+        let rgba = render_display_set_with_options(&display_set, &options).unwrap();
+        for (plain, adjusted) in plain
+            .chunks_exact(PIXEL_SIZE)
+            .zip(rgba.chunks_exact(PIXEL_SIZE))
+        {
+            assert_eq!(adjusted[3], plain[3]);
+            if plain[3] != 0 {
+                assert!(adjusted[..3].iter().zip(&plain[..3]).all(|(a, p)| a > p));
+            }
+        }
+
+        let mut strided = vec![0; rgba.len()];
+        render_display_set_strided_with_options(&display_set, &options, &mut strided, 16).unwrap();
+        assert_eq!(strided, rgba);
+        let (_, _, window) = render_window_with_options(&display_set, 0, &options).unwrap();
+        assert_eq!(&window[..2 * PIXEL_SIZE], &rgba[PIXEL_SIZE..3 * PIXEL_SIZE]);
+        let pool = RenderPool::with_options(1, options);
+        assert_eq!(&pool.render(&display_set).unwrap()[..], &rgba[..]);
+    }
+
+    #[test]
+    fn binary_mask_splits_half_alpha_by_threshold() {
+        // Opaque white, half transparent white, transparent and opaque black.
+        let pgs = parse_pgs(&mut cue(0, (2, 2), (0, 0), 2, &[1, 2, 0, 3])).unwrap();
+        let display_set = first_display_set(&pgs);
+        let mask = |threshold| render_binary_mask(&display_set, threshold).unwrap();
+
+        assert_eq!(mask(0), (2, 2, vec![255, 255, 0, 255]));
+        assert_eq!(mask(127), (2, 2, vec![255, 255, 0, 255]));
+        assert_eq!(mask(128), (2, 2, vec![255, 0, 0, 255]));
+        assert_eq!(mask(255), (2, 2, vec![0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn comparing_against_a_recolored_copy_reports_the_change() {
+        let stream = |indices: &[u8]| {
+            parse_pgs(&mut [cue(0, (4, 2), (0, 0), 2, indices), clear(9_000, (4, 2))].concat())
+                .unwrap()
+        };
+        let white = stream(&[1; 4]);
+        let black = stream(&[3; 4]);
+
+        let same = compare_rendered(&white, &white).unwrap();
+        assert!(!same.is_empty());
+        assert!(
+            same.iter()
+                .all(|diff| diff.max_difference == 0 && diff.mean_difference == 0.0)
+        );
+
+        let recolored = compare_rendered(&white, &black).unwrap();
+        // Half of the frame changes from white to black in its RGB channels.
+        assert_eq!(
+            recolored[0],
+            CueDiff {
+                presentation_timestamp: 0,
+                max_difference: 255,
+                mean_difference: (4 * 3 * 255) as f64 / (8 * PIXEL_SIZE) as f64,
+            }
+        );
+        assert!(recolored[1..].iter().all(|diff| diff.max_difference == 0));
+    }
+
+    #[test]
+    fn skip_empty_drops_clears_but_keeps_their_definitions() {
+        let mut data = [
+            cue(0, (64, 32), (0, 0), 2, &[1; 4]),
+            // A clear that also redefines the palette for the next cue.
+            pcs(9_000, (64, 32), 1, 0x00, 0, &[]),
+            pds(9_000, 1, &[(1, 0, 128, 128, 255)]),
+            end(9_000),
+            pcs(18_000, (64, 32), 2, 0x00, 0, &[(0, 0, 4, 4)]),
+            end(18_000),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        let all: Vec<DisplaySet> = DisplaySetIterator::new(&pgs).collect();
+        let shown: Vec<DisplaySet> = DisplaySetIterator::new(&pgs).skip_empty().collect();
+        assert_eq!(all.len(), 3);
+        assert_eq!(shown.len(), 2);
+        assert_eq!(shown[0], all[0]);
+        assert_eq!(shown[1], all[2]);
+        let times: Vec<u32> = shown
+            .iter()
+            .map(|display_set| display_set.presentation_timestamp)
+            .collect();
+        assert_eq!(times, [0, 18_000]);
+        assert_eq!(shown[1].palette(0).unwrap().version, 1);
+    }
+
+    #[test]
+    fn full_hd_subtitle_renders_scaled_onto_a_720p_frame() {
+        // A 30x12 object, white on its left half and black on its right.
+        let indices: Vec<u8> = (0..12).flat_map(|_| [[1; 15], [3; 15]].concat()).collect();
+        let pgs = parse_pgs(&mut cue(0, (1920, 1080), (300, 900), 30, &indices)).unwrap();
+        let rgba = render_display_set_at_resolution(&first_display_set(&pgs), 1280, 720).unwrap();
+
+        assert_eq!(rgba.len(), 1280 * 720 * PIXEL_SIZE);
+        let pixel = |x: usize, y: usize| &rgba[(y * 1280 + x) * PIXEL_SIZE..][..PIXEL_SIZE];
+        // Two thirds of the position and size: 20x8 at (200, 600).
+        let opaque = rgba
+            .chunks_exact(PIXEL_SIZE)
+            .filter(|pixel| pixel[3] != 0)
+            .count();
+        assert_eq!(opaque, 20 * 8);
+        assert_eq!(pixel(200, 600), [255, 255, 255, 255]);
+        assert_eq!(pixel(209, 607), [255, 255, 255, 255]);
+        assert_eq!(pixel(210, 600), [0, 0, 0, 255]);
+        assert_eq!(pixel(219, 607), [0, 0, 0, 255]);
+        assert_eq!(pixel(199, 600)[3], 0);
+        assert_eq!(pixel(220, 600)[3], 0);
+        assert_eq!(pixel(200, 608)[3], 0);
+    }
+
+    #[test]
+    fn coverage_plane_holds_palette_alphas_and_rgb_is_premultiplied() {
+        // Opaque white, half transparent white, transparent and opaque black.
+        let pgs = parse_pgs(&mut cue(0, (2, 2), (0, 0), 2, &[1, 2, 0, 3])).unwrap();
+        let (rgb, coverage) =
+            render_display_set_rgb_plus_coverage(&first_display_set(&pgs)).unwrap();
+
+        assert_eq!(coverage, [255, 128, 0, 255]);
+        assert_eq!(rgb, [255, 255, 255, 128, 128, 128, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn thumbnails_fit_within_max_dim() {
+        let mut data = [
+            cue(90_000, (64, 32), (4, 4), 16, &[1; 16 * 4]),
+            clear(180_000, (64, 32)),
+            cue(270_000, (64, 32), (10, 2), 2, &[3, 1, 1, 3, 1, 3]),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        let thumbnails = thumbnails(&pgs, 8).collect::<PgsResult<Vec<_>>>().unwrap();
+        let sizes: Vec<(Duration, u32, u32)> = thumbnails
+            .iter()
+            .map(|(time, image)| (*time, image.width(), image.height()))
+            .collect();
+        // The wide cue is downscaled to fit, the small one kept as is.
+        assert_eq!(
+            sizes,
+            [
+                (Duration::from_secs(1), 8, 2),
+                (Duration::from_secs(3), 2, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn rgb565_packs_each_pixel_composited_over_black() {
+        let mut data = cue(0, (2, 2), (0, 0), 2, &[1, 2, 3, 0]);
+        let pgs = parse_pgs(&mut data).unwrap();
+        let rgb565 = render_display_set_rgb565(&first_display_set(&pgs)).unwrap();
+        // Opaque white, white at half alpha darkened to 128 per channel,
+        // opaque black and transparent.
+        assert_eq!(rgb565, [0xFFFF, 0x8410, 0x0000, 0x0000]);
+    }
+
+    #[test]
+    fn half_pixel_offset_lands_between_whole_pixel_positions() {
+        let mut at_10 = cue(0, (16, 1), (10, 0), 2, &[1, 1]);
+        let mut at_11 = cue(0, (16, 1), (11, 0), 2, &[1, 1]);
+        let (at_10, at_11) = (
+            parse_pgs(&mut at_10).unwrap(),
+            parse_pgs(&mut at_11).unwrap(),
+        );
+        let display_set = first_display_set(&at_10);
+
+        let half = render_display_set_subpixel(&display_set, 0.5, 0.0).unwrap();
+        assert_ne!(
+            half,
+            render_display_set_subpixel(&display_set, 0.0, 0.0).unwrap()
+        );
+        assert_ne!(
+            half,
+            render_display_set(&first_display_set(&at_11)).unwrap()
+        );
+        // The two pixel wide object covers half of x=10 and x=12.
+        let alphas: Vec<u8> = half
+            .chunks_exact(PIXEL_SIZE)
+            .map(|pixel| pixel[3])
+            .collect();
+        assert_eq!(alphas[9..14], [0, 128, 255, 128, 0]);
+        assert_eq!(half[10 * PIXEL_SIZE..10 * PIXEL_SIZE + 3], [255, 255, 255]);
+    }
+
+    #[test]
+    fn onion_skin_shows_the_previous_cue_dimmed_under_the_current_one() {
+        let mut data = [
+            cue(0, (8, 1), (0, 0), 2, &[1, 1]),
+            cue(90_000, (8, 1), (4, 0), 2, &[3, 3]),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(&pgs).collect();
+
+        let rgba = render_onion_skin(&display_sets[0], &display_sets[1], 0.5).unwrap();
+        let pixel = |x: usize| &rgba[x * PIXEL_SIZE..(x + 1) * PIXEL_SIZE];
+        assert_eq!(pixel(0), [255, 255, 255, 128]);
+        assert_eq!(pixel(1), [255, 255, 255, 128]);
+        assert_eq!(pixel(2)[3], 0);
+        assert_eq!(pixel(4), [0, 0, 0, 255]);
+        assert_eq!(pixel(5), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn last_display_set_of_each_epoch_is_a_boundary() {
+        let mut data = [
+            cue(0, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(90_000, (64, 32)),
+            cue(180_000, (64, 32), (0, 0), 2, &[3; 4]),
+            clear(270_000, (64, 32)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let flags: Vec<bool> = pgs
+            .display_sets_by_epoch()
+            .map(|tagged| tagged.is_last_in_epoch)
+            .collect();
+        assert_eq!(flags, [false, true, false, true]);
+        let boundaries: Vec<bool> = (0..5).map(|index| pgs.is_epoch_boundary(index)).collect();
+        assert_eq!(boundaries, [false, true, false, true, false]);
+    }
+
+    #[test]
+    fn debug_outlines_trace_windows_and_crops() {
+        let mut data = [
+            pcs_with_objects(
+                0,
+                (10, 6),
+                0,
+                0x80,
+                0,
+                &[composition_object(0, 0, (2, 2), 0x40, Some((1, 0, 2, 2)))],
+            ),
+            wds(0, &[(0, 1, 1, 6, 4)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 4, &[0; 8]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_set = first_display_set(&pgs);
+        let options = RenderOptions {
+            debug_outlines: true,
+            ..RenderOptions::default()
+        };
+        let rgba = render_display_set_with_options(&display_set, &options).unwrap();
+        let pixel = |x: usize, y: usize| &rgba[(y * 10 + x) * PIXEL_SIZE..][..PIXEL_SIZE];
+
+        for (x, y) in [(1, 1), (6, 1), (1, 4), (6, 4), (4, 1), (1, 3)] {
+            assert_eq!(pixel(x, y), WINDOW_OUTLINE_COLOR, "window edge at {x},{y}");
+        }
+        for (x, y) in [(3, 2), (4, 2), (3, 3), (4, 3)] {
+            assert_eq!(pixel(x, y), CROP_OUTLINE_COLOR, "crop edge at {x},{y}");
+        }
+        for (x, y) in [(0, 0), (2, 2), (7, 5)] {
+            assert_eq!(pixel(x, y)[3], 0, "no outline at {x},{y}");
+        }
+        let plain = render_display_set(&display_set).unwrap();
+        assert!(plain.chunks_exact(PIXEL_SIZE).all(|pixel| pixel[3] == 0));
+    }
+
+    #[test]
+    fn two_color_object_splits_into_two_disjoint_layers() {
+        let mut data = cue(0, (4, 2), (1, 0), 2, &[1, 3, 3, 0]);
+        let pgs = parse_pgs(&mut data).unwrap();
+        let layers = render_color_layers(&first_display_set(&pgs)).unwrap();
+
+        let ids: Vec<u8> = layers.iter().map(|(entry, _)| entry.id).collect();
+        assert_eq!(ids, [1, 3]);
+        assert_eq!(layers[0].1, [0, 255, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(layers[1].1, [0, 0, 255, 0, 0, 255, 0, 0]);
+        assert!(
+            layers[0]
+                .1
+                .iter()
+                .zip(&layers[1].1)
+                .all(|(white, black)| *white == 0 || *black == 0)
+        );
     }
 }
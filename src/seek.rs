@@ -0,0 +1,170 @@
+use winnow::Result as PResult;
+use winnow::binary::{be_u32, length_repeat};
+use winnow::prelude::*;
+
+use crate::{
+    error::{PgsError, PgsResult},
+    parse::{CompositionState, Pgs, SegmentContents},
+};
+
+const INDEX_MAGIC: &[u8; 4] = b"PGSI";
+
+/// Bumped whenever the layout of a stored seek index changes, so indices
+/// written by an older version are rejected rather than misread.
+pub const SEEK_INDEX_VERSION: u16 = 1;
+
+/// The points a decoder can start from without having seen the preceding
+/// segments, i.e. the presentation compositions starting an epoch or marking
+/// an acquisition point, so a player can seek in a large stream without
+/// scanning it. The index can be stored alongside the stream with
+/// [`SeekIndex::to_bytes`] and loaded back with [`SeekIndex::from_bytes`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SeekIndex {
+    /// Presentation timestamp and segment index of each seek point, sorted
+    /// by presentation timestamp.
+    points: Vec<(u32, usize)>,
+}
+
+impl SeekIndex {
+    pub fn build(pgs: &Pgs) -> SeekIndex {
+        let mut points = pgs
+            .segments
+            .iter()
+            .enumerate()
+            .filter(|(_, segment)| {
+                matches!(
+                    &segment.contents,
+                    SegmentContents::PresentationComposition(composition)
+                        if composition.composition_state != CompositionState::Normal
+                )
+            })
+            .map(|(index, segment)| (segment.pts, index))
+            .collect::<Vec<_>>();
+        // Seek points out of stream order only occur in streams whose
+        // timestamps go backwards.
+        points.sort_by_key(|(pts, _)| *pts);
+        SeekIndex { points }
+    }
+
+    /// Serializes the index. Fails if a seek point's segment index doesn't
+    /// fit in the 32 bits stored for it.
+    pub fn to_bytes(&self) -> PgsResult<Vec<u8>> {
+        let mut out = Vec::with_capacity(INDEX_MAGIC.len() + 6 + self.points.len() * 8);
+        out.extend_from_slice(INDEX_MAGIC);
+        out.extend_from_slice(&SEEK_INDEX_VERSION.to_be_bytes());
+        out.extend_from_slice(&(self.points.len() as u32).to_be_bytes());
+        for (pts, index) in &self.points {
+            let index = u32::try_from(*index)
+                .map_err(|_| PgsError::SeekPointOutOfRange { index: *index })?;
+            out.extend_from_slice(&pts.to_be_bytes());
+            out.extend_from_slice(&index.to_be_bytes());
+        }
+        Ok(out)
+    }
+
+    /// Loads an index written by [`SeekIndex::to_bytes`]. Indices written
+    /// with a different [`SEEK_INDEX_VERSION`] or whose seek points aren't
+    /// sorted by presentation timestamp are rejected.
+    pub fn from_bytes(bytes: &[u8]) -> PgsResult<SeekIndex> {
+        let Some(input) = bytes.strip_prefix(INDEX_MAGIC) else {
+            return Err(PgsError::ParseError("not a PGS seek index".to_string()));
+        };
+        let Some((version, input)) = input.split_first_chunk::<2>() else {
+            return Err(PgsError::ParseError("truncated PGS seek index".to_string()));
+        };
+        let found = u16::from_be_bytes(*version);
+        if found != SEEK_INDEX_VERSION {
+            return Err(PgsError::SeekIndexVersionMismatch {
+                found,
+                expected: SEEK_INDEX_VERSION,
+            });
+        }
+        let points = parse_points
+            .parse(input)
+            .map_err(|e| PgsError::ParseError(format!("invalid seek index: {e}")))?;
+        if !points.is_sorted_by_key(|(pts, _)| *pts) {
+            return Err(PgsError::ParseError(
+                "invalid seek index: seek points are not sorted by presentation timestamp"
+                    .to_string(),
+            ));
+        }
+        Ok(SeekIndex {
+            points: points
+                .into_iter()
+                .map(|(pts, index)| (pts, index as usize))
+                .collect(),
+        })
+    }
+
+    /// Index of the segment to start decoding from to show the subtitle
+    /// active at `pts`: the last seek point presented at or before it.
+    /// Returns 0, the start of the stream, if there is none.
+    pub fn nearest_before(&self, pts: u32) -> usize {
+        let end = self.points.partition_point(|(point, _)| *point <= pts);
+        end.checked_sub(1)
+            .map_or(0, |position| self.points[position].1)
+    }
+}
+
+/// Presentation timestamp and segment index pairs, preceded by their count.
+fn parse_points(input: &mut &[u8]) -> PResult<Vec<(u32, u32)>> {
+    length_repeat(be_u32, (be_u32, be_u32)).parse_next(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{PALETTE, clear, cue, end, ods, pcs, pds, wds};
+    use crate::parse_pgs;
+
+    fn stream() -> Pgs {
+        let mut data = [
+            cue(0, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(90_000, (64, 32)),
+            cue(180_000, (64, 32), (0, 0), 2, &[3; 4]),
+            // An acquisition point repeating everything shown.
+            pcs(270_000, (64, 32), 3, 0x40, 0, &[(0, 0, 0, 0)]),
+            wds(270_000, &[(0, 0, 0, 2, 2)]),
+            pds(270_000, 0, &PALETTE),
+            ods(270_000, 0, 2, &[3; 4]),
+            end(270_000),
+        ]
+        .concat();
+        parse_pgs(&mut data).unwrap()
+    }
+
+    #[test]
+    fn seek_index_round_trips_through_bytes() {
+        let index = SeekIndex::build(&stream());
+        assert_eq!(index.points, [(0, 0), (180_000, 7), (270_000, 12)]);
+        let bytes = index.to_bytes().unwrap();
+        assert_eq!(SeekIndex::from_bytes(&bytes).unwrap(), index);
+
+        let mut other_version = bytes.clone();
+        other_version[5] ^= 0xFF;
+        assert!(matches!(
+            SeekIndex::from_bytes(&other_version),
+            Err(PgsError::SeekIndexVersionMismatch { .. })
+        ));
+        // Swap the timestamps of the first two seek points.
+        let mut unsorted = bytes;
+        let (first, second) = (10..14, 18..22);
+        let pts = unsorted[first.clone()].to_vec();
+        unsorted.copy_within(second.clone(), first.start);
+        unsorted[second].copy_from_slice(&pts);
+        assert!(matches!(
+            SeekIndex::from_bytes(&unsorted),
+            Err(PgsError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn nearest_before_finds_the_last_seek_point_at_or_before_a_time() {
+        let index = SeekIndex::build(&stream());
+        let found: Vec<usize> = [0, 90_000, 179_999, 180_000, 269_999, 270_000, u32::MAX]
+            .map(|pts| index.nearest_before(pts))
+            .into();
+        assert_eq!(found, [0, 0, 0, 7, 7, 12, 12]);
+        assert_eq!(SeekIndex::default().nearest_before(90_000), 0);
+    }
+}
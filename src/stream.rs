@@ -0,0 +1,268 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use crate::{
+    error::{PgsError, PgsResult},
+    parse::{
+        CompositionObject, CompositionState, LastInSequence, ObjectDefinition, PaletteDefinition,
+        PresentationComposition, Segment, SegmentContents, Window,
+    },
+    render::DisplaySet,
+};
+
+/// A display set that owns its definitions, so it can outlive the segments
+/// it was assembled from. Definitions carried over from earlier display sets
+/// are shared rather than copied.
+#[derive(Debug, PartialEq, Eq)]
+pub struct OwnedDisplaySet {
+    pub presentation_timestamp: u32,
+    pub decoding_timestamp: u32,
+    pub width: u16,
+    pub height: u16,
+    pub frame_rate: u8,
+    pub composition_number: u16,
+    pub composition_state: CompositionState,
+    pub palette_update: bool,
+    pub palette_id: u8,
+    pub composition_objects: Vec<CompositionObject>,
+    pub windows: HashMap<u8, Arc<Window>>,
+    pub palettes: HashMap<u8, Arc<PaletteDefinition>>,
+    pub objects: HashMap<u16, Arc<ObjectDefinition>>,
+}
+
+impl OwnedDisplaySet {
+    /// Borrows the display set in the form accepted by the renderer.
+    pub fn display_set(&self) -> DisplaySet<'_> {
+        DisplaySet {
+            presentation_timestamp: self.presentation_timestamp,
+            decoding_timestamp: self.decoding_timestamp,
+            width: self.width,
+            height: self.height,
+            frame_rate: self.frame_rate,
+            composition_number: self.composition_number,
+            composition_state: self.composition_state,
+            palette_update: self.palette_update,
+            palette_id: self.palette_id,
+            composition_objects: &self.composition_objects,
+            windows: self
+                .windows
+                .iter()
+                .map(|(id, window)| (*id, window.as_ref()))
+                .collect(),
+            palettes: self
+                .palettes
+                .iter()
+                .map(|(id, palette)| (*id, palette.as_ref()))
+                .collect(),
+            objects: self
+                .objects
+                .iter()
+                .map(|(id, object)| (*id, object.as_ref()))
+                .collect(),
+        }
+    }
+}
+
+/// Assembles display sets from segments pushed one at a time, for pipelines
+/// that never hold a complete [`crate::parse::Pgs`]. Window, palette and
+/// object definitions persist across display sets until the next epoch
+/// start, as with [`crate::render::DisplaySetIterator`].
+///
+/// Fragmented objects must already be assembled, as done by
+/// [`crate::parse::parse_pgs`], so the first fragment carries the complete
+/// run-length data.
+#[derive(Debug, Default)]
+pub struct DisplaySetAssembler {
+    /// Number of segments pushed so far.
+    pushed: usize,
+    pending: Vec<Segment>,
+    ready: VecDeque<PgsResult<OwnedDisplaySet>>,
+    windows: HashMap<u8, Arc<Window>>,
+    palettes: HashMap<u8, Arc<PaletteDefinition>>,
+    objects: HashMap<u16, Arc<ObjectDefinition>>,
+}
+
+impl DisplaySetAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, segment: Segment) {
+        self.pushed += 1;
+        match segment.contents {
+            SegmentContents::PresentationComposition(_) if !self.pending.is_empty() => {
+                // The end segment is missing; the composition starts the next
+                // display set.
+                let start = self.pushed - 1 - self.pending.len();
+                let result = self.assemble(
+                    start,
+                    Some(PgsError::UnterminatedDisplaySet { segment: start }),
+                );
+                self.ready.push_back(result);
+                self.pending.push(segment);
+            }
+            SegmentContents::End => {
+                self.pending.push(segment);
+                let result = self.assemble(self.pushed - self.pending.len(), None);
+                self.ready.push_back(result);
+            }
+            _ => self.pending.push(segment),
+        }
+    }
+
+    /// Returns the next completed display set, or `None` until the end
+    /// segment of one has been pushed.
+    pub fn try_next(&mut self) -> Option<PgsResult<OwnedDisplaySet>> {
+        self.ready.pop_front()
+    }
+
+    /// Builds a display set from the pending segments, the first of which was
+    /// segment number `start`, and updates the persistent definitions.
+    fn assemble(
+        &mut self,
+        start: usize,
+        mut error: Option<PgsError>,
+    ) -> PgsResult<OwnedDisplaySet> {
+        let mut segments = std::mem::take(&mut self.pending).into_iter();
+        let Some(Segment {
+            pts,
+            dts,
+            contents: SegmentContents::PresentationComposition(composition),
+        }) = segments.next()
+        else {
+            return Err(PgsError::ParseError(
+                "display set does not begin with a presentation composition segment".to_string(),
+            ));
+        };
+        let PresentationComposition {
+            width,
+            height,
+            frame_rate,
+            composition_number,
+            composition_state,
+            palette_update,
+            palette_id,
+            composition_objects,
+            ..
+        } = composition;
+        if composition_state == CompositionState::EpochStart {
+            self.windows.clear();
+            self.palettes.clear();
+            self.objects.clear();
+        }
+
+        let mut fragments: HashMap<u16, ObjectDefinition> = HashMap::new();
+        for (index, segment) in segments.enumerate() {
+            if segment.pts != pts || segment.dts != dts {
+                error.get_or_insert(PgsError::TimestampMismatch {
+                    segment: start + index + 1,
+                });
+            }
+            match segment.contents {
+                SegmentContents::WindowDefinition(window_definition) => {
+                    for window in window_definition.windows {
+                        self.windows.insert(window.id, Arc::new(window));
+                    }
+                }
+                SegmentContents::PaletteDefinition(palette_definition) => {
+                    self.palettes
+                        .insert(palette_definition.id, Arc::new(palette_definition));
+                }
+                SegmentContents::ObjectDefinition(object_definition) => {
+                    let id = object_definition.id;
+                    match (
+                        &object_definition.last_in_sequence,
+                        fragments.contains_key(&id),
+                    ) {
+                        (LastInSequence::FirstAndLast, false) => {
+                            self.objects.insert(id, Arc::new(object_definition));
+                        }
+                        (LastInSequence::First, false) => {
+                            fragments.insert(id, object_definition);
+                        }
+                        (LastInSequence::Middle, true) => {}
+                        (LastInSequence::Last, true) => {
+                            if let Some(first) = fragments.remove(&id) {
+                                self.objects.insert(id, Arc::new(first));
+                            }
+                        }
+                        (fragment, _) => {
+                            fragments.remove(&id);
+                            error.get_or_insert(PgsError::FragmentOutOfOrder {
+                                object_id: id,
+                                fragment: fragment.clone(),
+                            });
+                        }
+                    }
+                }
+                SegmentContents::PresentationComposition(_) | SegmentContents::End => {}
+            }
+        }
+        if let Some(object_id) = fragments.keys().min() {
+            error.get_or_insert(PgsError::IncompleteObject {
+                object_id: *object_id,
+            });
+        }
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        Ok(OwnedDisplaySet {
+            presentation_timestamp: pts,
+            decoding_timestamp: dts,
+            width,
+            height,
+            frame_rate,
+            composition_number,
+            composition_state,
+            palette_update,
+            palette_id,
+            composition_objects,
+            windows: self.windows.clone(),
+            palettes: self.palettes.clone(),
+            objects: self.objects.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{clear, cue, end, pcs};
+    use crate::parse_pgs;
+    use crate::render::DisplaySetIterator;
+
+    #[test]
+    fn pushed_segments_assemble_like_the_iterator() {
+        let mut bytes = [
+            cue(0, (8, 4), (2, 1), 2, &[1, 3, 3, 1]),
+            [pcs(9_000, (8, 4), 1, 0x00, 0, &[(0, 0, 4, 2)]), end(9_000)].concat(),
+            clear(18_000, (8, 4)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut bytes).unwrap();
+
+        let mut assembler = DisplaySetAssembler::new();
+        let mut assembled = Vec::new();
+        for segment in pgs.segments.clone() {
+            let is_end = segment.contents == SegmentContents::End;
+            assembler.push(segment);
+            match assembler.try_next() {
+                Some(display_set) => {
+                    assert!(is_end);
+                    assembled.push(display_set.unwrap());
+                }
+                None => assert!(!is_end),
+            }
+        }
+
+        let expected: Vec<DisplaySet> = DisplaySetIterator::new(&pgs).collect();
+        let actual: Vec<DisplaySet> = assembled.iter().map(OwnedDisplaySet::display_set).collect();
+        assert_eq!(actual, expected);
+        // The second display set reuses the object defined by the first.
+        assert!(Arc::ptr_eq(
+            &assembled[0].objects[&0],
+            &assembled[1].objects[&0]
+        ));
+    }
+}
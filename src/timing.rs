@@ -0,0 +1,362 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::{
+    parse::{CompositionState, Pgs, SegmentContents},
+    render::DisplaySetIterator,
+};
+
+/// PGS timestamps are expressed in ticks of a 90kHz clock.
+pub const PTS_CLOCK_HZ: u64 = 90_000;
+
+/// Frame rates paired with the code used for them in the PCS `frame_rate`
+/// byte.
+const FRAME_RATE_CODES: [(f64, u8); 6] = [
+    (24000.0 / 1001.0, 0x10),
+    (24.0, 0x20),
+    (25.0, 0x30),
+    (30000.0 / 1001.0, 0x40),
+    (50.0, 0x60),
+    (60000.0 / 1001.0, 0x70),
+];
+
+pub fn frame_rate_code(fps: f64) -> Option<u8> {
+    FRAME_RATE_CODES
+        .iter()
+        .find(|(rate, _)| (rate - fps).abs() < 0.01)
+        .map(|(_, code)| *code)
+}
+
+pub fn frame_rate_from_code(code: u8) -> Option<f64> {
+    FRAME_RATE_CODES
+        .iter()
+        .find(|(_, c)| *c == code)
+        .map(|(rate, _)| *rate)
+}
+
+pub fn pts_to_duration(pts: u32) -> Duration {
+    Duration::from_micros(pts as u64 * 1_000_000 / PTS_CLOCK_HZ)
+}
+
+impl Pgs {
+    /// Time span between the first and last segment's presentation timestamp.
+    pub fn duration(&self) -> Duration {
+        match (self.segments.first(), self.segments.last()) {
+            (Some(first), Some(last)) => pts_to_duration(last.pts.saturating_sub(first.pts)),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Rescales every PTS/DTS so each subtitle stays on the same frame when
+    /// the video is played at `to_fps` instead of `from_fps` (e.g. a 24fps to
+    /// 25fps PAL speed-up), and rewrites every PCS `frame_rate` code.
+    /// Timestamps are multiplied by `from_fps / to_fps`, so a speed-up moves
+    /// every cue earlier along with the shortened video.
+    ///
+    /// Returns the frame rate code that was written, or `None` if `to_fps` has
+    /// no PGS code, in which case the timestamps are still rescaled but the
+    /// `frame_rate` bytes are left untouched.
+    pub fn retime(&mut self, from_fps: f64, to_fps: f64) -> Option<u8> {
+        let scale = from_fps / to_fps;
+        let code = frame_rate_code(to_fps);
+        for segment in &mut self.segments {
+            segment.pts = (segment.pts as f64 * scale).round() as u32;
+            segment.dts = (segment.dts as f64 * scale).round() as u32;
+            if let (Some(code), SegmentContents::PresentationComposition(composition)) =
+                (code, &mut segment.contents)
+            {
+                composition.frame_rate = code;
+            }
+        }
+        code
+    }
+
+    /// The distinct frame rate codes declared by the stream's presentation
+    /// compositions, in order of first appearance. More than one means the
+    /// declarations are inconsistent.
+    pub fn frame_rates(&self) -> Vec<u8> {
+        let mut seen = HashSet::new();
+        self.segments
+            .iter()
+            .filter_map(|segment| match &segment.contents {
+                SegmentContents::PresentationComposition(composition) => {
+                    Some(composition.frame_rate)
+                }
+                _ => None,
+            })
+            .filter(|frame_rate| seen.insert(*frame_rate))
+            .collect()
+    }
+
+    /// Moves every PTS/DTS by `millis` milliseconds, later for a positive
+    /// shift and earlier for a negative one. Timestamps saturate at zero and
+    /// at the 32-bit limit rather than wrapping.
+    pub fn shift_time(&mut self, millis: i64) {
+        let ticks = millis.saturating_mul(PTS_CLOCK_HZ as i64) / 1000;
+        let shift = |timestamp: u32| (timestamp as i64 + ticks).clamp(0, u32::MAX as i64) as u32;
+        for segment in &mut self.segments {
+            segment.pts = shift(segment.pts);
+            segment.dts = shift(segment.dts);
+        }
+    }
+
+    /// Average time between consecutive acquisition points, the granularity
+    /// at which a decoder joining mid-stream can resynchronise. Returns `None`
+    /// if the stream has fewer than two acquisition points.
+    pub fn acquisition_interval(&self) -> Option<Duration> {
+        let timestamps: Vec<u32> = self
+            .segments
+            .iter()
+            .filter(|segment| {
+                matches!(
+                    &segment.contents,
+                    SegmentContents::PresentationComposition(composition)
+                        if composition.composition_state == CompositionState::AcquisitionPoint
+                )
+            })
+            .map(|segment| segment.pts)
+            .collect();
+        let (first, last) = (timestamps.first()?, timestamps.last()?);
+        let intervals = timestamps.len() as u32 - 1;
+        if intervals == 0 {
+            return None;
+        }
+        Some(pts_to_duration(last.saturating_sub(*first)) / intervals)
+    }
+
+    /// When the first non-empty display set is shown.
+    pub fn first_cue_time(&self) -> Option<Duration> {
+        DisplaySetIterator::new(self)
+            .find(|display_set| !display_set.is_empty())
+            .map(|display_set| pts_to_duration(display_set.presentation_timestamp))
+    }
+
+    /// When the last non-empty display set is replaced by the one following
+    /// it. If nothing follows, the last cue's own presentation time is used.
+    pub fn last_cue_time(&self) -> Option<Duration> {
+        let (mut last, mut visible) = (None, false);
+        for display_set in DisplaySetIterator::new(self) {
+            if visible || !display_set.is_empty() {
+                last = Some(display_set.presentation_timestamp);
+            }
+            visible = !display_set.is_empty();
+        }
+        last.map(pts_to_duration)
+    }
+
+    /// Intervals between a subtitle being cleared and the next one being
+    /// shown, as `(start, end)`. Time before the first cue and after the last
+    /// is not included.
+    pub fn gaps(&self) -> Vec<(Duration, Duration)> {
+        let mut gaps = Vec::new();
+        let (mut cleared, mut visible) = (None, false);
+        for display_set in DisplaySetIterator::new(self) {
+            let timestamp = display_set.presentation_timestamp;
+            if display_set.is_empty() {
+                if visible {
+                    cleared = Some(timestamp);
+                }
+                visible = false;
+            } else {
+                if let Some(start) = cleared.take() {
+                    gaps.push((pts_to_duration(start), pts_to_duration(timestamp)));
+                }
+                visible = true;
+            }
+        }
+        gaps
+    }
+
+    /// The distinct presentation timestamps of the display sets, in stream
+    /// order.
+    pub fn presentation_times(&self) -> Vec<u32> {
+        let mut seen = HashSet::new();
+        self.segments
+            .iter()
+            .filter(|segment| {
+                matches!(
+                    segment.contents,
+                    SegmentContents::PresentationComposition(_)
+                )
+            })
+            .map(|segment| segment.pts)
+            .filter(|pts| seen.insert(*pts))
+            .collect()
+    }
+
+    /// Whether no segment's presentation timestamp is earlier than the one
+    /// before it.
+    pub fn timestamps_monotonic(&self) -> bool {
+        self.segments
+            .windows(2)
+            .all(|pair| pair[0].pts <= pair[1].pts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{clear, cue, end, pcs};
+    use crate::parse_pgs;
+
+    #[test]
+    fn duration_spans_first_to_last_timestamp() {
+        let mut data = [
+            cue(900, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(135_900, (64, 32)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        assert_eq!(pgs.duration(), Duration::from_millis(1500));
+        assert_eq!(Pgs { segments: vec![] }.duration(), Duration::ZERO);
+    }
+
+    fn frame_rate_bytes(pgs: &Pgs) -> Vec<u8> {
+        pgs.segments
+            .iter()
+            .filter_map(|segment| match &segment.contents {
+                SegmentContents::PresentationComposition(composition) => {
+                    Some(composition.frame_rate)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn retime_scales_timestamps_and_rewrites_frame_rate() {
+        let mut data = [
+            cue(90_000, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(2_160_000, (64, 32)),
+        ]
+        .concat();
+        let mut pgs = parse_pgs(&mut data).unwrap();
+
+        assert_eq!(pgs.retime(24.0, 25.0), Some(0x30));
+        assert_eq!(pgs.segments[0].pts, 86_400);
+        assert_eq!(pgs.segments[0].dts, 86_400);
+        assert_eq!(pgs.segments.last().unwrap().pts, 2_073_600);
+        assert_eq!(frame_rate_bytes(&pgs), [0x30, 0x30]);
+
+        assert_eq!(pgs.retime(25.0, 26.0), None);
+        assert_eq!(frame_rate_bytes(&pgs), [0x30, 0x30]);
+    }
+
+    #[test]
+    fn acquisition_points_two_seconds_apart() {
+        let mut data = cue(0, (64, 32), (0, 0), 2, &[1; 4]);
+        for point in 1..=3 {
+            let pts = point * 180_000;
+            data.extend(pcs(pts, (64, 32), point as u16, 0x40, 0, &[(0, 0, 0, 0)]));
+            data.extend(end(pts));
+        }
+        let pgs = parse_pgs(&mut data).unwrap();
+        assert_eq!(pgs.acquisition_interval(), Some(Duration::from_secs(2)));
+
+        let single = parse_pgs(&mut cue(0, (64, 32), (0, 0), 2, &[1; 4])).unwrap();
+        assert_eq!(single.acquisition_interval(), None);
+    }
+
+    #[test]
+    fn cue_times_ignore_leading_and_trailing_clears() {
+        let mut data = [
+            clear(0, (64, 32)),
+            clear(45_000, (64, 32)),
+            cue(90_000, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(180_000, (64, 32)),
+            clear(270_000, (64, 32)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        assert_eq!(pgs.first_cue_time(), Some(Duration::from_secs(1)));
+        assert_eq!(pgs.last_cue_time(), Some(Duration::from_secs(2)));
+
+        let cleared = parse_pgs(&mut clear(0, (64, 32))).unwrap();
+        assert_eq!(cleared.first_cue_time(), None);
+        assert_eq!(cleared.last_cue_time(), None);
+    }
+
+    #[test]
+    fn gap_between_two_subtitles() {
+        let mut data = [
+            cue(90_000, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(180_000, (64, 32)),
+            cue(315_000, (64, 32), (0, 0), 2, &[3; 4]),
+            clear(360_000, (64, 32)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        assert_eq!(
+            pgs.gaps(),
+            vec![(Duration::from_secs(2), Duration::from_millis(3_500))]
+        );
+    }
+
+    #[test]
+    fn backward_timestamp_is_not_monotonic() {
+        let mut data = [
+            cue(90_000, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(180_000, (64, 32)),
+            cue(135_000, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(180_000, (64, 32)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        assert_eq!(pgs.presentation_times(), [90_000, 180_000, 135_000]);
+        assert!(!pgs.timestamps_monotonic());
+
+        let mut data = [cue(0, (64, 32), (0, 0), 2, &[1; 4]), clear(0, (64, 32))].concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        assert_eq!(pgs.presentation_times(), [0]);
+        assert!(pgs.timestamps_monotonic());
+    }
+
+    #[test]
+    fn shifts_move_timestamps_and_clamp_at_zero() {
+        let mut data = [
+            cue(45_000, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(135_000, (64, 32)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let timestamps = |pgs: &Pgs| -> Vec<(u32, u32)> {
+            pgs.segments
+                .iter()
+                .map(|segment| (segment.pts, segment.dts))
+                .collect()
+        };
+        let shifted = |millis| {
+            let mut pgs = pgs.clone();
+            pgs.shift_time(millis);
+            timestamps(&pgs)
+        };
+        let expected = |cue: u32, clear: u32| {
+            let mut expected = vec![(cue, cue); 5];
+            expected.extend([(clear, clear); 2]);
+            expected
+        };
+
+        assert_eq!(shifted(500), expected(90_000, 180_000));
+        assert_eq!(shifted(-500), expected(0, 90_000));
+        assert_eq!(shifted(-1_000), expected(0, 45_000));
+        assert_eq!(shifted(0), timestamps(&pgs));
+    }
+
+    #[test]
+    fn frame_rates_lists_each_distinct_code_once() {
+        let mut second_epoch = cue(90_000, (64, 32), (0, 0), 2, &[1; 4]);
+        // The frame rate byte follows the header and the frame size.
+        second_epoch[13 + 4] = 0x40;
+        let mut data = [
+            cue(0, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(45_000, (64, 32)),
+            second_epoch,
+            clear(135_000, (64, 32)),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        assert_eq!(pgs.frame_rates(), [0x10, 0x40]);
+    }
+}
@@ -0,0 +1,786 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::{PgsError, PgsResult},
+    parse::{
+        CompositionState, LastInSequence, ObjectDefinition, Pgs, SegmentContents, flag_issues,
+        parse_pgs, segment_table,
+    },
+    render::{DisplaySet, DisplaySetIterator},
+};
+
+impl DisplaySet<'_> {
+    /// Checks that every composition object refers to a defined object, so a
+    /// missing object can be reported before rendering starts.
+    pub fn validate_objects(&self) -> PgsResult<()> {
+        match self
+            .composition_objects
+            .iter()
+            .find(|composition_object| !self.objects.contains_key(&composition_object.id))
+        {
+            Some(composition_object) => Err(PgsError::ObjectNotFound {
+                object_id: composition_object.id,
+                display_set: format!("{:?}", self),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Pgs {
+    /// Checks that every non-empty display set would render without errors,
+    /// without rasterizing anything: the frame has a size, every composition
+    /// object's window and object are defined, each object's run-length data
+    /// fits its dimensions and every color it uses is in the palette. Returns
+    /// every problem found, in stream order.
+    pub fn can_render(&self) -> Result<(), Vec<PgsError>> {
+        let mut errors = Vec::new();
+        let mut display_sets = DisplaySetIterator::new(self);
+        while let Some(display_set) = display_sets.try_next() {
+            match display_set {
+                Ok(display_set) if !display_set.is_empty() => {
+                    check_renderable(&display_set, &mut errors);
+                }
+                Ok(_) => {}
+                Err(error) => errors.push(error),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Pgs {
+    /// Indices of epoch start display sets that refer to a window, palette
+    /// or object they don't define themselves. An epoch start discards all
+    /// earlier definitions, so such a display set can't be decoded when
+    /// playback starts from it, even if the definition appeared earlier.
+    pub fn validate_epoch_self_containment(&self) -> Vec<usize> {
+        DisplaySetIterator::new(self)
+            .enumerate()
+            .filter(|(_, display_set)| {
+                display_set.composition_state == CompositionState::EpochStart
+                    && !display_set.is_empty()
+                    && (!display_set.palettes.contains_key(&display_set.palette_id)
+                        || display_set
+                            .composition_objects
+                            .iter()
+                            .any(|composition_object| {
+                                !display_set
+                                    .windows
+                                    .contains_key(&composition_object.window_id)
+                                    || !display_set.objects.contains_key(&composition_object.id)
+                            }))
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+fn check_renderable(display_set: &DisplaySet, errors: &mut Vec<PgsError>) {
+    if display_set.width == 0 || display_set.height == 0 {
+        errors.push(PgsError::InvalidDimensions {
+            width: display_set.width,
+            height: display_set.height,
+            display_set: format!("{:?}", display_set),
+        });
+    }
+    let palette = display_set.palettes.get(&display_set.palette_id);
+    for composition_object in display_set.composition_objects {
+        if !display_set
+            .windows
+            .contains_key(&composition_object.window_id)
+        {
+            errors.push(PgsError::WindowNotFound {
+                window_id: composition_object.window_id,
+                display_set: format!("{:?}", display_set),
+            });
+        }
+        let Some(object) = display_set.objects.get(&composition_object.id) else {
+            errors.push(PgsError::ObjectNotFound {
+                object_id: composition_object.id,
+                display_set: format!("{:?}", display_set),
+            });
+            continue;
+        };
+        if object.width == 0 || object.height == 0 {
+            errors.push(PgsError::InvalidObjectDimensions {
+                object_id: object.id,
+                width: object.width,
+                height: object.height,
+            });
+            continue;
+        }
+        for (line, range) in object.data.line_ranges().into_iter().enumerate() {
+            let runs = &object.data.0[range];
+            let length: u64 = runs.iter().map(|run| run.count as u64).sum();
+            if !runs.is_empty() && (line >= object.height as usize || length > object.width as u64)
+            {
+                errors.push(PgsError::RunLengthOverrun {
+                    object_id: object.id,
+                    width: object.width,
+                    height: object.height,
+                    line,
+                });
+                break;
+            }
+        }
+        if let Some(entry_id) = object
+            .used_colors()
+            .into_iter()
+            .filter(|color| palette.is_none_or(|palette| !palette.entries.contains_key(color)))
+            .min()
+        {
+            errors.push(PgsError::PaletteNotFound {
+                palette_id: display_set.palette_id,
+                entry_id,
+                display_set: format!("{:?}", display_set),
+            });
+        }
+    }
+}
+
+impl ObjectDefinition {
+    /// Rewrites the declared width and height to match the decoded data: the
+    /// width becomes the longest scanline and the height the number of lines.
+    /// Salvages objects whose header is slightly off from their run-length
+    /// data, which would otherwise have their rows misplaced when rendered.
+    pub fn repair_dimensions(&mut self) {
+        let lines = self.data.line_ranges();
+        let width = lines
+            .iter()
+            .map(|range| {
+                self.data.0[range.clone()]
+                    .iter()
+                    .map(|run| run.count as u64)
+                    .sum::<u64>()
+            })
+            .max()
+            .unwrap_or(0);
+        self.width = width.min(u16::MAX as u64) as u16;
+        self.height = lines.len().min(u16::MAX as usize) as u16;
+    }
+}
+
+impl Pgs {
+    /// Indices of display sets that show a subtitle which is never cleared by
+    /// an empty display set: either it stays on screen until the end of the
+    /// stream, or a different composition replaces it directly. Palette-only
+    /// updates, such as fade steps, continue the cue rather than replace it.
+    pub fn unclosed_cues(&self) -> Vec<usize> {
+        let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(self).collect();
+        display_sets
+            .iter()
+            .enumerate()
+            .filter(|(index, display_set)| {
+                !display_set.is_empty()
+                    && display_sets.get(index + 1).is_none_or(|next| {
+                        !next.is_empty()
+                            && (!next.palette_update
+                                || next.composition_objects != display_set.composition_objects)
+                    })
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// A segment whose declared length differs from the size of the body its
+/// contents describe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthMismatch {
+    /// Byte offset of the segment's `PG` magic.
+    pub offset: usize,
+    pub segment_type: u8,
+    pub declared: u16,
+    /// Body size implied by the segment's contents.
+    pub expected: usize,
+}
+
+/// Checks every segment's declared length against the size implied by its
+/// body, e.g. the object count of a presentation composition or the data
+/// length of an unfragmented object. When the two disagree and a segment
+/// starts where the contents say the body ends, scanning continues from
+/// there; otherwise the declared length is trusted. Bodies whose size cannot
+/// be derived, such as object fragments, are not checked.
+pub fn validate_segment_lengths(bytes: &[u8]) -> Vec<LengthMismatch> {
+    let mut mismatches = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = bytes.get(offset..offset + 13) {
+        if &header[..2] != b"PG" {
+            break;
+        }
+        let segment_type = header[10];
+        let declared = u16::from_be_bytes([header[11], header[12]]);
+        let body = &bytes[offset + 13..];
+        let mut next = offset + 13 + declared as usize;
+        if let Some(expected) = implied_body_length(segment_type, body, declared)
+            && expected != declared as usize
+        {
+            mismatches.push(LengthMismatch {
+                offset,
+                segment_type,
+                declared,
+                expected,
+            });
+            let resynced = offset + 13 + expected;
+            if bytes.get(resynced..resynced + 2) == Some(b"PG") || resynced == bytes.len() {
+                next = resynced;
+            }
+        }
+        offset = next;
+        while bytes.get(offset) == Some(&0) {
+            offset += 1;
+        }
+    }
+    mismatches
+}
+
+/// A deviation from the canonical encoding found by
+/// [`parse_pgs_diagnostic`], which the parser tolerated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Index of the segment in stream order.
+    pub segment: usize,
+    /// Byte offset of the deviation within the input.
+    pub offset: usize,
+    pub kind: DiagnosticKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A flag or enumerated byte holding a value the format does not define,
+    /// or with reserved bits set.
+    NonCanonicalValue { field: &'static str, value: u8 },
+    /// The declared body length differs from the size implied by the body,
+    /// as reported by [`validate_segment_lengths`].
+    LengthMismatch { declared: u16, expected: usize },
+    /// Zero bytes following the segment's body.
+    Padding { length: usize },
+}
+
+/// Parses the stream as [`parse_pgs`] does, also collecting a diagnostic for
+/// every non-canonical flag byte, unexpected segment length and run of
+/// padding encountered, in stream order.
+pub fn parse_pgs_diagnostic(input: &mut [u8]) -> PgsResult<(Pgs, Vec<Diagnostic>)> {
+    let table = segment_table(input)?;
+    let mut diagnostics = Vec::new();
+    for (segment, entry) in table.iter().enumerate() {
+        let start = entry.offset + 13;
+        let end = start + entry.length as usize;
+        for issue in flag_issues(entry, &input[start..end]) {
+            diagnostics.push(Diagnostic {
+                segment,
+                offset: issue.offset,
+                kind: DiagnosticKind::NonCanonicalValue {
+                    field: issue.field,
+                    value: issue.value,
+                },
+            });
+        }
+        if let Some(expected) =
+            implied_body_length(entry.segment_type, &input[start..], entry.length)
+            && expected != entry.length as usize
+        {
+            diagnostics.push(Diagnostic {
+                segment,
+                offset: entry.offset,
+                kind: DiagnosticKind::LengthMismatch {
+                    declared: entry.length,
+                    expected,
+                },
+            });
+        }
+        let next = table
+            .get(segment + 1)
+            .map_or(input.len(), |next| next.offset);
+        if next > end {
+            diagnostics.push(Diagnostic {
+                segment,
+                offset: end,
+                kind: DiagnosticKind::Padding { length: next - end },
+            });
+        }
+    }
+    Ok((parse_pgs(input)?, diagnostics))
+}
+
+/// The body length implied by a segment's contents, or `None` if it can't be
+/// derived from the bytes available.
+fn implied_body_length(segment_type: u8, body: &[u8], declared: u16) -> Option<usize> {
+    let byte = |index: usize| body.get(index).copied().map(usize::from);
+    match segment_type {
+        // Palette definition: id and version, then 5 bytes per entry.
+        0x14 => Some(2 + (declared as usize).saturating_sub(2) / 5 * 5),
+        // Object definition: only unfragmented objects declare their size.
+        0x15 if byte(3)? == 0xC0 => Some(7 + (byte(4)? << 16 | byte(5)? << 8 | byte(6)?)),
+        0x15 => None,
+        // Presentation composition: 11 bytes, then 8 per object plus 8 more
+        // for cropped objects.
+        0x16 => {
+            let mut length = 11;
+            for _ in 0..byte(10)? {
+                length += if byte(length + 3)? & 0x40 != 0 { 16 } else { 8 };
+            }
+            Some(length)
+        }
+        // Window definition: the window count, then 9 bytes per window.
+        0x17 => Some(1 + 9 * byte(0)?),
+        0x80 => Some(0),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceIssue {
+    pub severity: Severity,
+    /// Index of the display set the issue was found in.
+    pub display_set: usize,
+    pub message: String,
+}
+
+impl Pgs {
+    /// Runs every structural check against the stream and collects the
+    /// violations found, in stream order.
+    pub fn conformance_report(&self) -> Vec<ConformanceIssue> {
+        let mut issues = Vec::new();
+        check_segments(self, &mut issues);
+        for (index, display_set) in DisplaySetIterator::new(self).enumerate() {
+            if index == 0 && display_set.composition_state != CompositionState::EpochStart {
+                issues.push(ConformanceIssue {
+                    severity: Severity::Error,
+                    display_set: index,
+                    message: "stream does not begin with an epoch start".to_string(),
+                });
+            }
+            check_display_set(index, &display_set, &mut issues);
+        }
+        issues.sort_by_key(|issue| issue.display_set);
+        issues
+    }
+}
+
+/// Checks that need the raw segment order: duplicate definitions within a
+/// display set and incomplete object fragment sequences.
+fn check_segments(pgs: &Pgs, issues: &mut Vec<ConformanceIssue>) {
+    let mut index = 0;
+    let mut window_ids = HashSet::new();
+    let mut palette_ids = HashSet::new();
+    let mut open_fragments = HashSet::new();
+    for segment in &pgs.segments {
+        let mut issue = |severity, message| {
+            issues.push(ConformanceIssue {
+                severity,
+                display_set: index,
+                message,
+            })
+        };
+        match &segment.contents {
+            SegmentContents::PresentationComposition(_) => {
+                window_ids.clear();
+                palette_ids.clear();
+                open_fragments.clear();
+            }
+            SegmentContents::WindowDefinition(window_definition) => {
+                for window in &window_definition.windows {
+                    if !window_ids.insert(window.id) {
+                        issue(
+                            Severity::Error,
+                            format!("window {} is defined more than once", window.id),
+                        );
+                    }
+                }
+            }
+            SegmentContents::PaletteDefinition(palette_definition) => {
+                if !palette_ids.insert(palette_definition.id) {
+                    issue(
+                        Severity::Warning,
+                        format!(
+                            "palette {} is defined more than once",
+                            palette_definition.id
+                        ),
+                    );
+                }
+            }
+            SegmentContents::ObjectDefinition(object_definition) => {
+                let id = object_definition.id;
+                match object_definition.last_in_sequence {
+                    LastInSequence::First => {
+                        if !open_fragments.insert(id) {
+                            issue(
+                                Severity::Error,
+                                format!(
+                                    "object {} starts a new fragment sequence before finishing the previous one",
+                                    id
+                                ),
+                            );
+                        }
+                    }
+                    LastInSequence::Middle => {
+                        if !open_fragments.contains(&id) {
+                            issue(
+                                Severity::Error,
+                                format!(
+                                    "object {} has a middle fragment without a first fragment",
+                                    id
+                                ),
+                            );
+                        }
+                    }
+                    LastInSequence::Last => {
+                        if !open_fragments.remove(&id) {
+                            issue(
+                                Severity::Error,
+                                format!(
+                                    "object {} has a last fragment without a first fragment",
+                                    id
+                                ),
+                            );
+                        }
+                    }
+                    LastInSequence::FirstAndLast => {
+                        if open_fragments.contains(&id) {
+                            issue(
+                                Severity::Error,
+                                format!(
+                                    "object {} is redefined in the middle of a fragment sequence",
+                                    id
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+            SegmentContents::End => {
+                let mut unfinished: Vec<_> = open_fragments.drain().collect();
+                unfinished.sort();
+                for id in unfinished {
+                    issue(
+                        Severity::Error,
+                        format!("object {} has a first fragment without a last fragment", id),
+                    );
+                }
+                index += 1;
+            }
+        }
+    }
+}
+
+fn check_display_set(index: usize, display_set: &DisplaySet, issues: &mut Vec<ConformanceIssue>) {
+    let mut issue = |severity, message| {
+        issues.push(ConformanceIssue {
+            severity,
+            display_set: index,
+            message,
+        })
+    };
+
+    if display_set.width == 0 || display_set.height == 0 {
+        issue(
+            Severity::Error,
+            format!(
+                "invalid presentation dimensions {}x{}",
+                display_set.width, display_set.height
+            ),
+        );
+    }
+
+    let mut window_ids: Vec<_> = display_set.windows.keys().copied().collect();
+    window_ids.sort();
+    for window in window_ids.iter().map(|id| display_set.windows[id]) {
+        if window.horizontal_position as u32 + window.width as u32 > display_set.width as u32
+            || window.vertical_position as u32 + window.height as u32 > display_set.height as u32
+        {
+            issue(
+                Severity::Error,
+                format!("window {} extends outside the presentation area", window.id),
+            );
+        }
+    }
+
+    if !display_set.is_empty() && !display_set.palettes.contains_key(&display_set.palette_id) {
+        issue(
+            Severity::Error,
+            format!(
+                "palette {} is referenced but not defined",
+                display_set.palette_id
+            ),
+        );
+    }
+
+    let mut object_ids: Vec<_> = display_set.objects.keys().copied().collect();
+    object_ids.sort();
+    for object in object_ids.iter().map(|id| display_set.objects[id]) {
+        if let Some(message) = check_object_data(object) {
+            issue(Severity::Error, message);
+        }
+    }
+
+    let mut seen = HashMap::new();
+    for composition_object in display_set.composition_objects {
+        if seen
+            .insert(composition_object.id, composition_object.window_id)
+            .is_some_and(|window_id| window_id == composition_object.window_id)
+        {
+            issue(
+                Severity::Warning,
+                format!(
+                    "object {} is composed more than once in window {}",
+                    composition_object.id, composition_object.window_id
+                ),
+            );
+        }
+        let Some(window) = display_set.windows.get(&composition_object.window_id) else {
+            issue(
+                Severity::Error,
+                format!(
+                    "object {} references undefined window {}",
+                    composition_object.id, composition_object.window_id
+                ),
+            );
+            continue;
+        };
+        let Some(object) = display_set.objects.get(&composition_object.id) else {
+            issue(
+                Severity::Error,
+                format!(
+                    "object {} is referenced but not defined",
+                    composition_object.id
+                ),
+            );
+            continue;
+        };
+        let (width, height) = match &composition_object.cropped {
+            Some(crop) => {
+                if crop.horizontal_position as u32 + crop.width as u32 > object.width as u32
+                    || crop.vertical_position as u32 + crop.height as u32 > object.height as u32
+                {
+                    issue(
+                        Severity::Warning,
+                        format!("object {} crop rectangle exceeds the object", object.id),
+                    );
+                }
+                (crop.width, crop.height)
+            }
+            None => (object.width, object.height),
+        };
+        if composition_object.horizontal_position < window.horizontal_position
+            || composition_object.vertical_position < window.vertical_position
+            || composition_object.horizontal_position as u32 + width as u32
+                > window.horizontal_position as u32 + window.width as u32
+            || composition_object.vertical_position as u32 + height as u32
+                > window.vertical_position as u32 + window.height as u32
+        {
+            issue(
+                Severity::Error,
+                format!(
+                    "object {} extends outside window {}",
+                    composition_object.id, window.id
+                ),
+            );
+        }
+    }
+}
+
+/// Checks that the object's run-length data decodes to exactly `height`
+/// scanlines of `width` pixels each.
+fn check_object_data(object: &ObjectDefinition) -> Option<String> {
+    let lines = object.data.line_ranges();
+    for (line, range) in lines.iter().enumerate() {
+        let line_width: u64 = object.data.0[range.clone()]
+            .iter()
+            .map(|run| run.count as u64)
+            .sum();
+        if line_width != object.width as u64 {
+            return Some(format!(
+                "object {} line {} decodes to {} pixels, expected {}",
+                object.id, line, line_width, object.width
+            ));
+        }
+    }
+    if lines.len() != object.height as usize {
+        return Some(format!(
+            "object {} decodes to {} lines, expected {}",
+            object.id,
+            lines.len(),
+            object.height
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{PALETTE, clear, cue, end, ods, pcs, pds, wds};
+    use crate::parse_pgs;
+    use crate::render::DisplaySetIterator;
+
+    #[test]
+    fn conformance_report_lists_each_violation() {
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x00, 0, &[(0, 0, 8, 8)]),
+            wds(0, &[(0, 8, 8, 2, 2), (0, 8, 8, 2, 2)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 2, &[1; 4]),
+            end(0),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        let issues = pgs.conformance_report();
+        let messages: Vec<&str> = issues.iter().map(|issue| issue.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "window 0 is defined more than once",
+                "stream does not begin with an epoch start",
+            ]
+        );
+        assert!(issues.iter().all(|issue| issue.severity == Severity::Error));
+    }
+
+    #[test]
+    fn composition_of_an_undefined_object_fails_validation() {
+        let mut data = [
+            cue(0, (64, 32), (0, 0), 2, &[1; 4]),
+            pcs(9_000, (64, 32), 1, 0x00, 0, &[(0, 0, 0, 0), (3, 0, 4, 0)]),
+            end(9_000),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(&pgs).collect();
+
+        assert!(display_sets[0].validate_objects().is_ok());
+        assert!(matches!(
+            display_sets[1].validate_objects(),
+            Err(PgsError::ObjectNotFound { object_id: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn off_by_one_width_is_repaired() {
+        let mut pgs =
+            parse_pgs(&mut cue(0, (64, 32), (0, 0), 4, &[1, 1, 2, 2, 3, 3, 3, 3])).unwrap();
+        for segment in &mut pgs.segments {
+            if let SegmentContents::ObjectDefinition(object) = &mut segment.contents {
+                object.width = 3;
+                object.height = 3;
+                object.repair_dimensions();
+                assert_eq!((object.width, object.height), (4, 2));
+            }
+        }
+        assert!(pgs.can_render().is_ok());
+    }
+
+    #[test]
+    fn cue_left_on_screen_is_unclosed() {
+        let mut data = [
+            cue(0, (64, 32), (0, 0), 2, &[1; 4]),
+            clear(90_000, (64, 32)),
+            cue(180_000, (64, 32), (4, 4), 2, &[3; 4]),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+
+        assert_eq!(pgs.unclosed_cues(), [2]);
+    }
+
+    #[test]
+    fn wrong_length_field_is_reported() {
+        let mut data = cue(0, (64, 32), (0, 0), 2, &[1; 4]);
+        assert_eq!(validate_segment_lengths(&data), []);
+
+        // The window definition starts at byte 32 and declares 10 bytes.
+        data[43..45].copy_from_slice(&12u16.to_be_bytes());
+        assert_eq!(
+            validate_segment_lengths(&data),
+            [LengthMismatch {
+                offset: 32,
+                segment_type: 0x17,
+                declared: 12,
+                expected: 10,
+            }]
+        );
+    }
+
+    #[test]
+    fn non_canonical_palette_update_byte_is_diagnosed() {
+        let mut data = [
+            pcs(0, (64, 32), 0, 0x80, 0x01, &[(0, 0, 0, 0)]),
+            wds(0, &[(0, 0, 0, 2, 2)]),
+            pds(0, 0, &PALETTE),
+            ods(0, 0, 2, &[1; 4]),
+            end(0),
+        ]
+        .concat();
+        data.extend([0; 2]);
+        let expected = parse_pgs(&mut data.clone()).unwrap();
+
+        let (pgs, diagnostics) = parse_pgs_diagnostic(&mut data).unwrap();
+        assert_eq!(pgs, expected);
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic {
+                    segment: 0,
+                    offset: 21,
+                    kind: DiagnosticKind::NonCanonicalValue {
+                        field: "palette_update_flag",
+                        value: 0x01,
+                    },
+                },
+                Diagnostic {
+                    segment: 4,
+                    offset: data.len() - 2,
+                    kind: DiagnosticKind::Padding { length: 2 },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_object_makes_a_stream_unrenderable() {
+        let mut renderable =
+            [cue(0, (64, 32), (0, 0), 2, &[1; 4]), clear(9_000, (64, 32))].concat();
+        assert!(parse_pgs(&mut renderable).unwrap().can_render().is_ok());
+
+        let mut data = [
+            cue(0, (64, 32), (0, 0), 2, &[1; 4]),
+            pcs(9_000, (64, 32), 1, 0x00, 0, &[(5, 0, 0, 0)]),
+            end(9_000),
+            clear(18_000, (64, 32)),
+        ]
+        .concat();
+        let errors = parse_pgs(&mut data).unwrap().can_render().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0],
+            PgsError::ObjectNotFound { object_id: 5, .. }
+        ));
+    }
+
+    #[test]
+    fn epoch_start_relying_on_an_earlier_palette_is_not_self_contained() {
+        let mut data = [
+            cue(0, (64, 32), (4, 4), 2, &[1; 4]),
+            clear(90_000, (64, 32)),
+            pcs(180_000, (64, 32), 2, 0x80, 0, &[(1, 0, 8, 8)]),
+            wds(180_000, &[(0, 8, 8, 2, 2)]),
+            ods(180_000, 1, 2, &[3; 4]),
+            end(180_000),
+        ]
+        .concat();
+        let pgs = parse_pgs(&mut data).unwrap();
+        assert_eq!(pgs.validate_epoch_self_containment(), [2]);
+    }
+}